@@ -0,0 +1,267 @@
+use crate::types::*;
+use std::rc::Rc;
+
+// * Syntactic analysis, based on SICP 4.1.7
+
+/// An analyzed Exp.
+/// The special forms are recognized once here, so that executing a Node
+/// never needs to look at the head Symbol or to clone the source Exp.
+/// Variables are resolved to lexical addresses or global cells at the same time.
+pub enum Node {
+    /// A quoted or self-evaluating Exp.
+    Const(Exp),
+    /// A local variable reference, as a (depth, index) pair.
+    /// The Symbol is only kept for error messages.
+    Local(usize, usize, Symbol),
+    /// A global variable reference.
+    Global(Rc<GlobalCell>),
+    /// `(define symbol definition)` in a Closure body.
+    /// The symbol always lives in the current Frame.
+    DefineLocal(usize, Box<Node>),
+    /// `(define symbol definition)` at the top level.
+    DefineGlobal(Rc<GlobalCell>, Box<Node>),
+    /// `(set! symbol definition)` on a local variable.
+    SetLocal(usize, usize, Box<Node>),
+    /// `(set! symbol definition)` on a global variable.
+    SetGlobal(Rc<GlobalCell>, Box<Node>),
+    /// `(if condition then else)`
+    If(Box<Node>, Box<Node>, Box<Node>),
+    /// `(cond (condition then)* (else then)?)`
+    Cond(Vec<(Node, Node)>, Option<Box<Node>>),
+    /// `(lambda (params*) body+)`
+    Lambda(Rc<Lambda>),
+    /// `(begin body*)`
+    Begin(Vec<Node>),
+    /// `(func args*)`
+    Apply(Box<Node>, Vec<Node>),
+}
+
+/// The analyzed form of a lambda expression, shared by all the closures created from it.
+pub struct Lambda {
+    pub params: Vec<Symbol>,
+    /// The number of variables in the Frame: the params followed by the internal definitions.
+    pub frame_size: usize,
+    pub body: Vec<Node>,
+}
+
+/// The compile-time counterpart of a Frame.
+struct Scope<'a> {
+    vars: Vec<Symbol>,
+    outer: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Find the lexical address of a symbol.
+    fn lookup(&self, symbol: Symbol) -> Option<(usize, usize)> {
+        match self.vars.iter().position(|&var| var == symbol) {
+            Some(index) => Some((0, index)),
+            None => self
+                .outer?
+                .lookup(symbol)
+                .map(|(depth, index)| (depth + 1, index)),
+        }
+    }
+}
+
+/// Analyze a (desugared) Exp at the top level of the given global Env.
+pub fn analyze(exp: &Exp, env: &RcRefCell<Env>) -> Result<Node, ScmErr> {
+    Analyzer { env }.analyze(exp, None)
+}
+
+struct Analyzer<'a> {
+    env: &'a RcRefCell<Env>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn analyze(&self, exp: &Exp, scope: Option<&Scope>) -> Result<Node, ScmErr> {
+        match exp {
+            Exp::Bool(_) | Exp::Number(_) | Exp::String(_) | Exp::Char(_) | Exp::Vector(_) => {
+                Ok(Node::Const(exp.clone()))
+            }
+
+            &Exp::Symbol(s) => Ok(match scope.and_then(|sc| sc.lookup(s)) {
+                Some((depth, index)) => Node::Local(depth, index, s),
+                None => Node::Global(self.env.borrow_mut().cell(s)),
+            }),
+
+            Exp::List(list) => {
+                let (head, tail) = match list.split_first() {
+                    Some(res) => res,
+                    None => return Err(ScmErr::from("eval: expect a non-empty list")),
+                };
+
+                let head = match head {
+                    &Exp::Symbol(res) => res,
+                    // head is an inline lambda expression
+                    Exp::List(_) => return self.analyze_apply(head, tail, scope),
+                    _ => return Err(ScmErr::from("eval: head of the list is not a function")),
+                };
+
+                match head {
+                    // Quoted Lists are read with `cons`, like the data read by `read`.
+                    Symbol::QUOTE => tail
+                        .first()
+                        .map(|x| Node::Const(x.clone()))
+                        .ok_or_else(|| ScmErr::from("quote: nothing to quote")),
+
+                    Symbol::LAMBDA => self
+                        .analyze_lambda(tail, scope)
+                        .map(|lambda| Node::Lambda(Rc::new(lambda))),
+
+                    Symbol::DEFINE => match tail {
+                        [symbol, definition] => {
+                            let symbol = match symbol {
+                                &Exp::Symbol(res) => res,
+                                _ => return Err(ScmErr::from("define: expected Symbol")),
+                            };
+                            let definition = Box::new(self.analyze(definition, scope)?);
+                            Ok(match scope.and_then(|sc| sc.lookup(symbol)) {
+                                // The internal definitions have been scanned out into the current Frame.
+                                Some((0, index)) => Node::DefineLocal(index, definition),
+                                _ => Node::DefineGlobal(
+                                    self.env.borrow_mut().definition_cell(symbol),
+                                    definition,
+                                ),
+                            })
+                        }
+                        _ => Err(ScmErr::from("define: nothing to define")),
+                    },
+
+                    Symbol::SET => match tail {
+                        [symbol, definition, ..] => {
+                            let symbol = match symbol {
+                                &Exp::Symbol(res) => res,
+                                _ => return Err(ScmErr::from("set!: expected Symbol")),
+                            };
+                            let definition = Box::new(self.analyze(definition, scope)?);
+                            Ok(match scope.and_then(|sc| sc.lookup(symbol)) {
+                                Some((depth, index)) => Node::SetLocal(depth, index, definition),
+                                None => {
+                                    Node::SetGlobal(self.env.borrow_mut().cell(symbol), definition)
+                                }
+                            })
+                        }
+                        _ => Err(ScmErr::from("set!: nothing to set!")),
+                    },
+
+                    Symbol::IF => {
+                        let condition = tail
+                            .first()
+                            .ok_or_else(|| ScmErr::from("if: missing condition"))?;
+                        let then_ = tail
+                            .get(1)
+                            .ok_or_else(|| ScmErr::from("if: missing then clause"))?;
+                        let else_ = tail
+                            .get(2)
+                            .ok_or_else(|| ScmErr::from("if: missing else clause"))?;
+                        Ok(Node::If(
+                            Box::new(self.analyze(condition, scope)?),
+                            Box::new(self.analyze(then_, scope)?),
+                            Box::new(self.analyze(else_, scope)?),
+                        ))
+                    }
+
+                    Symbol::COND => {
+                        let mut clauses = Vec::new();
+                        for item in tail.iter() {
+                            let pair = match item {
+                                Exp::List(res) => res,
+                                _ => return Err(ScmErr::from("cond: expected pairs")),
+                            };
+                            let then_ = pair
+                                .get(1)
+                                .ok_or_else(|| ScmErr::from("cond: missing then clause"))?;
+                            match pair.first() {
+                                Some(&Exp::Symbol(Symbol::ELSE)) => {
+                                    // Any clause after `else` is unreachable.
+                                    let else_ = self.analyze(then_, scope)?;
+                                    return Ok(Node::Cond(clauses, Some(Box::new(else_))));
+                                }
+                                Some(condition) => clauses.push((
+                                    self.analyze(condition, scope)?,
+                                    self.analyze(then_, scope)?,
+                                )),
+                                None => return Err(ScmErr::from("cond: missing condition")),
+                            };
+                        }
+                        Ok(Node::Cond(clauses, None))
+                    }
+
+                    Symbol::BEGIN => Ok(Node::Begin(self.analyze_body(tail, scope)?)),
+
+                    // head is a closure
+                    _ => self.analyze_apply(&list[0], tail, scope),
+                }
+            }
+            _ => Err(ScmErr::from("eval: unexpected Exp")),
+        }
+    }
+
+    fn analyze_body(&self, body: &[Exp], scope: Option<&Scope>) -> Result<Vec<Node>, ScmErr> {
+        body.iter().map(|exp| self.analyze(exp, scope)).collect()
+    }
+
+    fn analyze_apply(
+        &self,
+        func: &Exp,
+        args: &[Exp],
+        scope: Option<&Scope>,
+    ) -> Result<Node, ScmErr> {
+        Ok(Node::Apply(
+            Box::new(self.analyze(func, scope)?),
+            self.analyze_body(args, scope)?,
+        ))
+    }
+
+    fn analyze_lambda(&self, tail: &[Exp], scope: Option<&Scope>) -> Result<Lambda, ScmErr> {
+        let params = match tail.first() {
+            Some(Exp::List(vars)) => vars
+                .iter()
+                .map(|var| match var {
+                    &Exp::Symbol(i) => Ok(i),
+                    _ => Err(ScmErr::from(
+                        "closure unpacking error: expected a list of Symbol's",
+                    )),
+                })
+                .collect::<Result<Vec<Symbol>, ScmErr>>()?,
+            _ => {
+                return Err(ScmErr::from(
+                    "closure unpacking error: expected a non-empty list",
+                ))
+            }
+        };
+        let definition = &tail[1..];
+        if definition.is_empty() {
+            return Err(ScmErr::from("closure unpacking error: missing definition"));
+        }
+
+        // Scan out the internal definitions, as in SICP 4.1.6.
+        let mut vars = params.clone();
+        definition
+            .iter()
+            .for_each(|exp| scan_defines(exp, &mut vars));
+        let scope = Scope { vars, outer: scope };
+
+        Ok(Lambda {
+            params,
+            frame_size: scope.vars.len(),
+            body: self.analyze_body(definition, Some(&scope))?,
+        })
+    }
+}
+
+/// Collect the symbols defined in a Closure body, without entering nested lambdas and quotes.
+pub fn scan_defines(exp: &Exp, vars: &mut Vec<Symbol>) {
+    if let Exp::List(list) = exp {
+        match list.as_slice() {
+            [Exp::Symbol(Symbol::QUOTE), ..] | [Exp::Symbol(Symbol::LAMBDA), ..] => (),
+            [Exp::Symbol(Symbol::DEFINE), Exp::Symbol(symbol), definition] => {
+                if !vars.contains(symbol) {
+                    vars.push(*symbol);
+                }
+                scan_defines(definition, vars);
+            }
+            _ => list.iter().for_each(|exp| scan_defines(exp, vars)),
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use crate::analyzer::scan_defines;
+use crate::types::*;
+use std::rc::Rc;
+
+mod bytecode;
+pub mod image;
+
+pub use bytecode::{Capture, Code, Loc, Op};
+
+// * Bytecode compiler
+
+/// Compile a (desugared) Exp at the top level of the given global Env.
+/// The resulting code object takes no arguments, and can be run by `vm::run`.
+pub fn compile(exp: &Exp, env: &RcRefCell<Env>) -> Result<Rc<Code>, ScmErr> {
+    let mut compiler = Compiler {
+        env,
+        funcs: vec![FuncState::new(Vec::new(), Vec::new())],
+    };
+    compiler.compile(exp, false)?;
+    compiler.emit(Op::Return);
+    Ok(Rc::new(compiler.funcs.pop().unwrap().code))
+}
+
+/// How a variable is accessed from the function being compiled.
+enum Access {
+    Local(usize),
+    Cell(usize),
+    Upval(usize),
+    Global(usize),
+}
+
+/// The function being compiled.
+struct FuncState {
+    vars: Vec<(Symbol, Loc)>,
+    code: Code,
+}
+
+impl FuncState {
+    fn new(vars: Vec<(Symbol, Loc)>, params: Vec<Loc>) -> Self {
+        let mut locals = Vec::new();
+        let mut cells = Vec::new();
+        for &(var, loc) in vars.iter() {
+            match loc {
+                Loc::Local(_) => locals.push(var),
+                Loc::Cell(_) => cells.push(var),
+            }
+        }
+        FuncState {
+            vars,
+            code: Code {
+                params,
+                locals,
+                cells,
+                upvals: Vec::new(),
+                ops: Vec::new(),
+                consts: Vec::new(),
+                protos: Vec::new(),
+                globals: Vec::new(),
+            },
+        }
+    }
+}
+
+struct Compiler<'a> {
+    env: &'a RcRefCell<Env>,
+    /// The stack of the functions being compiled, the innermost being the last.
+    /// The first one is the top-level form.
+    funcs: Vec<FuncState>,
+}
+
+impl<'a> Compiler<'a> {
+    fn current(&mut self) -> &mut FuncState {
+        self.funcs.last_mut().unwrap()
+    }
+
+    /// Emit an instruction, returning its position.
+    fn emit(&mut self, op: Op) -> usize {
+        let ops = &mut self.current().code.ops;
+        ops.push(op);
+        ops.len() - 1
+    }
+
+    /// Set the target of the jump instruction at the given position to the next instruction.
+    fn patch(&mut self, pos: usize) {
+        let ops = &mut self.current().code.ops;
+        let target = ops.len();
+        match &mut ops[pos] {
+            Op::Jump(t) | Op::JumpIfFalse(t, _) => *t = target,
+            op => unreachable!("compile: cannot patch {:?}", op),
+        }
+    }
+
+    fn emit_const(&mut self, exp: Exp) {
+        let consts = &mut self.current().code.consts;
+        consts.push(exp);
+        let k = consts.len() - 1;
+        self.emit(Op::Const(k));
+    }
+
+    fn global(&mut self, symbol: Symbol) -> usize {
+        let cell = self.env.borrow_mut().cell(symbol);
+        self.global_cell(cell)
+    }
+
+    /// Get the index of a global cell in the current function, adding it if necessary.
+    /// Cells are compared by identity, since an imported variable may be renamed.
+    fn global_cell(&mut self, cell: Rc<GlobalCell>) -> usize {
+        let globals = &mut self.current().code.globals;
+        match globals.iter().position(|g| Rc::ptr_eq(g, &cell)) {
+            Some(g) => g,
+            None => {
+                globals.push(cell);
+                globals.len() - 1
+            }
+        }
+    }
+
+    /// Resolve a non-global variable as seen from the function at the given level.
+    fn resolve_in(&mut self, level: usize, symbol: Symbol) -> Option<Access> {
+        if let Some(&(_, loc)) = self.funcs[level]
+            .vars
+            .iter()
+            .find(|(var, _)| *var == symbol)
+        {
+            return Some(match loc {
+                Loc::Local(i) => Access::Local(i),
+                Loc::Cell(i) => Access::Cell(i),
+            });
+        }
+        if level == 0 {
+            return None;
+        }
+        let capture = match self.resolve_in(level - 1, symbol)? {
+            Access::Cell(i) => Capture::Cell(i),
+            Access::Upval(i) => Capture::Upval(i),
+            Access::Local(_) => unreachable!("compile: {} is captured but not in a cell", symbol),
+            Access::Global(_) => return None,
+        };
+        let upvals = &mut self.funcs[level].code.upvals;
+        let i = match upvals.iter().position(|&(var, _)| var == symbol) {
+            Some(i) => i,
+            None => {
+                upvals.push((symbol, capture));
+                upvals.len() - 1
+            }
+        };
+        Some(Access::Upval(i))
+    }
+
+    fn resolve(&mut self, symbol: Symbol) -> Access {
+        let level = self.funcs.len() - 1;
+        match self.resolve_in(level, symbol) {
+            Some(access) => access,
+            None => Access::Global(self.global(symbol)),
+        }
+    }
+
+    /// Emit the instruction popping a value into a variable.
+    fn emit_set(&mut self, access: Access) {
+        self.emit(match access {
+            Access::Local(i) => Op::LocalSet(i),
+            Access::Cell(i) => Op::CellSet(i),
+            Access::Upval(i) => Op::UpvalSet(i),
+            Access::Global(g) => Op::GlobalSet(g),
+        });
+        self.emit(Op::Empty);
+    }
+
+    /// Compile an Exp. If it's in a tail position, calls are compiled as tail calls.
+    fn compile(&mut self, exp: &Exp, tail: bool) -> Result<(), ScmErr> {
+        match exp {
+            Exp::Bool(_) | Exp::Number(_) | Exp::String(_) | Exp::Char(_) | Exp::Vector(_) => {
+                self.emit_const(exp.clone())
+            }
+
+            &Exp::Symbol(s) => {
+                let op = match self.resolve(s) {
+                    Access::Local(i) => Op::LocalGet(i),
+                    Access::Cell(i) => Op::CellGet(i),
+                    Access::Upval(i) => Op::UpvalGet(i),
+                    Access::Global(g) => Op::GlobalGet(g),
+                };
+                self.emit(op);
+            }
+
+            Exp::List(list) => {
+                let (head, tail_) = match list.split_first() {
+                    Some(res) => res,
+                    None => return Err(ScmErr::from("eval: expect a non-empty list")),
+                };
+
+                let head = match head {
+                    &Exp::Symbol(res) => res,
+                    // head is an inline lambda expression
+                    Exp::List(_) => return self.compile_apply(head, tail_, tail),
+                    _ => return Err(ScmErr::from("eval: head of the list is not a function")),
+                };
+
+                match head {
+                    Symbol::QUOTE => {
+                        let quoted = tail_
+                            .first()
+                            .ok_or_else(|| ScmErr::from("quote: nothing to quote"))?;
+                        self.emit_const(quoted.clone());
+                    }
+
+                    Symbol::LAMBDA => {
+                        let proto = self.compile_lambda(tail_)?;
+                        let protos = &mut self.current().code.protos;
+                        protos.push(Rc::new(proto));
+                        let idx = protos.len() - 1;
+                        self.emit(Op::Closure(idx));
+                    }
+
+                    Symbol::DEFINE => match tail_ {
+                        [symbol, definition] => {
+                            let symbol = match symbol {
+                                &Exp::Symbol(res) => res,
+                                _ => return Err(ScmErr::from("define: expected Symbol")),
+                            };
+                            self.compile(definition, false)?;
+                            // The internal definitions have been scanned out into the current function.
+                            let local = self.current().vars.iter().find(|(var, _)| *var == symbol);
+                            let access = match local.map(|&(_, loc)| loc) {
+                                Some(Loc::Local(i)) => Access::Local(i),
+                                Some(Loc::Cell(i)) => Access::Cell(i),
+                                None => {
+                                    let cell = self.env.borrow_mut().definition_cell(symbol);
+                                    Access::Global(self.global_cell(cell))
+                                }
+                            };
+                            self.emit_set(access);
+                        }
+                        _ => return Err(ScmErr::from("define: nothing to define")),
+                    },
+
+                    Symbol::SET => match tail_ {
+                        [symbol, definition, ..] => {
+                            let symbol = match symbol {
+                                &Exp::Symbol(res) => res,
+                                _ => return Err(ScmErr::from("set!: expected Symbol")),
+                            };
+                            self.compile(definition, false)?;
+                            let access = self.resolve(symbol);
+                            self.emit_set(access);
+                        }
+                        _ => return Err(ScmErr::from("set!: nothing to set!")),
+                    },
+
+                    Symbol::IF => {
+                        let condition = tail_
+                            .first()
+                            .ok_or_else(|| ScmErr::from("if: missing condition"))?;
+                        let then_ = tail_
+                            .get(1)
+                            .ok_or_else(|| ScmErr::from("if: missing then clause"))?;
+                        let else_ = tail_
+                            .get(2)
+                            .ok_or_else(|| ScmErr::from("if: missing else clause"))?;
+                        self.compile(condition, false)?;
+                        let to_else = self.emit(Op::JumpIfFalse(0, Symbol::IF));
+                        self.compile(then_, tail)?;
+                        let to_end = self.emit(Op::Jump(0));
+                        self.patch(to_else);
+                        self.compile(else_, tail)?;
+                        self.patch(to_end);
+                    }
+
+                    Symbol::COND => {
+                        let mut to_ends = Vec::new();
+                        let mut has_else = false;
+                        for item in tail_.iter() {
+                            let pair = match item {
+                                Exp::List(res) => res,
+                                _ => return Err(ScmErr::from("cond: expected pairs")),
+                            };
+                            let then_ = pair
+                                .get(1)
+                                .ok_or_else(|| ScmErr::from("cond: missing then clause"))?;
+                            match pair.first() {
+                                Some(&Exp::Symbol(Symbol::ELSE)) => {
+                                    // Any clause after `else` is unreachable.
+                                    self.compile(then_, tail)?;
+                                    has_else = true;
+                                    break;
+                                }
+                                Some(condition) => {
+                                    self.compile(condition, false)?;
+                                    let to_next = self.emit(Op::JumpIfFalse(0, Symbol::COND));
+                                    self.compile(then_, tail)?;
+                                    to_ends.push(self.emit(Op::Jump(0)));
+                                    self.patch(to_next);
+                                }
+                                None => return Err(ScmErr::from("cond: missing condition")),
+                            };
+                        }
+                        if !has_else {
+                            self.emit(Op::NoMatch);
+                        }
+                        to_ends.into_iter().for_each(|pos| self.patch(pos));
+                    }
+
+                    Symbol::BEGIN => self.compile_body(tail_, tail)?,
+
+                    // head is a closure
+                    _ => return self.compile_apply(&list[0], tail_, tail),
+                }
+            }
+            _ => return Err(ScmErr::from("eval: unexpected Exp")),
+        };
+        Ok(())
+    }
+
+    /// Compile a sequence of Exp's, keeping only the value of the last one.
+    fn compile_body(&mut self, body: &[Exp], tail: bool) -> Result<(), ScmErr> {
+        match body.split_last() {
+            Some((last, init)) => {
+                for exp in init.iter() {
+                    self.compile(exp, false)?;
+                    self.emit(Op::Pop);
+                }
+                self.compile(last, tail)
+            }
+            None => {
+                self.emit(Op::Empty);
+                Ok(())
+            }
+        }
+    }
+
+    fn compile_apply(&mut self, func: &Exp, args: &[Exp], tail: bool) -> Result<(), ScmErr> {
+        self.compile(func, false)?;
+        for arg in args.iter() {
+            self.compile(arg, false)?;
+        }
+        self.emit(if tail {
+            Op::TailCall(args.len())
+        } else {
+            Op::Call(args.len())
+        });
+        Ok(())
+    }
+
+    fn compile_lambda(&mut self, tail: &[Exp]) -> Result<Code, ScmErr> {
+        let params = match tail.first() {
+            Some(Exp::List(vars)) => vars
+                .iter()
+                .map(|var| match var {
+                    &Exp::Symbol(i) => Ok(i),
+                    _ => Err(ScmErr::from(
+                        "closure unpacking error: expected a list of Symbol's",
+                    )),
+                })
+                .collect::<Result<Vec<Symbol>, ScmErr>>()?,
+            _ => {
+                return Err(ScmErr::from(
+                    "closure unpacking error: expected a non-empty list",
+                ))
+            }
+        };
+        let definition = &tail[1..];
+        if definition.is_empty() {
+            return Err(ScmErr::from("closure unpacking error: missing definition"));
+        }
+
+        let mut names = params.clone();
+        definition
+            .iter()
+            .for_each(|exp| scan_defines(exp, &mut names));
+        let captured = captured_vars(definition, &names);
+
+        // Only the variables captured by some nested lambda need to live in cells.
+        let (mut n_locals, mut n_cells) = (0, 0);
+        let vars: Vec<(Symbol, Loc)> = names
+            .iter()
+            .map(|&var| {
+                let loc = if captured.contains(&var) {
+                    n_cells += 1;
+                    Loc::Cell(n_cells - 1)
+                } else {
+                    n_locals += 1;
+                    Loc::Local(n_locals - 1)
+                };
+                (var, loc)
+            })
+            .collect();
+        let param_locs = vars[..params.len()].iter().map(|&(_, loc)| loc).collect();
+
+        self.funcs.push(FuncState::new(vars, param_locs));
+        let res = self.compile_body(definition, true);
+        self.emit(Op::Return);
+        let func = self.funcs.pop().unwrap();
+        res.map(|_| func.code)
+    }
+}
+
+/// Find which of the given variables of a lambda body are referred to by its nested lambdas.
+fn captured_vars(body: &[Exp], vars: &[Symbol]) -> Vec<Symbol> {
+    fn walk(exp: &Exp, vars: &[Symbol], shadowed: &[Symbol], nested: bool, res: &mut Vec<Symbol>) {
+        match exp {
+            Exp::Symbol(s)
+                if nested && vars.contains(s) && !shadowed.contains(s) && !res.contains(s) =>
+            {
+                res.push(*s)
+            }
+            Exp::List(list) => match list.as_slice() {
+                [Exp::Symbol(Symbol::QUOTE), ..] => (),
+                [Exp::Symbol(Symbol::LAMBDA), Exp::List(params), body @ ..] => {
+                    let mut shadowed = shadowed.to_vec();
+                    params.iter().for_each(|param| {
+                        if let &Exp::Symbol(param) = param {
+                            shadowed.push(param);
+                        }
+                    });
+                    body.iter().for_each(|exp| scan_defines(exp, &mut shadowed));
+                    body.iter()
+                        .for_each(|exp| walk(exp, vars, &shadowed, true, res));
+                }
+                _ => list
+                    .iter()
+                    .for_each(|exp| walk(exp, vars, shadowed, nested, res)),
+            },
+            _ => (),
+        }
+    }
+
+    let mut res = Vec::new();
+    body.iter()
+        .for_each(|exp| walk(exp, vars, &[], false, &mut res));
+    res
+}
@@ -0,0 +1,108 @@
+use crate::types::*;
+use std::fmt;
+use std::rc::Rc;
+
+/// A bytecode instruction.
+/// Every expression leaves exactly one value on the stack.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    /// Push a constant from the constant pool.
+    Const(usize),
+    /// Push a local variable.
+    LocalGet(usize),
+    /// Pop a value into a local variable.
+    LocalSet(usize),
+    /// Push a local variable which is captured by some Closure, and thus lives in a cell.
+    CellGet(usize),
+    /// Pop a value into a captured local variable.
+    CellSet(usize),
+    /// Push a variable captured by the current Closure.
+    UpvalGet(usize),
+    /// Pop a value into a variable captured by the current Closure.
+    UpvalSet(usize),
+    /// Push a global variable.
+    GlobalGet(usize),
+    /// Pop a value into a global variable.
+    GlobalSet(usize),
+    /// Push `Exp::Empty`, the value of `define` and `set!`.
+    Empty,
+    /// Discard the top of the stack.
+    Pop,
+    /// Create a Closure from a nested code object, capturing the variables it needs.
+    Closure(usize),
+    /// Jump to the given instruction.
+    Jump(usize),
+    /// Pop a Bool and jump to the given instruction if it's false.
+    /// The Symbol is the special form which the branch comes from, for error messages.
+    JumpIfFalse(usize, Symbol),
+    /// Fail because no `cond` clause has been chosen.
+    NoMatch,
+    /// Call the function below the given number of arguments.
+    Call(usize),
+    /// Call the function below the given number of arguments, replacing the current call frame.
+    TailCall(usize),
+    /// Return the top of the stack to the caller.
+    Return,
+}
+
+/// Where a local variable is stored.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Loc {
+    /// A slot in the current call frame.
+    Local(usize),
+    /// A cell in the current call frame, shared with the Closures capturing it.
+    Cell(usize),
+}
+
+/// How a Closure captures a variable from the call frame where it is created.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Capture {
+    /// A captured local variable of the enclosing function.
+    Cell(usize),
+    /// A variable which the enclosing function has itself captured.
+    Upval(usize),
+}
+
+/// A compiled function, or a compiled top-level form.
+pub struct Code {
+    /// The locations of the parameters.
+    pub params: Vec<Loc>,
+    /// The names of the local variables, for error messages.
+    pub locals: Vec<Symbol>,
+    /// The names of the captured local variables, for error messages.
+    pub cells: Vec<Symbol>,
+    /// The variables to capture when creating a Closure from this code object,
+    /// along with their names.
+    pub upvals: Vec<(Symbol, Capture)>,
+    pub ops: Vec<Op>,
+    /// The constant pool.
+    pub consts: Vec<Exp>,
+    /// The nested code objects, one for each lambda expression.
+    pub protos: Vec<Rc<Code>>,
+    /// The global variables, resolved when the code is compiled or loaded.
+    pub globals: Vec<Rc<GlobalCell>>,
+}
+
+impl fmt::Debug for Code {
+    /// Disassemble the code object.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "code params={:?} locals={:?} cells={:?} upvals={:?}",
+            self.params, self.locals, self.cells, self.upvals
+        )?;
+        for (i, op) in self.ops.iter().enumerate() {
+            write!(f, "{:>4} {:?}", i, op)?;
+            match *op {
+                Op::Const(k) => write!(f, "\t; {}", self.consts[k])?,
+                Op::GlobalGet(g) | Op::GlobalSet(g) => write!(f, "\t; {}", self.globals[g].symbol)?,
+                _ => (),
+            }
+            writeln!(f)?;
+        }
+        for proto in self.protos.iter() {
+            write!(f, "{:?}", proto)?;
+        }
+        Ok(())
+    }
+}
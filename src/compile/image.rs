@@ -0,0 +1,489 @@
+use super::{Capture, Code, Loc, Op};
+use crate::types::*;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+// * Bytecode images
+//
+// An image is a sequence of compiled top-level forms. Its layout is:
+//
+// "RUSK" version:u32 symbol_count:u32 (len:u32 utf8)* code_count:u32 code*
+//
+// All integers are little-endian. Symbols are stored as indices into the image's own symbol table,
+// which is interned again when the image is loaded.
+
+pub const MAGIC: &[u8; 4] = b"RUSK";
+
+/// The version of the image format. Bump it whenever the layout or the instruction set changes.
+pub const VERSION: u32 = 3;
+
+/// Check if the given bytes start like an image.
+pub fn is_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Serialize compiled top-level forms.
+pub fn write_image(codes: &[Rc<Code>], out: &mut impl Write) -> Result<(), ScmErr> {
+    let mut writer = Writer {
+        buf: Vec::new(),
+        symbols: Vec::new(),
+        symbol_ids: HashMap::new(),
+    };
+    writer.u32(codes.len() as u32);
+    for code in codes.iter() {
+        writer.code(code)?;
+    }
+
+    let mut header = Writer {
+        buf: MAGIC.to_vec(),
+        symbols: Vec::new(),
+        symbol_ids: HashMap::new(),
+    };
+    header.u32(VERSION);
+    header.u32(writer.symbols.len() as u32);
+    for sym in writer.symbols.iter() {
+        header.string(sym.as_str());
+    }
+
+    out.write_all(&header.buf)
+        .and_then(|_| out.write_all(&writer.buf))
+        .map_err(|e| ScmErr::from(&format!("image: {}", e)))
+}
+
+/// Deserialize compiled top-level forms, resolving their global variables in the given Env.
+pub fn read_image(bytes: &[u8], env: &RcRefCell<Env>) -> Result<Vec<Rc<Code>>, ScmErr> {
+    let mut reader = Reader {
+        bytes,
+        symbols: Vec::new(),
+        env,
+    };
+    let mut magic = [0; 4];
+    reader.read(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(ScmErr::from("image: not a rusk bytecode image"));
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(ScmErr::from(&format!(
+            "image: unsupported version {} (expected {})",
+            version, VERSION
+        )));
+    }
+    for _ in 0..reader.u32()? {
+        let name = reader.string()?;
+        reader.symbols.push(Symbol::intern(&name));
+    }
+    (0..reader.u32()?)
+        .map(|_| match reader.code()? {
+            // A top-level form is run without any captured variables.
+            code if !code.upvals.is_empty() => Err(ScmErr::from("image: invalid code object")),
+            code => Ok(code),
+        })
+        .collect()
+}
+
+struct Writer {
+    buf: Vec<u8>,
+    symbols: Vec<Symbol>,
+    symbol_ids: HashMap<Symbol, u32>,
+}
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.u32(n as u32);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.usize(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn symbol(&mut self, sym: Symbol) {
+        let symbols = &mut self.symbols;
+        let id = *self.symbol_ids.entry(sym).or_insert_with(|| {
+            symbols.push(sym);
+            symbols.len() as u32 - 1
+        });
+        self.u32(id);
+    }
+
+    fn symbols(&mut self, syms: &[Symbol]) {
+        self.usize(syms.len());
+        syms.iter().for_each(|&sym| self.symbol(sym));
+    }
+
+    fn exp(&mut self, exp: &Exp) -> Result<(), ScmErr> {
+        match exp {
+            Exp::Empty => self.u8(0),
+            &Exp::Bool(b) => {
+                self.u8(1);
+                self.u8(b as u8);
+            }
+            Exp::Number(n) => {
+                self.u8(2);
+                self.buf.extend_from_slice(&n.to_le_bytes());
+            }
+            &Exp::Symbol(s) => {
+                self.u8(3);
+                self.symbol(s);
+            }
+            Exp::String(s) => {
+                self.u8(5);
+                self.string(s);
+            }
+            &Exp::Char(c) => {
+                self.u8(7);
+                self.u32(c as u32);
+            }
+            Exp::List(list) => {
+                self.u8(4);
+                self.usize(list.len());
+                for item in list.iter() {
+                    self.exp(item)?;
+                }
+            }
+            Exp::Vector(items) => {
+                self.u8(6);
+                self.usize(items.borrow().len());
+                for item in items.borrow().iter() {
+                    self.exp(item)?;
+                }
+            }
+            _ => {
+                return Err(ScmErr::from(&format!(
+                    "image: cannot serialize constant {}",
+                    exp
+                )))
+            }
+        };
+        Ok(())
+    }
+
+    fn op(&mut self, op: Op) {
+        let (tag, arg) = match op {
+            Op::Const(k) => (0, k),
+            Op::LocalGet(i) => (1, i),
+            Op::LocalSet(i) => (2, i),
+            Op::CellGet(i) => (3, i),
+            Op::CellSet(i) => (4, i),
+            Op::UpvalGet(i) => (5, i),
+            Op::UpvalSet(i) => (6, i),
+            Op::GlobalGet(g) => (7, g),
+            Op::GlobalSet(g) => (8, g),
+            Op::Empty => (9, 0),
+            Op::Pop => (10, 0),
+            Op::Closure(idx) => (11, idx),
+            Op::Jump(target) => (12, target),
+            Op::JumpIfFalse(target, form) => {
+                self.u8(13);
+                self.usize(target);
+                self.symbol(form);
+                return;
+            }
+            Op::NoMatch => (14, 0),
+            Op::Call(argc) => (15, argc),
+            Op::TailCall(argc) => (16, argc),
+            Op::Return => (17, 0),
+        };
+        self.u8(tag);
+        self.usize(arg);
+    }
+
+    fn code(&mut self, code: &Code) -> Result<(), ScmErr> {
+        self.usize(code.params.len());
+        for &loc in code.params.iter() {
+            let (tag, i) = match loc {
+                Loc::Local(i) => (0, i),
+                Loc::Cell(i) => (1, i),
+            };
+            self.u8(tag);
+            self.usize(i);
+        }
+        self.symbols(&code.locals);
+        self.symbols(&code.cells);
+        self.usize(code.upvals.len());
+        for &(sym, capture) in code.upvals.iter() {
+            self.symbol(sym);
+            let (tag, i) = match capture {
+                Capture::Cell(i) => (0, i),
+                Capture::Upval(i) => (1, i),
+            };
+            self.u8(tag);
+            self.usize(i);
+        }
+        self.usize(code.ops.len());
+        code.ops.iter().for_each(|&op| self.op(op));
+        self.usize(code.consts.len());
+        for exp in code.consts.iter() {
+            self.exp(exp)?;
+        }
+        self.usize(code.protos.len());
+        for proto in code.protos.iter() {
+            self.code(proto)?;
+        }
+        let globals: Vec<Symbol> = code.globals.iter().map(|cell| cell.symbol).collect();
+        self.symbols(&globals);
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    symbols: Vec<Symbol>,
+    env: &'a RcRefCell<Env>,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), ScmErr> {
+        self.bytes
+            .read_exact(buf)
+            .map_err(|_| ScmErr::from("image: unexpected end of image"))
+    }
+
+    fn u8(&mut self) -> Result<u8, ScmErr> {
+        let mut buf = [0; 1];
+        self.read(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ScmErr> {
+        let mut buf = [0; 4];
+        self.read(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn usize(&mut self) -> Result<usize, ScmErr> {
+        self.u32().map(|n| n as usize)
+    }
+
+    fn string(&mut self) -> Result<String, ScmErr> {
+        let len = self.usize()?;
+        if len > self.bytes.len() {
+            return Err(ScmErr::from("image: unexpected end of image"));
+        }
+        let mut buf = vec![0; len];
+        self.read(&mut buf)?;
+        String::from_utf8(buf).map_err(|_| ScmErr::from("image: invalid UTF-8 string"))
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, ScmErr> {
+        let id = self.usize()?;
+        self.symbols
+            .get(id)
+            .cloned()
+            .ok_or_else(|| ScmErr::from("image: invalid symbol index"))
+    }
+
+    fn symbols(&mut self) -> Result<Vec<Symbol>, ScmErr> {
+        (0..self.usize()?).map(|_| self.symbol()).collect()
+    }
+
+    fn exp(&mut self) -> Result<Exp, ScmErr> {
+        Ok(match self.u8()? {
+            0 => Exp::Empty,
+            1 => Exp::Bool(self.u8()? != 0),
+            2 => {
+                let mut buf = [0; 8];
+                self.read(&mut buf)?;
+                Exp::Number(f64::from_le_bytes(buf))
+            }
+            3 => Exp::Symbol(self.symbol()?),
+            4 => Exp::List(
+                (0..self.usize()?)
+                    .map(|_| self.exp())
+                    .collect::<Result<_, _>>()?,
+            ),
+            5 => Exp::String(self.string()?),
+            6 => Exp::vector(
+                (0..self.usize()?)
+                    .map(|_| self.exp())
+                    .collect::<Result<_, _>>()?,
+            ),
+            7 => {
+                let code = self.u32()?;
+                Exp::Char(
+                    std::char::from_u32(code)
+                        .ok_or_else(|| ScmErr::from(&format!("image: invalid char {:#x}", code)))?,
+                )
+            }
+            tag => {
+                return Err(ScmErr::from(&format!(
+                    "image: invalid constant tag {}",
+                    tag
+                )))
+            }
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, ScmErr> {
+        let tag = self.u8()?;
+        let arg = self.usize()?;
+        Ok(match tag {
+            0 => Op::Const(arg),
+            1 => Op::LocalGet(arg),
+            2 => Op::LocalSet(arg),
+            3 => Op::CellGet(arg),
+            4 => Op::CellSet(arg),
+            5 => Op::UpvalGet(arg),
+            6 => Op::UpvalSet(arg),
+            7 => Op::GlobalGet(arg),
+            8 => Op::GlobalSet(arg),
+            9 => Op::Empty,
+            10 => Op::Pop,
+            11 => Op::Closure(arg),
+            12 => Op::Jump(arg),
+            13 => Op::JumpIfFalse(arg, self.symbol()?),
+            14 => Op::NoMatch,
+            15 => Op::Call(arg),
+            16 => Op::TailCall(arg),
+            17 => Op::Return,
+            _ => return Err(ScmErr::from(&format!("image: invalid opcode {}", tag))),
+        })
+    }
+
+    fn code(&mut self) -> Result<Rc<Code>, ScmErr> {
+        let params = (0..self.usize()?)
+            .map(|_| {
+                let tag = self.u8()?;
+                let i = self.usize()?;
+                match tag {
+                    0 => Ok(Loc::Local(i)),
+                    1 => Ok(Loc::Cell(i)),
+                    _ => Err(ScmErr::from("image: invalid parameter location")),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        let locals = self.symbols()?;
+        let cells = self.symbols()?;
+        let upvals = (0..self.usize()?)
+            .map(|_| {
+                let sym = self.symbol()?;
+                let tag = self.u8()?;
+                let i = self.usize()?;
+                match tag {
+                    0 => Ok((sym, Capture::Cell(i))),
+                    1 => Ok((sym, Capture::Upval(i))),
+                    _ => Err(ScmErr::from("image: invalid capture")),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        let ops = (0..self.usize()?)
+            .map(|_| self.op())
+            .collect::<Result<_, _>>()?;
+        let consts = (0..self.usize()?)
+            .map(|_| self.exp())
+            .collect::<Result<_, _>>()?;
+        let protos = (0..self.usize()?)
+            .map(|_| self.code())
+            .collect::<Result<_, _>>()?;
+        let globals = self
+            .symbols()?
+            .into_iter()
+            .map(|sym| self.env.borrow_mut().cell(sym))
+            .collect();
+        let code = Code {
+            params,
+            locals,
+            cells,
+            upvals,
+            ops,
+            consts,
+            protos,
+            globals,
+        };
+        validate(&code)?;
+        Ok(Rc::new(code))
+    }
+}
+
+/// Check that the operands of a loaded code object are in range, so that a corrupted image
+/// is reported as an error instead of crashing the VM.
+fn validate(code: &Code) -> Result<(), ScmErr> {
+    let in_range = |i: usize, len: usize| i < len;
+    let params_ok = code.params.iter().all(|&loc| match loc {
+        Loc::Local(i) => in_range(i, code.locals.len()),
+        Loc::Cell(i) => in_range(i, code.cells.len()),
+    });
+    let ops_ok = code.ops.iter().all(|&op| match op {
+        Op::Const(k) => in_range(k, code.consts.len()),
+        Op::LocalGet(i) | Op::LocalSet(i) => in_range(i, code.locals.len()),
+        Op::CellGet(i) | Op::CellSet(i) => in_range(i, code.cells.len()),
+        Op::UpvalGet(i) | Op::UpvalSet(i) => in_range(i, code.upvals.len()),
+        Op::GlobalGet(g) | Op::GlobalSet(g) => in_range(g, code.globals.len()),
+        Op::Closure(idx) => in_range(idx, code.protos.len()),
+        Op::Jump(target) | Op::JumpIfFalse(target, _) => in_range(target, code.ops.len()),
+        _ => true,
+    });
+    // The captures of the nested code objects are taken from this one when creating their Closures.
+    let captures_ok = code
+        .protos
+        .iter()
+        .flat_map(|proto| &proto.upvals)
+        .all(|&(_, capture)| match capture {
+            Capture::Cell(i) => in_range(i, code.cells.len()),
+            Capture::Upval(i) => in_range(i, code.upvals.len()),
+        });
+    let last_ok = matches!(code.ops.last(), Some(Op::Return));
+    if params_ok && ops_ok && captures_ok && last_ok && stack_ok(code) {
+        Ok(())
+    } else {
+        Err(ScmErr::from("image: invalid code object"))
+    }
+}
+
+/// Check that no instruction pops more operands than the call frame holds,
+/// and that the stack has the same depth wherever control flow joins, by following every path.
+fn stack_ok(code: &Code) -> bool {
+    let mut depths = vec![None; code.ops.len()];
+    let mut todo = vec![(0, 0)];
+    while let Some((pc, depth)) = todo.pop() {
+        match depths.get(pc) {
+            None => return false,
+            Some(&Some(seen)) if seen == depth => continue,
+            Some(&Some(_)) => return false,
+            Some(None) => depths[pc] = Some(depth),
+        }
+        let op = code.ops[pc];
+        let (pops, pushes) = match op {
+            Op::Const(_)
+            | Op::LocalGet(_)
+            | Op::CellGet(_)
+            | Op::UpvalGet(_)
+            | Op::GlobalGet(_)
+            | Op::Empty
+            | Op::Closure(_) => (0, 1),
+            Op::LocalSet(_)
+            | Op::CellSet(_)
+            | Op::UpvalSet(_)
+            | Op::GlobalSet(_)
+            | Op::Pop
+            | Op::JumpIfFalse(..)
+            | Op::Return => (1, 0),
+            Op::Jump(_) | Op::NoMatch => (0, 0),
+            Op::Call(argc) => (argc + 1, 1),
+            // The function and its arguments must be the only operands of the frame.
+            Op::TailCall(argc) if depth != argc + 1 => return false,
+            Op::TailCall(argc) => (argc + 1, 0),
+        };
+        if depth < pops {
+            return false;
+        }
+        let depth = depth - pops + pushes;
+        match op {
+            Op::Jump(target) => todo.push((target, depth)),
+            Op::JumpIfFalse(target, _) => todo.extend([(target, depth), (pc + 1, depth)]),
+            Op::NoMatch | Op::TailCall(_) | Op::Return => (),
+            _ => todo.push((pc + 1, depth)),
+        }
+    }
+    true
+}
@@ -0,0 +1,262 @@
+use crate::interp::Interp;
+use crate::types::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// * Conversions between Rust values and Exp
+//
+// Lists are built with `cons`, so a `Vec` is converted to nested pairs ending with `null`,
+// and a `HashMap` to an association List of `(key . value)` pairs.
+// `Option::None` is converted to `#f`.
+
+/// Convert a Rust value into an Exp.
+pub trait IntoScheme {
+    fn into_scheme(self) -> Exp;
+}
+
+/// Convert an Exp into a Rust value, failing if it has the wrong type.
+pub trait FromScheme: Sized {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr>;
+}
+
+fn expected(what: &str, exp: &Exp) -> ScmErr {
+    ScmErr::from(&format!("expected {}, got {}", what, exp))
+}
+
+/// Collect the items of a List built with `cons`.
+/// A flat List is taken as its items, unless it has two items and is read as a pair.
+pub fn list_to_vec(list: &Exp) -> Result<Vec<Exp>, ScmErr> {
+    let mut res = Vec::new();
+    let mut rest = list;
+    loop {
+        match rest {
+            Exp::List(pair) if pair.len() == 2 => {
+                res.push(pair[0].clone());
+                rest = &pair[1];
+            }
+            Exp::List(items) => {
+                res.extend(items.iter().cloned());
+                break Ok(res);
+            }
+            _ => break Err(expected("a List", list)),
+        }
+    }
+}
+
+/// Build a List with `cons`.
+pub fn vec_to_list(items: Vec<Exp>) -> Exp {
+    items
+        .into_iter()
+        .rev()
+        .fold(Exp::List(Vec::new()), |list, item| {
+            Exp::List(vec![item, list])
+        })
+}
+
+impl IntoScheme for Exp {
+    fn into_scheme(self) -> Exp {
+        self
+    }
+}
+
+impl FromScheme for Exp {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        Ok(exp.clone())
+    }
+}
+
+impl IntoScheme for () {
+    fn into_scheme(self) -> Exp {
+        Exp::Empty
+    }
+}
+
+impl IntoScheme for f64 {
+    fn into_scheme(self) -> Exp {
+        Exp::Number(self)
+    }
+}
+
+impl FromScheme for f64 {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        match *exp {
+            Exp::Number(n) => Ok(n),
+            _ => Err(expected("a Number", exp)),
+        }
+    }
+}
+
+impl IntoScheme for i64 {
+    fn into_scheme(self) -> Exp {
+        Exp::Number(self as f64)
+    }
+}
+
+impl FromScheme for i64 {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        match *exp {
+            Exp::Number(n) if n.fract() == 0.0 && n.abs() <= i64::MAX as f64 => Ok(n as i64),
+            _ => Err(expected("an integer", exp)),
+        }
+    }
+}
+
+impl IntoScheme for bool {
+    fn into_scheme(self) -> Exp {
+        Exp::Bool(self)
+    }
+}
+
+impl FromScheme for bool {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        match *exp {
+            Exp::Bool(b) => Ok(b),
+            _ => Err(expected("a Bool", exp)),
+        }
+    }
+}
+
+impl IntoScheme for String {
+    fn into_scheme(self) -> Exp {
+        Exp::String(self)
+    }
+}
+
+impl IntoScheme for &str {
+    fn into_scheme(self) -> Exp {
+        Exp::String(self.into())
+    }
+}
+
+impl FromScheme for String {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        match exp {
+            Exp::String(s) => Ok(s.clone()),
+            _ => Err(expected("a String", exp)),
+        }
+    }
+}
+
+impl<T: IntoScheme> IntoScheme for Vec<T> {
+    fn into_scheme(self) -> Exp {
+        vec_to_list(self.into_iter().map(T::into_scheme).collect())
+    }
+}
+
+impl<T: FromScheme> FromScheme for Vec<T> {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        list_to_vec(exp)?.iter().map(T::from_scheme).collect()
+    }
+}
+
+impl<K: IntoScheme, V: IntoScheme> IntoScheme for HashMap<K, V> {
+    fn into_scheme(self) -> Exp {
+        vec_to_list(
+            self.into_iter()
+                .map(|(k, v)| Exp::List(vec![k.into_scheme(), v.into_scheme()]))
+                .collect(),
+        )
+    }
+}
+
+impl<K: FromScheme + Eq + Hash, V: FromScheme> FromScheme for HashMap<K, V> {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        list_to_vec(exp)?
+            .iter()
+            .map(|pair| match pair {
+                Exp::List(pair) if pair.len() == 2 => {
+                    Ok((K::from_scheme(&pair[0])?, V::from_scheme(&pair[1])?))
+                }
+                _ => Err(expected("an association List", exp)),
+            })
+            .collect()
+    }
+}
+
+impl<T: IntoScheme> IntoScheme for Option<T> {
+    fn into_scheme(self) -> Exp {
+        match self {
+            Some(x) => x.into_scheme(),
+            None => Exp::Bool(false),
+        }
+    }
+}
+
+impl<T: FromScheme> FromScheme for Option<T> {
+    fn from_scheme(exp: &Exp) -> Result<Self, ScmErr> {
+        match exp {
+            Exp::Bool(false) => Ok(None),
+            _ => T::from_scheme(exp).map(Some),
+        }
+    }
+}
+
+/// The return value of a wrapped function: either a value, or a `Result` of one.
+pub trait IntoSchemeResult {
+    fn into_scheme_result(self) -> Result<Exp, ScmErr>;
+}
+
+impl<T: IntoScheme> IntoSchemeResult for T {
+    fn into_scheme_result(self) -> Result<Exp, ScmErr> {
+        Ok(self.into_scheme())
+    }
+}
+
+impl<T: IntoScheme> IntoSchemeResult for Result<T, ScmErr> {
+    fn into_scheme_result(self) -> Result<Exp, ScmErr> {
+        self.map(T::into_scheme)
+    }
+}
+
+/// A Rust function which can be wrapped as a Native function.
+/// `Args` is the tuple of its argument types.
+pub trait IntoNative<Args> {
+    fn into_native(self, name: &str) -> Exp;
+}
+
+macro_rules! impl_into_native {
+    ($n:expr; $($arg:ident),*) => {
+        impl<F, R, $($arg),*> IntoNative<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoSchemeResult,
+            $($arg: FromScheme),*
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn into_native(self, name: &str) -> Exp {
+                let fn_name = name.to_string();
+                Exp::native(name, Arity::Exactly($n), move |args: &[Exp], _: &mut Interp| {
+                    let mut args = args.iter();
+                    $(
+                        let $arg = $arg::from_scheme(args.next().unwrap())
+                            .map_err(|e| ScmErr::from(&format!("{}: {}", fn_name, e)))?;
+                    )*
+                    self($($arg),*).into_scheme_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native!(0;);
+impl_into_native!(1; A);
+impl_into_native!(2; A, B);
+impl_into_native!(3; A, B, C);
+impl_into_native!(4; A, B, C, D);
+
+/// Wrap a Rust function as a Native function, converting its arguments and its return value.
+/// Wrong argument counts and types are reported as errors.
+///
+/// ```
+/// # use rusk::{wrap_fn, Interpreter};
+/// let mut rusk = Interpreter::new();
+/// rusk.define_global("repeat", wrap_fn("repeat", |s: String, n: i64| s.repeat(n as usize)));
+/// assert_eq!(rusk.eval_str(r#"(repeat "ab" 3)"#).unwrap().to_string(), r#""ababab""#);
+/// assert_eq!(
+///     rusk.eval_str(r#"(repeat "ab" 1.5)"#).unwrap_err().to_string(),
+///     "repeat: expected an integer, got 1.5"
+/// );
+/// ```
+pub fn wrap_fn<Args>(name: &str, func: impl IntoNative<Args>) -> Exp {
+    func.into_native(name)
+}
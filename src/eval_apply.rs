@@ -1,216 +1,140 @@
-use crate::prelude::make_env_ptr;
+use crate::analyzer::{analyze, Node};
+use crate::gc;
+use crate::interp::Interp;
 use crate::types::*;
+use crate::vm;
 use std::rc::Rc;
 
-pub fn eval(exp: Exp, env: RcRefCell<Env>) -> Result<Exp, ScmErr> {
-    match exp {
-        n @ Exp::Number(_) => Ok(n),
+pub fn eval(exp: Exp, interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let node = analyze(&exp, &interp.env)?;
+    execute(&node, None, interp)
+}
+
+pub fn undefined(symbol: Symbol) -> ScmErr {
+    ScmErr::from(&format!("eval: Symbol {} undefined", symbol))
+}
 
-        ref s @ Exp::Symbol(_) => env
+/// Execute an analyzed Exp in the given Frame, or at the top level if there is none.
+pub fn execute(
+    node: &Node,
+    frame: Option<&RcRefCell<Frame>>,
+    interp: &mut Interp,
+) -> Result<Exp, ScmErr> {
+    interp.step()?;
+    match node {
+        Node::Const(exp) => Ok(exp.clone()),
+
+        &Node::Local(depth, index, symbol) => frame
+            .and_then(|f| f.borrow().lookup(depth, index))
+            .ok_or_else(|| undefined(symbol)),
+
+        Node::Global(cell) => cell
+            .value
             .borrow()
-            .lookup(s)
-            .ok_or(ScmErr::from(&format!("eval: Symbol {} undefined", s))),
+            .clone()
+            .ok_or_else(|| undefined(cell.symbol)),
+
+        Node::Lambda(lambda) => {
+            let closure = ScmClosure {
+                lambda: Rc::clone(lambda),
+                env: frame.cloned(),
+                // Here we want to clone a pointer, not to clone a Frame.
+            };
+            Ok(Exp::Closure(closure))
+        }
 
-        Exp::List(list) => {
-            if list.is_empty() {
-                return Err(ScmErr::from("eval: expect a non-empty list"));
-            }
+        Node::DefineLocal(index, definition) => {
+            let eval_definition = execute(definition, frame, interp)?;
+            let frame = frame.expect("define: local definition at the top level");
+            frame.borrow_mut().set(0, *index, eval_definition);
+            Ok(Exp::Empty)
+        }
 
-            let tail = &list[1..];
+        Node::DefineGlobal(cell, definition) | Node::SetGlobal(cell, definition) => {
+            let eval_definition = execute(definition, frame, interp)?;
+            cell.value.replace(Some(eval_definition));
+            Ok(Exp::Empty)
+        }
 
-            // A tiny closure to send a lambda expression to apply
-            let handle_lambda = |exp: Exp| {
-                let func = eval(exp, Rc::clone(&env))?;
-                let args: Vec<Exp> = tail
-                    .iter()
-                    .map(|i| eval(i.clone(), Rc::clone(&env)).unwrap())
-                    .collect();
-                apply(func, &args[..])
-            };
+        &Node::SetLocal(depth, index, ref definition) => {
+            let eval_definition = execute(definition, frame, interp)?;
+            let frame = frame.expect("set!: local variable at the top level");
+            frame.borrow_mut().set(depth, index, eval_definition);
+            Ok(Exp::Empty)
+        }
 
-            let head = match list.first() {
-                Some(Exp::Symbol(res)) => res,
-                Some(lambda @ Exp::List(_)) => {
-                    // head is an inline lambda expression
-                    return handle_lambda(lambda.clone());
-                }
-                _ => return Err(ScmErr::from("eval: head of the list is not a function")),
-            };
+        Node::If(condition, then_, else_) => match execute(condition, frame, interp) {
+            Ok(Exp::Bool(true)) => execute(then_, frame, interp),
+            Ok(Exp::Bool(false)) => execute(else_, frame, interp),
+            Err(e) => Err(e),
+            _ => Err(ScmErr::from("if: expected Exp::Bool")),
+        },
 
-            match head.as_ref() {
-                // ! This is a WRONG quote.
-                // TODO: implement proper cons structure.
-                "quote" => tail
-                    .first()
-                    .ok_or_else(|| ScmErr::from("quote: nothing to quote"))
-                    .cloned(),
-
-                "lambda" => {
-                    let tail: Vec<Exp> = tail.iter().cloned().collect();
-                    let closure = ScmClosure {
-                        body: Box::new(Exp::List(tail)),
-                        env: Env::from_outer(Some(Rc::clone(&env))),
-                        // Here we want to clone a pointer, not to clone an Env.
-                    };
-                    Ok(Exp::Closure(closure))
+        Node::Cond(clauses, else_) => {
+            for (condition, then_) in clauses.iter() {
+                match execute(condition, frame, interp) {
+                    Ok(Exp::Bool(true)) => return execute(then_, frame, interp),
+                    Ok(Exp::Bool(false)) => continue,
+                    Err(e) => return Err(e),
+                    _ => return Err(ScmErr::from("cond: expected Exp::Bool")),
                 }
+            }
+            match else_ {
+                Some(then_) => execute(then_, frame, interp),
+                None => Err(ScmErr::from("cond: missing else clause")),
+            }
+        }
 
-                "define" => {
-                    match tail {
-                        [symbol, definition] => {
-                            let symbol_str = match symbol {
-                                Exp::Symbol(res) => res.clone(),
-                                _ => return Err(ScmErr::from("define: expected Symbol")),
-                            };
-                            let eval_definition = eval(definition.clone(), Rc::clone(&env))?;
-                            env.borrow_mut().data.insert(symbol_str, eval_definition);
-                        }
-                        _ => return Err(ScmErr::from("define: nothing to define")),
-                    };
-                    Ok(Exp::Empty)
-                }
+        Node::Begin(body) => execute_body(body, frame, interp),
 
-                "set!" => {
-                    let symbol = tail
-                        .get(0)
-                        .ok_or(ScmErr::from("set!: nothing to set!"))?
-                        .clone();
-                    let definition = tail
-                        .get(1)
-                        .ok_or(ScmErr::from("set!: nothing to set!"))?
-                        .clone();
-                    let eval_definition = eval(definition, Rc::clone(&env))?;
-                    let key = match symbol.clone() {
-                        Exp::Symbol(res) => res,
-                        _ => return Err(ScmErr::from("set!: expected Symbol")),
-                    };
-
-                    // Find the innermost Env in which a symbol is defined starting from the current Env.
-                    let target: RcRefCell<Env> = {
-                        let mut current = Rc::clone(&env);
-                        loop {
-                            let outer = match &current.borrow().outer {
-                                Some(x) => Rc::clone(&x),
-                                None => break Rc::clone(&current),
-                            };
-                            match current.borrow().data.get(&key) {
-                                Some(_) => break Rc::clone(&current),
-                                None => (),
-                            };
-                            current = outer;
-                        }
-                    };
-                    target.borrow_mut().data.insert(key, eval_definition);
-                    /*
-                    // * print details
-                    println!(
-                        ">> Symbol \"{:?}\" set to {:?}",
-                        symbol,
-                        env.borrow().lookup(&symbol).unwrap()
-                    );
-                    */
-                    Ok(Exp::Empty)
-                }
+        Node::Apply(func, args) => {
+            let func = execute(func, frame, interp)?;
+            let args = args
+                .iter()
+                .map(|i| execute(i, frame, interp))
+                .collect::<Result<Vec<Exp>, ScmErr>>()?;
+            apply(func, &args[..], interp)
+        }
+    }
+}
 
-                "if" => {
-                    let condition = tail
-                        .get(0)
-                        .ok_or(ScmErr::from("if: missing condition"))?
-                        .clone();
-                    let then_ = tail
-                        .get(1)
-                        .ok_or(ScmErr::from("if: missing then clause"))?
-                        .clone();
-                    let else_ = tail
-                        .get(2)
-                        .ok_or(ScmErr::from("if: missing else clause"))?
-                        .clone();
-                    // return eval(then_ if eval(condition, env) else else_, env)
-                    match eval(condition, Rc::clone(&env)) {
-                        Ok(Exp::Bool(true)) => eval(then_, env),
-                        Ok(Exp::Bool(false)) => eval(else_, env),
-                        _ => Err(ScmErr::from("if: expected Exp::Bool")),
-                    }
-                }
+/// Execute a sequence of Nodes, returning the value of the last one.
+fn execute_body(
+    body: &[Node],
+    frame: Option<&RcRefCell<Frame>>,
+    interp: &mut Interp,
+) -> Result<Exp, ScmErr> {
+    body.iter()
+        .try_fold(Exp::Empty, |_seed, node| execute(node, frame, interp))
+}
 
-                "cond" => {
-                    for item in tail.iter() {
-                        let pair = match item {
-                            Exp::List(res) => res.clone(),
-                            _ => return Err(ScmErr::from("cond: expected pairs")),
-                        };
-                        let then_ = pair
-                            .get(1)
-                            .ok_or(ScmErr::from("cond: missing then clause"))?
-                            .clone();
-                        let condition = match pair.get(0) {
-                            Some(Exp::Symbol(s)) => match s.as_ref() {
-                                "else" => return eval(then_, env),
-                                _ => Exp::Symbol(s.clone()),
-                            },
-                            Some(res) => res.clone(),
-                            None => return Err(ScmErr::from("cond: missing condition")),
-                        };
-                        match eval(condition, Rc::clone(&env)) {
-                            Ok(Exp::Bool(true)) => return eval(then_, env),
-                            Ok(Exp::Bool(false)) => continue,
-                            _ => return Err(ScmErr::from("cond: expected Exp::Bool")),
-                        }
-                    }
-                    Err(ScmErr::from("cond: missing else clause"))
-                }
+pub fn apply(func: Exp, args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    // func can be Exp::Primitive, Exp::Native, Exp::Closure or Exp::Compiled
+    match func {
+        Exp::Primitive(prim) => prim(args).and_then(|res| interp.allocate(res)),
 
-                "begin" => tail.iter().fold(Ok(Exp::Empty), |_seed, item| {
-                    eval(item.clone(), Rc::clone(&env))
-                }),
+        Exp::Native(native) => {
+            native.arity.check(&native.name, args.len())?;
+            (native.func)(args, interp).and_then(|res| interp.allocate(res))
+        }
 
-                _ => {
-                    // head is a closure
-                    handle_lambda(Exp::Symbol(head.clone()))
-                }
+        Exp::Closure(clos) => {
+            let lambda = &clos.lambda;
+            let mut slots = vec![None; lambda.frame_size];
+            for (slot, arg) in slots.iter_mut().zip(args.iter().take(lambda.params.len())) {
+                *slot = Some(arg.clone());
             }
+            let local_env = gc::alloc_frame(Frame::from_outer(slots, clos.env));
+            interp.enter()?;
+            let res = execute_body(&lambda.body, Some(&local_env), interp);
+            interp.leave();
+            res
         }
-        _ => Err(ScmErr::from("eval: unexpected Exp")),
-    }
-}
 
-fn apply(func: Exp, args: &[Exp]) -> Result<Exp, ScmErr> {
-    // func can be Exp::Primitive or Exp::Closure
-    match func {
-        Exp::Primitive(prim) => prim(args),
-
-        Exp::Closure(clos) => match *clos.body {
-            Exp::List(body) => match body.first() {
-                Some(Exp::List(vars)) => {
-                    let local_env = make_env_ptr(clos.env.clone());
-                    for (var, arg) in vars.iter().zip(args) {
-                        match var {
-                            Exp::Symbol(i) => {
-                                local_env.borrow_mut().data.insert(i.clone(), arg.clone())
-                            }
-                            _ => {
-                                return Err(ScmErr::from(
-                                    "closure unpacking error: expected a list of Symbol's",
-                                ))
-                            }
-                        };
-                    }
-                    let definition = &body[1..];
-                    if definition.is_empty() {
-                        return Err(ScmErr::from("closure unpacking error: missing definition"));
-                    }
-                    definition.iter().fold(Ok(Exp::Empty), |_seed, exp| {
-                        eval(exp.clone(), Rc::clone(&local_env))
-                    })
-                }
-                _ => Err(ScmErr::from(
-                    "closure unpacking error: expected a non-empty list",
-                )),
-            },
-            _ => Err(ScmErr::from("closure unpacking error: expected a list")),
-        },
+        Exp::Compiled(clos) => vm::call(clos, args, interp),
+
         _ => Err(ScmErr::from(
-            "apply: a function can only be Exp::Primitive or Exp::Closure",
+            "apply: a function can only be Exp::Primitive, Exp::Native or Exp::Closure",
         )),
     }
 }
@@ -0,0 +1,43 @@
+use crate::parser::{cst, is_shebang};
+use crate::printer::{layout_forms, DEFAULT_WIDTH};
+use crate::types::*;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// * Source code formatting
+//
+// The source is read with its comments and blank lines, then laid out by the pretty printer.
+// Runs of blank lines are collapsed into one.
+
+/// Format a source in the canonical style.
+/// The shebang line of a script is kept as it is.
+pub fn format_source(src: &str) -> Result<String, ScmErr> {
+    match src.split_once('\n') {
+        Some((shebang, rest)) if is_shebang(shebang) => {
+            // The shebang line is left blank, so that the errors have the right line numbers.
+            let forms = cst::read_source(&format!("\n{}", rest))?;
+            Ok(format!(
+                "{}\n{}",
+                shebang,
+                layout_forms(&forms, DEFAULT_WIDTH)
+            ))
+        }
+        _ if is_shebang(src) => Ok(format!("{}\n", src)),
+        _ => Ok(layout_forms(&cst::read_source(src)?, DEFAULT_WIDTH)),
+    }
+}
+
+/// Format a source file in place, or only check it if `check` is set.
+/// Returns whether the file was already formatted.
+pub fn format_file(path: &Path, check: bool) -> Result<bool, Box<dyn Error>> {
+    let src = fs::read_to_string(path)?;
+    let res = format_source(&src).map_err(|e| format!("`{}`: {}", path.display(), e))?;
+    if res == src {
+        return Ok(true);
+    }
+    if !check {
+        fs::write(path, res)?;
+    }
+    Ok(false)
+}
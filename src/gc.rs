@@ -0,0 +1,425 @@
+use crate::types::*;
+use crate::vm::{Upval, VmClosure};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+// * Garbage collection
+//
+// Environments are reference-counted, but closures stored in the Frames they capture form cycles,
+// which reference counting alone never frees.
+// The heap keeps weak references to every object which can take part in such a cycle:
+// Frames, VM cells, VM Closures, Vectors and hash tables.
+//
+// A collection is a mark-and-sweep over those objects, where the roots are found by trial deletion:
+// an object with more strong references than the ones coming from other heap objects must be
+// referred to from elsewhere (the Rust stack, the VM stack, a global variable...), and is thus alive.
+// The unreachable objects are then cleared, which breaks the cycles and lets `Rc` free them.
+// This means that a collection is safe at any time, even in the middle of an evaluation.
+//
+// Each object is charged to the `Account` which was current when it was allocated, if any,
+// so that a sandbox can bound the objects allocated on its behalf, whichever else shares the thread.
+
+/// The minimal number of allocations between two automatic collections.
+const MIN_THRESHOLD: usize = 10_000;
+
+/// A snapshot of the heap statistics.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct HeapStats {
+    /// The number of live heap objects.
+    pub live: usize,
+    /// The largest number of live heap objects seen at the start of a collection.
+    pub peak: usize,
+    /// The total number of heap objects allocated.
+    pub allocated: usize,
+    /// The total number of heap objects freed by the collector.
+    pub freed: usize,
+    /// The number of collections run.
+    pub collections: usize,
+}
+
+/// The number of heap objects charged to a sandbox, which haven't been forgotten by a collection yet.
+#[derive(Clone, Default, Debug)]
+pub struct Account(Rc<Cell<usize>>);
+
+impl Account {
+    pub fn count(&self) -> usize {
+        self.0.get()
+    }
+
+    fn forget(account: &Option<Account>) {
+        if let Some(account) = account {
+            account.0.set(account.0.get() - 1);
+        }
+    }
+}
+
+/// A weak reference to a heap object.
+enum Tracked {
+    Frame(Weak<RefCell<Frame>>),
+    Cell(Weak<RefCell<Option<Exp>>>),
+    Closure(Weak<VmClosure>),
+    Vector(Weak<RefCell<Vec<Exp>>>),
+    Table(Weak<RefCell<HashTable>>),
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Obj> {
+        match self {
+            Tracked::Frame(w) => w.upgrade().map(Obj::Frame),
+            Tracked::Cell(w) => w.upgrade().map(Obj::Cell),
+            Tracked::Closure(w) => w.upgrade().map(Obj::Closure),
+            Tracked::Vector(w) => w.upgrade().map(Obj::Vector),
+            Tracked::Table(w) => w.upgrade().map(Obj::Table),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Frame(w) => w.strong_count() > 0,
+            Tracked::Cell(w) => w.strong_count() > 0,
+            Tracked::Closure(w) => w.strong_count() > 0,
+            Tracked::Vector(w) => w.strong_count() > 0,
+            Tracked::Table(w) => w.strong_count() > 0,
+        }
+    }
+}
+
+/// A strong reference to a heap object.
+enum Obj {
+    Frame(RcRefCell<Frame>),
+    Cell(Upval),
+    Closure(Rc<VmClosure>),
+    Vector(RcRefCell<Vec<Exp>>),
+    Table(RcRefCell<HashTable>),
+}
+
+struct Heap {
+    /// The objects, with the account they are charged to.
+    objects: Vec<(Tracked, Option<Account>)>,
+    /// The account charged for new objects.
+    account: Option<Account>,
+    since_last: usize,
+    threshold: usize,
+    stats: HeapStats,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        objects: Vec::new(),
+        account: None,
+        since_last: 0,
+        threshold: MIN_THRESHOLD,
+        stats: HeapStats::default(),
+    });
+}
+
+fn addr<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+/// Register a new object, collecting first if enough objects have been allocated since the last collection.
+fn track(obj: Tracked) {
+    let due = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.since_last >= heap.threshold
+    });
+    if due {
+        collect();
+    }
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        let account = heap.account.clone();
+        if let Some(account) = &account {
+            account.0.set(account.0.get() + 1);
+        }
+        heap.objects.push((obj, account));
+        heap.since_last += 1;
+        heap.stats.allocated += 1;
+    });
+}
+
+/// Allocate a Frame on the heap.
+pub fn alloc_frame(frame: Frame) -> RcRefCell<Frame> {
+    let res = Rc::new(RefCell::new(frame));
+    track(Tracked::Frame(Rc::downgrade(&res)));
+    res
+}
+
+/// Allocate a VM cell on the heap.
+pub fn alloc_cell(value: Option<Exp>) -> Upval {
+    let res = Rc::new(RefCell::new(value));
+    track(Tracked::Cell(Rc::downgrade(&res)));
+    res
+}
+
+/// Allocate a VM Closure on the heap.
+pub fn alloc_closure(closure: VmClosure) -> Rc<VmClosure> {
+    let res = Rc::new(closure);
+    track(Tracked::Closure(Rc::downgrade(&res)));
+    res
+}
+
+/// Allocate a Vector on the heap.
+pub fn alloc_vector(items: Vec<Exp>) -> RcRefCell<Vec<Exp>> {
+    let res = Rc::new(RefCell::new(items));
+    track(Tracked::Vector(Rc::downgrade(&res)));
+    res
+}
+
+/// Allocate a hash table on the heap.
+pub fn alloc_table(table: HashTable) -> RcRefCell<HashTable> {
+    let res = Rc::new(RefCell::new(table));
+    track(Tracked::Table(Rc::downgrade(&res)));
+    res
+}
+
+impl Obj {
+    fn addr(&self) -> usize {
+        match self {
+            Obj::Frame(rc) => addr(rc),
+            Obj::Cell(rc) => addr(rc),
+            Obj::Closure(rc) => addr(rc),
+            Obj::Vector(rc) => addr(rc),
+            Obj::Table(rc) => addr(rc),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Obj::Frame(rc) => Rc::strong_count(rc),
+            Obj::Cell(rc) => Rc::strong_count(rc),
+            Obj::Closure(rc) => Rc::strong_count(rc),
+            Obj::Vector(rc) => Rc::strong_count(rc),
+            Obj::Table(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// Push the addresses of the heap objects directly referred to by this one.
+    /// Returns false if the object is being mutated and can't be inspected.
+    fn children(&self, out: &mut Vec<usize>) -> bool {
+        fn exp_children(exp: &Exp, out: &mut Vec<usize>) {
+            match exp {
+                Exp::Closure(clos) => out.extend(clos.env.as_ref().map(addr)),
+                Exp::Compiled(clos) => out.push(addr(clos)),
+                Exp::List(list) => list.iter().for_each(|item| exp_children(item, out)),
+                Exp::Vector(rc) => out.push(addr(rc)),
+                Exp::HashTable(rc) => out.push(addr(rc)),
+                // Native functions are opaque, so whatever they capture is treated as a root.
+                _ => (),
+            }
+        }
+
+        match self {
+            Obj::Frame(rc) => match rc.try_borrow() {
+                Ok(frame) => {
+                    frame
+                        .slots
+                        .iter()
+                        .flatten()
+                        .for_each(|exp| exp_children(exp, out));
+                    out.extend(frame.outer.as_ref().map(addr));
+                    true
+                }
+                Err(_) => false,
+            },
+            Obj::Cell(rc) => match rc.try_borrow() {
+                Ok(cell) => {
+                    cell.iter().for_each(|exp| exp_children(exp, out));
+                    true
+                }
+                Err(_) => false,
+            },
+            Obj::Closure(rc) => {
+                out.extend(rc.upvals.iter().map(addr));
+                true
+            }
+            Obj::Vector(rc) => match rc.try_borrow() {
+                Ok(items) => {
+                    items.iter().for_each(|exp| exp_children(exp, out));
+                    true
+                }
+                Err(_) => false,
+            },
+            Obj::Table(rc) => match rc.try_borrow() {
+                Ok(table) => {
+                    table.values().for_each(|exp| exp_children(exp, out));
+                    true
+                }
+                Err(_) => false,
+            },
+        }
+    }
+
+    /// Take the references held by this object out of it, so that they can be dropped later.
+    fn clear(&self, garbage: &mut Garbage) {
+        match self {
+            Obj::Frame(rc) => {
+                let mut frame = rc.borrow_mut();
+                garbage
+                    .exps
+                    .extend(mem::take(&mut frame.slots).into_iter().flatten());
+                garbage.frames.extend(frame.outer.take());
+            }
+            Obj::Cell(rc) => garbage.exps.extend(rc.borrow_mut().take()),
+            // A VM Closure is immutable, but all the cycles through it go through its cells.
+            Obj::Closure(_) => (),
+            Obj::Vector(rc) => garbage.exps.extend(mem::take(&mut *rc.borrow_mut())),
+            Obj::Table(rc) => garbage
+                .exps
+                .extend(mem::take(&mut *rc.borrow_mut()).into_values()),
+        }
+    }
+}
+
+/// The references taken out of the unreachable objects.
+#[derive(Default)]
+struct Garbage {
+    exps: Vec<Exp>,
+    frames: Vec<RcRefCell<Frame>>,
+}
+
+/// Run a full collection, returning the number of objects freed.
+pub fn collect() -> usize {
+    let tracked = HEAP.with(|heap| mem::take(&mut heap.borrow_mut().objects));
+    let mut objs = Vec::new();
+    let mut tracked_alive = Vec::new();
+    for (weak, account) in tracked.into_iter() {
+        match weak.upgrade() {
+            Some(obj) => {
+                objs.push(obj);
+                tracked_alive.push((weak, account));
+            }
+            None => Account::forget(&account),
+        }
+    }
+    let index: HashMap<usize, usize> = objs
+        .iter()
+        .enumerate()
+        .map(|(i, obj)| (obj.addr(), i))
+        .collect();
+
+    // Trial deletion: subtract the references coming from heap objects, and the one in `objs`.
+    // An object which can't be inspected is a root, and its children are not subtracted,
+    // so they are kept as well.
+    let mut children: Vec<Vec<usize>> = Vec::with_capacity(objs.len());
+    let mut external: Vec<isize> = objs
+        .iter()
+        .map(|obj| obj.strong_count() as isize - 1)
+        .collect();
+    for (i, obj) in objs.iter().enumerate() {
+        let mut out = Vec::new();
+        if obj.children(&mut out) {
+            let out: Vec<usize> = out.iter().filter_map(|a| index.get(a).cloned()).collect();
+            out.iter().for_each(|&j| external[j] -= 1);
+            children.push(out);
+        } else {
+            external[i] = isize::MAX;
+            children.push(Vec::new());
+        }
+    }
+
+    // Mark everything reachable from the roots.
+    let mut marked = vec![false; objs.len()];
+    let mut stack: Vec<usize> = (0..objs.len()).filter(|&i| external[i] != 0).collect();
+    while let Some(i) = stack.pop() {
+        if !marked[i] {
+            marked[i] = true;
+            stack.extend(children[i].iter().filter(|&&j| !marked[j]));
+        }
+    }
+
+    // Sweep: clear the unreachable objects to break their cycles.
+    let mut garbage = Garbage::default();
+    let mut survivors = Vec::new();
+    let mut freed = 0;
+    for ((obj, entry), marked) in objs.iter().zip(tracked_alive).zip(marked) {
+        if marked {
+            survivors.push(entry);
+        } else {
+            obj.clear(&mut garbage);
+            Account::forget(&entry.1);
+            freed += 1;
+        }
+    }
+    let live = survivors.len();
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        // Objects allocated while dropping the garbage are kept as well.
+        survivors.append(&mut heap.objects);
+        heap.objects = survivors;
+        heap.since_last = 0;
+        heap.threshold = MIN_THRESHOLD.max(live);
+        heap.stats.peak = heap.stats.peak.max(objs.len());
+        heap.stats.freed += freed;
+        heap.stats.collections += 1;
+    });
+
+    // Finally drop the references, now that the heap is in a consistent state.
+    drop(garbage);
+    drop(objs);
+    freed
+}
+
+/// Get the current heap statistics.
+pub fn stats() -> HeapStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        let live = heap
+            .objects
+            .iter()
+            .filter(|(weak, _)| weak.is_alive())
+            .count();
+        HeapStats { live, ..heap.stats }
+    })
+}
+
+/// Set the account charged for new objects, returning the previous one.
+pub fn set_account(account: Option<Account>) -> Option<Account> {
+    HEAP.with(|heap| mem::replace(&mut heap.borrow_mut().account, account))
+}
+
+/// The size of the Lists and Strings in a datum, which aren't heap objects but take memory as well:
+/// one per List item, and one per 8 bytes of a String.
+/// The heap objects it refers to aren't counted.
+pub fn data_size(exp: &Exp) -> usize {
+    let mut size = 0;
+    let mut stack = vec![exp];
+    while let Some(exp) = stack.pop() {
+        match exp {
+            Exp::List(list) => {
+                size += list.len();
+                stack.extend(list.iter());
+            }
+            Exp::String(s) => size += s.len().div_ceil(8),
+            _ => (),
+        }
+    }
+    size
+}
+
+/// The size of the data held by the live objects charged to an account, as given by `data_size`.
+pub fn data_held(account: &Account) -> usize {
+    let objs: Vec<Obj> = HEAP.with(|heap| {
+        heap.borrow()
+            .objects
+            .iter()
+            .filter(|(_, owner)| matches!(owner, Some(owner) if Rc::ptr_eq(&owner.0, &account.0)))
+            .filter_map(|(weak, _)| weak.upgrade())
+            .collect()
+    });
+    let size = |exps: &mut dyn Iterator<Item = &Exp>| exps.map(data_size).sum::<usize>();
+    objs.iter()
+        .map(|obj| match obj {
+            Obj::Frame(rc) => rc
+                .try_borrow()
+                .map_or(0, |frame| size(&mut frame.slots.iter().flatten())),
+            Obj::Cell(rc) => rc.try_borrow().map_or(0, |cell| size(&mut cell.iter())),
+            Obj::Closure(_) => 0,
+            Obj::Vector(rc) => rc.try_borrow().map_or(0, |items| size(&mut items.iter())),
+            Obj::Table(rc) => rc.try_borrow().map_or(0, |table| size(&mut table.values())),
+        })
+        .sum()
+}
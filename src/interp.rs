@@ -0,0 +1,183 @@
+use crate::compile::compile;
+use crate::eval_apply;
+use crate::gc;
+use crate::interrupt::Interrupt;
+use crate::library::{self, Libraries};
+use crate::parser::expand_includes;
+use crate::run::Backend;
+use crate::sandbox::Sandbox;
+use crate::types::*;
+use crate::vm;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// * Interpreter state
+
+/// The state of a running interpreter.
+/// It is threaded through the evaluation, so that Native procedures can call back into Scheme.
+pub struct Interp {
+    /// The global Environment.
+    pub env: RcRefCell<Env>,
+    /// The way top-level forms are evaluated.
+    pub backend: Backend,
+    /// The resource limits of the evaluation, if it is sandboxed.
+    pub sandbox: Option<Sandbox>,
+    /// The flag which interrupts the running evaluation when set.
+    pub interrupt: Interrupt,
+    /// The current input, output and error ports.
+    pub ports: Ports,
+    /// The source file being loaded, which `load` and `include` resolve relative paths against.
+    pub file: Option<PathBuf>,
+    /// The libraries defined so far, and where to look for the others.
+    pub libraries: Libraries,
+    /// The command line given by `command-line`, the process arguments by default.
+    pub command_line: Vec<String>,
+}
+
+/// The current ports, which the printing and reading procedures use by default.
+pub struct Ports {
+    pub input: Rc<Port>,
+    pub output: Rc<Port>,
+    pub error: Rc<Port>,
+}
+
+impl Default for Ports {
+    /// The standard input, output and error streams.
+    fn default() -> Self {
+        Ports {
+            input: Rc::new(Port::stdin()),
+            output: Rc::new(Port::stdout()),
+            error: Rc::new(Port::stderr()),
+        }
+    }
+}
+
+impl Ports {
+    /// Flush the current output and error ports.
+    pub fn flush(&self) -> Result<(), ScmErr> {
+        self.output.flush()?;
+        self.error.flush()
+    }
+}
+
+impl Interp {
+    pub fn new(env: RcRefCell<Env>, backend: Backend) -> Self {
+        Interp {
+            env,
+            backend,
+            sandbox: None,
+            interrupt: Interrupt::new(),
+            ports: Ports::default(),
+            file: None,
+            libraries: Libraries::default(),
+            command_line: std::env::args().collect(),
+        }
+    }
+
+    /// Evaluate a top-level form in the global Environment, after splicing in its includes.
+    /// The output is flushed if the evaluation calls `exit`.
+    pub fn eval(&mut self, exp: Exp) -> Result<Exp, ScmErr> {
+        let res = match self.sandbox {
+            // The sandbox has no access to the files.
+            Some(_) => self.charged(|interp| interp.eval_form(exp)),
+            None => expand_includes(exp, &self.dir()).and_then(|exp| self.eval_form(exp)),
+        };
+        if let Err(e) = &res {
+            if let ErrKind::Exit(_) = e.kind() {
+                self.ports.flush()?;
+            }
+        }
+        res
+    }
+
+    fn eval_form(&mut self, exp: Exp) -> Result<Exp, ScmErr> {
+        if let Exp::List(list) = &exp {
+            match list.first() {
+                Some(Exp::Symbol(Symbol::IMPORT)) => return library::import(&list[1..], self),
+                Some(Exp::Symbol(Symbol::DEFINE_LIBRARY)) => {
+                    return library::define_library(&list[1..], self)
+                }
+                _ => (),
+            }
+        }
+        match self.backend {
+            Backend::Eval => eval_apply::eval(exp, self),
+            Backend::Vm => {
+                let code = compile(&exp, &self.env)?;
+                vm::run(code, self)
+            }
+        }
+    }
+
+    /// The directory which relative paths are resolved against:
+    /// the one of the file being loaded, or the current one.
+    pub fn dir(&self) -> PathBuf {
+        self.file
+            .as_ref()
+            .and_then(|file| file.parent())
+            .unwrap_or_else(|| Path::new(""))
+            .into()
+    }
+
+    /// Apply a procedure of any kind to the given arguments.
+    pub fn apply(&mut self, func: &Exp, args: &[Exp]) -> Result<Exp, ScmErr> {
+        self.charged(|interp| eval_apply::apply(func.clone(), args, interp))
+    }
+
+    /// Run an evaluation, charging the heap objects it allocates to the sandbox if any.
+    pub fn charged<T>(&mut self, eval: impl FnOnce(&mut Self) -> T) -> T {
+        match self.sandbox.as_ref().and_then(Sandbox::account) {
+            Some(account) => {
+                let previous = gc::set_account(Some(account));
+                let res = eval(self);
+                gc::set_account(previous);
+                res
+            }
+            None => eval(self),
+        }
+    }
+
+    /// Take one evaluation step, checking for an interrupt and the limits of the sandbox if any.
+    pub fn step(&mut self) -> Result<(), ScmErr> {
+        self.interrupt.check()?;
+        match &mut self.sandbox {
+            Some(sandbox) => sandbox.step(&self.env),
+            None => Ok(()),
+        }
+    }
+
+    /// Count the data returned by a primitive against the limits of the sandbox if any.
+    pub fn allocate(&mut self, exp: Exp) -> Result<Exp, ScmErr> {
+        match &mut self.sandbox {
+            Some(sandbox) => sandbox.allocate(&exp).map(|_| exp),
+            None => Ok(exp),
+        }
+    }
+
+    /// Enter a procedure call, checking the maximum depth of the sandbox if any.
+    pub fn enter(&mut self) -> Result<(), ScmErr> {
+        match &mut self.sandbox {
+            Some(sandbox) => sandbox.enter(),
+            None => Ok(()),
+        }
+    }
+
+    /// Leave a procedure call.
+    pub fn leave(&mut self) {
+        if let Some(sandbox) = &mut self.sandbox {
+            sandbox.leave();
+        }
+    }
+
+    /// The current call depth, if it is tracked.
+    pub fn depth(&self) -> usize {
+        self.sandbox.as_ref().map_or(0, Sandbox::depth)
+    }
+
+    /// Restore the call depth after an evaluation has been aborted.
+    pub fn set_depth(&mut self, depth: usize) {
+        if let Some(sandbox) = &mut self.sandbox {
+            sandbox.set_depth(depth);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::compile::image;
+use crate::convert::{FromScheme, IntoNative, IntoScheme};
+use crate::eval_apply::undefined;
+use crate::interp::Interp;
+use crate::interrupt::Interrupt;
+use crate::parser::{InPort, InStr, Input};
+use crate::prelude::{get_prelude, get_restricted_prelude, make_env_ptr};
+use crate::run::{self, Backend};
+use crate::sandbox::{Limits, Sandbox};
+use crate::types::*;
+use crate::vm;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The standard library written in Scheme, bundled into the binary.
+pub const STDLIB: &str = include_str!("../scheme/stdlib.rkt");
+
+/// A Scheme interpreter with its own global Environment.
+///
+/// ```
+/// use rusk::Interpreter;
+///
+/// let mut rusk = Interpreter::new();
+/// rusk.eval_str("(define (square x) (* x x))").unwrap();
+/// assert_eq!(rusk.eval_str("(square 12)").unwrap().to_string(), "144");
+/// ```
+pub struct Interpreter {
+    interp: Interp,
+    /// The width to pretty print the results of the REPL within, if any.
+    pretty: Option<usize>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// Create an interpreter with a fresh global Environment, containing only the Prelude.
+    /// Top-level forms are evaluated by the tree-walking backend.
+    pub fn new() -> Self {
+        Interpreter::with_backend(Backend::Eval)
+    }
+
+    /// Create an interpreter with a fresh global Environment, using the given backend.
+    pub fn with_backend(backend: Backend) -> Self {
+        Interpreter {
+            interp: Interp::new(make_env_ptr(get_prelude()), backend),
+            pretty: None,
+        }
+    }
+
+    /// Create a sandboxed interpreter for untrusted code.
+    /// Its Prelude has no access to the host (`exit`, files...),
+    /// and its evaluation fails with an error of kind `ErrKind::Limit` when a limit is exceeded.
+    ///
+    /// ```
+    /// # use rusk::{ErrKind, Interpreter, Limit, Limits};
+    /// let limits = Limits {
+    ///     fuel: Some(1000),
+    ///     max_depth: Some(1000),
+    ///     max_heap: None,
+    /// };
+    /// let mut rusk = Interpreter::sandboxed(limits);
+    /// let err = rusk.eval_str("(define (loop) (loop)) (loop)").unwrap_err();
+    /// assert_eq!(err.kind(), ErrKind::Limit(Limit::Fuel));
+    /// assert!(rusk.eval_str("(exit)").is_err());
+    /// ```
+    pub fn sandboxed(limits: Limits) -> Self {
+        let mut interp = Interp::new(make_env_ptr(get_restricted_prelude()), Backend::Eval);
+        interp.sandbox = Some(Sandbox::new(limits));
+        Interpreter {
+            interp,
+            pretty: None,
+        }
+    }
+
+    /// Get the sandbox of the interpreter, if it is sandboxed.
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.interp.sandbox.as_ref()
+    }
+
+    /// Evaluate every form in the source, returning the value of the last one.
+    /// The evaluation stops at the first error.
+    ///
+    /// ```
+    /// # use rusk::Interpreter;
+    /// let mut rusk = Interpreter::new();
+    /// let res = rusk.eval_str("(define x 20) (+ x 22)").unwrap();
+    /// assert_eq!(res.to_string(), "42");
+    /// assert!(rusk.eval_str("(car 1)").is_err());
+    /// ```
+    pub fn eval_str(&mut self, src: &str) -> Result<Exp, ScmErr> {
+        self.eval_port(&mut InStr::new(src))
+    }
+
+    /// Evaluate the bundled standard library, which defines `map`, `displayln`... on top of the Prelude.
+    ///
+    /// ```
+    /// # use rusk::Interpreter;
+    /// let mut rusk = Interpreter::new();
+    /// assert!(rusk.get_global("displayln").is_none());
+    /// rusk.load_stdlib().unwrap();
+    /// assert!(rusk.get_global("displayln").is_some());
+    /// ```
+    pub fn load_stdlib(&mut self) -> Result<(), ScmErr> {
+        self.eval_str(STDLIB)
+            .map(drop)
+            .map_err(|e| ScmErr::from(&format!("stdlib: {}", e)))
+    }
+
+    /// Evaluate a source file in the same way as `eval_str`, or run a compiled image.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Exp, ScmErr> {
+        let path = path.as_ref();
+        let file_err = |e: io::Error| ScmErr::from(&format!("`{}`: {}", path.display(), e));
+        let bytes = fs::read(path).map_err(file_err)?;
+        if image::is_image(&bytes) {
+            let codes = image::read_image(&bytes, &self.interp.env)?;
+            codes.into_iter().try_fold(Exp::Empty, |_seed, code| {
+                self.interp.charged(|interp| vm::run(code, interp))
+            })
+        } else {
+            run::load_file(path, &mut self.interp)
+        }
+    }
+
+    fn eval_port(&mut self, inport: &mut impl InPort) -> Result<Exp, ScmErr> {
+        let mut res = Exp::Empty;
+        loop {
+            match inport.next_token() {
+                Ok(None) => break Ok(res),
+                token => {
+                    let exp = inport.read_exp(token)?;
+                    res = self.interp.eval(exp)?;
+                }
+            }
+        }
+    }
+
+    /// Add a directory to search for the files of the imported libraries.
+    /// The directories are searched in the order they have been added.
+    pub fn add_library_path(&mut self, dir: impl Into<PathBuf>) {
+        self.interp.libraries.paths.push(dir.into());
+    }
+
+    /// Bind a global variable, overwriting the previous definition if any.
+    ///
+    /// ```
+    /// # use rusk::Interpreter;
+    /// let mut rusk = Interpreter::new();
+    /// rusk.define_global("greeting", "hello");
+    /// rusk.define_global("primes", vec![2, 3, 5, 7]);
+    /// assert_eq!(rusk.eval_str("(car (cdr primes))").unwrap().to_string(), "3");
+    /// ```
+    pub fn define_global(&mut self, name: &str, value: impl IntoScheme) {
+        self.interp
+            .env
+            .borrow_mut()
+            .define(Symbol::intern(name), value.into_scheme());
+    }
+
+    /// Register a Rust closure as a global Scheme function.
+    /// The closure is given the interpreter, so that it can call back into Scheme.
+    ///
+    /// ```
+    /// # use rusk::{Arity, Exp, Interpreter};
+    /// let mut rusk = Interpreter::new();
+    /// rusk.register_fn("twice", Arity::Exactly(2), |args, interp| {
+    ///     let once = interp.apply(&args[0], &args[1..])?;
+    ///     interp.apply(&args[0], &[once])
+    /// });
+    /// let res = rusk.eval_str("(twice (lambda (x) (* x 3)) 5)").unwrap();
+    /// assert_eq!(res.to_string(), "45");
+    /// ```
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        func: impl Fn(&[Exp], &mut Interp) -> Result<Exp, ScmErr> + 'static,
+    ) {
+        self.define_global(name, Exp::native(name, arity, func));
+    }
+
+    /// Register a Rust function as a global Scheme function,
+    /// converting its arguments and its return value as in `wrap_fn`.
+    ///
+    /// ```
+    /// # use rusk::Interpreter;
+    /// let mut rusk = Interpreter::new();
+    /// rusk.register_wrapped("hypot", |x: f64, y: f64| x.hypot(y));
+    /// assert_eq!(rusk.eval_str("(hypot 3 4)").unwrap().to_string(), "5");
+    /// ```
+    pub fn register_wrapped<Args>(&mut self, name: &str, func: impl IntoNative<Args>) {
+        self.define_global(name, func.into_native(name));
+    }
+
+    /// Get the value of a global variable, if it is defined.
+    ///
+    /// ```
+    /// # use rusk::{Exp, Interpreter};
+    /// let mut rusk = Interpreter::new();
+    /// rusk.eval_str("(define answer 42)").unwrap();
+    /// match rusk.get_global("answer") {
+    ///     Some(Exp::Number(n)) => assert_eq!(n, 42.0),
+    ///     _ => panic!("answer should be a Number"),
+    /// }
+    /// assert!(rusk.get_global("question").is_none());
+    /// ```
+    pub fn get_global(&self, name: &str) -> Option<Exp> {
+        self.interp.env.borrow().lookup(Symbol::intern(name))
+    }
+
+    /// Get the value of a global variable, converted to a Rust value.
+    ///
+    /// ```
+    /// # use rusk::Interpreter;
+    /// let mut rusk = Interpreter::new();
+    /// rusk.eval_str("(define xs (cons 1 (cons 2 null)))").unwrap();
+    /// assert_eq!(rusk.get::<Vec<i64>>("xs").unwrap(), vec![1, 2]);
+    /// assert!(rusk.get::<String>("xs").is_err());
+    /// ```
+    pub fn get<T: FromScheme>(&self, name: &str) -> Result<T, ScmErr> {
+        let symbol = Symbol::intern(name);
+        let value = self.get_global(name).ok_or_else(|| undefined(symbol))?;
+        T::from_scheme(&value).map_err(|e| ScmErr::from(&format!("{}: {}", name, e)))
+    }
+
+    /// Call a global function with the given arguments.
+    pub fn call(&mut self, name: &str, args: &[Exp]) -> Result<Exp, ScmErr> {
+        let func = self
+            .get_global(name)
+            .ok_or_else(|| undefined(Symbol::intern(name)))?;
+        self.interp.apply(&func, args)
+    }
+
+    /// Get a handle which interrupts the running evaluation, from another thread for example.
+    /// The evaluation then fails with an error of kind `ErrKind::Interrupted`.
+    ///
+    /// ```
+    /// # use rusk::{ErrKind, Interpreter};
+    /// let mut rusk = Interpreter::new();
+    /// rusk.interrupt_handle().interrupt();
+    /// let err = rusk.eval_str("(define x 1)").unwrap_err();
+    /// assert_eq!(err.kind(), ErrKind::Interrupted);
+    /// assert!(rusk.get_global("x").is_none());
+    /// ```
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interp.interrupt.clone()
+    }
+
+    /// Set the command line given by `command-line`, like a script followed by its arguments.
+    ///
+    /// ```
+    /// # use rusk::Interpreter;
+    /// let mut rusk = Interpreter::new();
+    /// rusk.set_command_line(vec!["script.scm".into(), "-v".into()]);
+    /// let res = rusk.eval_str("(car (cdr (command-line)))").unwrap();
+    /// assert_eq!(res.to_string(), "\"-v\"");
+    /// ```
+    pub fn set_command_line(&mut self, args: Vec<String>) {
+        self.interp.command_line = args;
+    }
+
+    /// Pretty print the results of the REPL within the given width, or print them on one line.
+    pub fn set_pretty_print(&mut self, width: Option<usize>) {
+        self.pretty = width;
+    }
+
+    /// Run an interactive REPL on the terminal.
+    /// Ctrl-C aborts the running evaluation and goes back to the prompt.
+    /// Returns the exit code given to `exit`, or 0 at the end of the input.
+    pub fn repl(&mut self) -> io::Result<i32> {
+        let _guard = self.interp.interrupt.on_sigint()?;
+        run::repl(&mut Input::new(), &mut self.interp, self.pretty)
+    }
+}
@@ -0,0 +1,58 @@
+use crate::types::*;
+use signal_hook::consts::SIGINT;
+use signal_hook::SigId;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// * Interrupting a running evaluation
+//
+// An interrupt only sets a flag, which the evaluation polls at every step.
+// The current top-level form is then aborted with an error of kind `ErrKind::Interrupted`,
+// and the Environment is left as it was.
+
+/// A flag to interrupt the evaluations of an interpreter,
+/// from a signal handler or from another thread.
+#[derive(Clone, Default)]
+pub struct Interrupt {
+    flag: Arc<AtomicBool>,
+}
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Interrupt::default()
+    }
+
+    /// Request the running evaluation to stop.
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    /// Forget a pending request.
+    pub fn clear(&self) {
+        self.flag.store(false, Ordering::SeqCst);
+    }
+
+    /// Fail if an interrupt has been requested, clearing the request.
+    pub fn check(&self) -> Result<(), ScmErr> {
+        if self.flag.load(Ordering::Relaxed) && self.flag.swap(false, Ordering::SeqCst) {
+            Err(ScmErr::interrupted())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Interrupt on SIGINT (Ctrl-C), until the returned guard is dropped.
+    pub fn on_sigint(&self) -> io::Result<SigintGuard> {
+        signal_hook::flag::register(SIGINT, Arc::clone(&self.flag)).map(SigintGuard)
+    }
+}
+
+/// Keeps SIGINT registered to an `Interrupt`.
+pub struct SigintGuard(SigId);
+
+impl Drop for SigintGuard {
+    fn drop(&mut self) {
+        signal_hook::low_level::unregister(self.0);
+    }
+}
@@ -0,0 +1,51 @@
+//! rusk, a simple Scheme interpreter.
+//!
+//! The [`Interpreter`] type is the entry point for embedding rusk in a Rust program:
+//!
+//! ```
+//! use rusk::{Arity, Exp, Interpreter};
+//!
+//! let mut rusk = Interpreter::new();
+//! rusk.register_fn("double", Arity::Exactly(1), |args, _| match args[0] {
+//!     Exp::Number(x) => Ok(Exp::Number(x * 2.0)),
+//!     _ => Err(rusk::ScmErr::from("double: expected Exp::Number")),
+//! });
+//! rusk.eval_str("(define x (double 21))").unwrap();
+//! assert_eq!(rusk.get_global("x").unwrap().to_string(), "42");
+//! ```
+//!
+//! With the `serde` feature enabled, [`Exp`] implements `Serialize` and `Deserialize`.
+
+mod analyzer;
+mod compile;
+mod convert;
+mod eval_apply;
+mod formatter;
+mod gc;
+mod interp;
+mod interpreter;
+mod interrupt;
+mod library;
+mod parser;
+mod prelude;
+mod printer;
+mod run;
+mod sandbox;
+#[cfg(feature = "serde")]
+mod serialize;
+mod tests;
+mod types;
+mod vm;
+
+pub use crate::convert::{wrap_fn, FromScheme, IntoNative, IntoScheme, IntoSchemeResult};
+pub use crate::formatter::{format_file, format_source};
+pub use crate::interp::{Interp, Ports};
+pub use crate::interpreter::{Interpreter, STDLIB};
+pub use crate::interrupt::{Interrupt, SigintGuard};
+pub use crate::printer::{layout, pretty_print, Doc, Labels, Printer, Style, DEFAULT_WIDTH};
+pub use crate::run::{compile_file, is_image_file, Backend};
+pub use crate::sandbox::{Limits, Sandbox};
+pub use crate::types::{Arity, ErrKind, Exp, Limit, Port, ScmErr, Symbol};
+
+#[macro_use]
+extern crate lazy_static;
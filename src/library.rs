@@ -0,0 +1,381 @@
+use crate::interp::Interp;
+use crate::prelude::{get_prelude, get_restricted_prelude, LIBRARIES};
+use crate::printer;
+use crate::run;
+use crate::types::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// * Libraries, following R7RS
+//
+// `(define-library (name ...) declaration ...)` evaluates the body of a library
+// in a new global Environment, where only the imported variables are bound.
+// `(import import-set ...)` binds the variables exported by libraries in the current Environment.
+// The importer shares the cells of the library's variables, so it sees their later definitions.
+//
+// A library which hasn't been defined yet is loaded from `a/b.sld` for `(a b)`,
+// looked up in the library search paths, then in the directories of the libraries being loaded,
+// and next to the importing file.
+// Every library is loaded at most once per interpreter.
+// The standard libraries export the definitions of the Prelude.
+
+/// The name of a library, like `(scheme base)`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct LibraryName(Vec<String>);
+
+impl LibraryName {
+    fn from_exp(exp: &Exp) -> Result<Self, ScmErr> {
+        let parts = match exp {
+            Exp::List(parts) if !parts.is_empty() => parts,
+            _ => {
+                return Err(ScmErr::from(&format!(
+                    "library: invalid name {}",
+                    show(exp)
+                )))
+            }
+        };
+        let parts: Option<Vec<String>> = parts
+            .iter()
+            .map(|part| match part {
+                Exp::Symbol(s) => Some(s.as_str().into()),
+                &Exp::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n.to_string()),
+                _ => None,
+            })
+            .collect();
+        parts
+            .map(LibraryName)
+            .ok_or_else(|| ScmErr::from(&format!("library: invalid name {}", show(exp))))
+    }
+
+    /// The relative path of the file defining the library.
+    fn file(&self) -> PathBuf {
+        let mut res: PathBuf = self.0.iter().collect();
+        res.set_extension("sld");
+        res
+    }
+}
+
+impl fmt::Display for LibraryName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({})", self.0.join(" "))
+    }
+}
+
+/// Print a form, for the error messages.
+/// Code is read as flat Lists, which `write` would print as pairs when they have two items.
+fn show(exp: &Exp) -> String {
+    match exp {
+        Exp::List(items) => {
+            let items: Vec<String> = items.iter().map(show).collect();
+            format!("({})", items.join(" "))
+        }
+        _ => printer::write(exp),
+    }
+}
+
+/// A defined library.
+pub struct Library {
+    env: RcRefCell<Env>,
+    /// The exported names, with the names of the variables in the library.
+    exports: Vec<(Symbol, Symbol)>,
+}
+
+impl Library {
+    /// Make a standard library from the Prelude, if there is one with the given name.
+    /// The definitions missing from the Prelude, like in a sandbox, aren't exported.
+    fn standard(name: &LibraryName, prelude: &Env) -> Option<Self> {
+        let key = name.0.join(" ");
+        let &(_, names) = LIBRARIES.iter().find(|(lib, _)| *lib == key)?;
+        let mut env = Env::new();
+        let mut exports = Vec::new();
+        for &name in names {
+            let symbol = Symbol::intern(name);
+            if let Some(value) = prelude.lookup(symbol) {
+                env.define(symbol, value);
+                exports.push((symbol, symbol));
+            }
+        }
+        Some(Library {
+            env: make_env_ptr(env),
+            exports,
+        })
+    }
+
+    /// The exported variables, by their exported names.
+    fn bindings(&self) -> Vec<(Symbol, Rc<GlobalCell>)> {
+        let mut env = self.env.borrow_mut();
+        self.exports
+            .iter()
+            .map(|&(external, internal)| (external, env.cell(internal)))
+            .collect()
+    }
+}
+
+/// The libraries of an interpreter.
+#[derive(Default)]
+pub struct Libraries {
+    /// The directories searched for library files.
+    pub paths: Vec<PathBuf>,
+    defined: HashMap<LibraryName, Rc<Library>>,
+    /// The libraries being defined, from the outermost one, to report circular imports.
+    loading: Vec<LibraryName>,
+    /// The directories where the libraries being loaded have been found.
+    roots: Vec<PathBuf>,
+}
+
+/// Evaluate `(define-library name declaration ...)`.
+pub fn define_library(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let name = match args.first() {
+        Some(name) => LibraryName::from_exp(name)?,
+        None => return Err(ScmErr::from("define-library: expected a name")),
+    };
+    // A library loaded by `import` is already on the stack.
+    let outermost = interp.libraries.loading.last() != Some(&name);
+    if outermost {
+        interp.libraries.loading.push(name.clone());
+    }
+    let lib = Library {
+        env: make_env_ptr(Env::new()),
+        exports: Vec::new(),
+    };
+    let res = args[1..]
+        .iter()
+        .try_fold(lib, |lib, decl| declaration(lib, decl, interp));
+    if outermost {
+        interp.libraries.loading.pop();
+    }
+    let lib = res.map_err(|e| in_library(e, &name))?;
+    for &(_, internal) in &lib.exports {
+        if lib.env.borrow().lookup(internal).is_none() {
+            return Err(ScmErr::from(&format!(
+                "define-library: {} exports {}, which is undefined",
+                name, internal
+            )));
+        }
+    }
+    interp.libraries.defined.insert(name, Rc::new(lib));
+    Ok(Exp::Empty)
+}
+
+fn in_library(e: ScmErr, name: &LibraryName) -> ScmErr {
+    match e.kind() {
+        ErrKind::Error => ScmErr::from(&format!("{}: {}", name, e)),
+        _ => e,
+    }
+}
+
+/// Evaluate a library declaration.
+fn declaration(mut lib: Library, decl: &Exp, interp: &mut Interp) -> Result<Library, ScmErr> {
+    let (head, args) = match decl {
+        Exp::List(list) => match list.split_first() {
+            Some((Exp::Symbol(head), args)) => (*head, args),
+            _ => return Err(ScmErr::from(&format!("invalid declaration {}", show(decl)))),
+        },
+        _ => return Err(ScmErr::from(&format!("invalid declaration {}", show(decl)))),
+    };
+    match head.as_str() {
+        "export" => {
+            for spec in args {
+                lib.exports.push(export_spec(spec)?);
+            }
+        }
+        "import" => import_into(args, &lib.env, interp)?,
+        // Includes have been spliced in as `begin`.
+        "begin" => {
+            let global = mem::replace(&mut interp.env, Rc::clone(&lib.env));
+            let res = args
+                .iter()
+                .try_for_each(|form| interp.eval(form.clone()).map(drop));
+            interp.env = global;
+            res?;
+        }
+        _ => return Err(ScmErr::from(&format!("unknown declaration {}", show(decl)))),
+    }
+    Ok(lib)
+}
+
+/// Parse an export spec, `name` or `(rename internal external)`.
+fn export_spec(spec: &Exp) -> Result<(Symbol, Symbol), ScmErr> {
+    match spec {
+        &Exp::Symbol(s) => Ok((s, s)),
+        Exp::List(list) => match list.as_slice() {
+            [Exp::Symbol(rename), Exp::Symbol(internal), Exp::Symbol(external)]
+                if rename.as_str() == "rename" =>
+            {
+                Ok((*external, *internal))
+            }
+            _ => Err(ScmErr::from(&format!(
+                "export: invalid spec {}",
+                show(spec)
+            ))),
+        },
+        _ => Err(ScmErr::from(&format!(
+            "export: invalid spec {}",
+            show(spec)
+        ))),
+    }
+}
+
+/// Evaluate `(import import-set ...)` in the current Environment.
+pub fn import(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let env = Rc::clone(&interp.env);
+    import_into(args, &env, interp)?;
+    Ok(Exp::Empty)
+}
+
+fn import_into(sets: &[Exp], env: &RcRefCell<Env>, interp: &mut Interp) -> Result<(), ScmErr> {
+    for set in sets {
+        for (symbol, cell) in import_set(set, interp)? {
+            env.borrow_mut().import(symbol, cell);
+        }
+    }
+    Ok(())
+}
+
+type Bindings = Vec<(Symbol, Rc<GlobalCell>)>;
+
+/// Get the bindings of an import set, by the names they are imported as.
+fn import_set(set: &Exp, interp: &mut Interp) -> Result<Bindings, ScmErr> {
+    let invalid = || ScmErr::from(&format!("import: invalid import set {}", show(set)));
+    let list = match set {
+        Exp::List(list) => list,
+        _ => return Err(invalid()),
+    };
+    let (modifier, inner, args) = match list.as_slice() {
+        [Exp::Symbol(modifier), inner @ Exp::List(_), args @ ..]
+            if ["only", "except", "prefix", "rename"].contains(&modifier.as_str()) =>
+        {
+            (modifier.as_str(), inner, args)
+        }
+        _ => {
+            let name = LibraryName::from_exp(set)?;
+            return Ok(library(&name, interp)?.bindings());
+        }
+    };
+    let mut bindings = import_set(inner, interp)?;
+    let symbols = || -> Result<Vec<Symbol>, ScmErr> {
+        args.iter()
+            .map(|arg| match arg {
+                &Exp::Symbol(s) => Ok(s),
+                _ => Err(invalid()),
+            })
+            .collect()
+    };
+    let find = |bindings: &Bindings, symbol: Symbol| {
+        bindings
+            .iter()
+            .position(|&(s, _)| s == symbol)
+            .ok_or_else(|| {
+                ScmErr::from(&format!(
+                    "import: {} isn't in the import set {}",
+                    symbol,
+                    show(inner)
+                ))
+            })
+    };
+    match modifier {
+        "only" => {
+            let mut res = Vec::new();
+            for symbol in symbols()? {
+                let i = find(&bindings, symbol)?;
+                res.push(bindings[i].clone());
+            }
+            Ok(res)
+        }
+        "except" => {
+            for symbol in symbols()? {
+                let i = find(&bindings, symbol)?;
+                bindings.remove(i);
+            }
+            Ok(bindings)
+        }
+        "prefix" => match args {
+            [Exp::Symbol(prefix)] => Ok(bindings
+                .into_iter()
+                .map(|(s, cell)| (Symbol::intern(&format!("{}{}", prefix, s)), cell))
+                .collect()),
+            _ => Err(invalid()),
+        },
+        _ => {
+            for arg in args {
+                match arg {
+                    Exp::List(pair) => match pair.as_slice() {
+                        &[Exp::Symbol(from), Exp::Symbol(to)] => {
+                            let i = find(&bindings, from)?;
+                            bindings[i].0 = to;
+                        }
+                        _ => return Err(invalid()),
+                    },
+                    _ => return Err(invalid()),
+                }
+            }
+            Ok(bindings)
+        }
+    }
+}
+
+/// Get a library, loading it if it hasn't been defined yet.
+fn library(name: &LibraryName, interp: &mut Interp) -> Result<Rc<Library>, ScmErr> {
+    if let Some(lib) = interp.libraries.defined.get(name) {
+        return Ok(Rc::clone(lib));
+    }
+    let prelude = match interp.sandbox {
+        Some(_) => get_restricted_prelude(),
+        None => get_prelude(),
+    };
+    if let Some(lib) = Library::standard(name, &prelude) {
+        let lib = Rc::new(lib);
+        interp
+            .libraries
+            .defined
+            .insert(name.clone(), Rc::clone(&lib));
+        return Ok(lib);
+    }
+    if interp.libraries.loading.contains(name) {
+        let cycle: Vec<String> = interp
+            .libraries
+            .loading
+            .iter()
+            .skip_while(|lib| *lib != name)
+            .chain(Some(name))
+            .map(LibraryName::to_string)
+            .collect();
+        return Err(ScmErr::from(&format!(
+            "import: circular import {}",
+            cycle.join(" -> ")
+        )));
+    }
+    // The sandbox has no access to the files.
+    let libs = &interp.libraries;
+    let root = match interp.sandbox {
+        Some(_) => None,
+        None => libs
+            .paths
+            .iter()
+            .chain(libs.roots.iter().rev())
+            .cloned()
+            .chain(Some(interp.dir()))
+            .find(|dir| dir.join(name.file()).is_file()),
+    };
+    let root = root.ok_or_else(|| ScmErr::from(&format!("import: library {} not found", name)))?;
+    let path = root.join(name.file());
+    interp.libraries.loading.push(name.clone());
+    interp.libraries.roots.push(root);
+    // The other forms of the file don't leak into the importer.
+    let global = mem::replace(&mut interp.env, make_env_ptr(Env::new()));
+    let res = run::load_file(&path, interp);
+    interp.env = global;
+    interp.libraries.loading.pop();
+    interp.libraries.roots.pop();
+    res?;
+    interp.libraries.defined.get(name).cloned().ok_or_else(|| {
+        ScmErr::from(&format!(
+            "import: `{}` doesn't define {}",
+            path.display(),
+            name
+        ))
+    })
+}
@@ -1,72 +1,175 @@
-#![feature(result_cloned)]
-
-mod eval_apply;
-mod parser;
-mod prelude;
-mod run;
-mod tests;
-mod types;
-
-use crate::parser::{InFile, Input};
-use crate::prelude::{get_prelude, make_env_ptr};
 use clap::App;
+use rusk::{Backend, ErrKind, Exp, Interpreter, Labels, Printer, ScmErr, Style};
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::process;
 
 #[macro_use]
 extern crate clap;
 
-#[macro_use]
-extern crate lazy_static;
-
-static WELCOME_BANNER: &'static str = "Welcome to rusk, a simple Scheme interpreter.";
-static STDLIB_PATH: &'static str = "./scheme/stdlib.rkt";
+static WELCOME_BANNER: &str = "Welcome to rusk, a simple Scheme interpreter.";
+static LIBRARY_PATH_VAR: &str = "RUSK_LIBRARY_PATH";
 
 fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
 
-    // println!("Hello, rusk!");
-    println!("{}", WELCOME_BANNER);
-    let global_env = make_env_ptr(get_prelude());
+    if let Some(matches) = matches.subcommand_matches("compile") {
+        let input = matches.value_of("INPUT").unwrap();
+        let output = match matches.value_of("output") {
+            Some(output) => output.into(),
+            None => Path::new(input).with_extension("rbc"),
+        };
+        let stdlib = !matches.is_present("no-stdlib");
+        if let Err(e) = rusk::compile_file(input, &output, stdlib) {
+            eprintln!("compile: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
-    // Interpret source file
-    let read_source_file = |path: &str| {
-        let mut inport = InFile::new(path);
-        run::repl(&mut inport, &mut std::io::sink(), &global_env)
-    };
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        let check = matches.is_present("check");
+        let mut formatted = true;
+        for path in matches.values_of("FILES").unwrap() {
+            match rusk::format_file(Path::new(path), check) {
+                Ok(true) => (),
+                Ok(false) if check => {
+                    eprintln!("fmt: `{}` is not formatted", path);
+                    formatted = false;
+                }
+                Ok(false) => (),
+                Err(e) => {
+                    eprintln!("fmt: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        process::exit(if formatted { 0 } else { 1 });
+    }
 
-    let read_source_file_verbose = |path: &str| {
-        let mut inport = InFile::new(path);
-        print!(".. Reading `{}`: ", inport.file_str);
-        run::repl(&mut inport, &mut std::io::sink(), &global_env)
-            .expect("Error while loading file.");
-        println!("Done.");
+    let backend = if matches.is_present("vm") {
+        Backend::Vm
+    } else {
+        Backend::Eval
     };
+    let mut rusk = Interpreter::with_backend(backend);
+    let pretty = matches.is_present("pretty");
+    if pretty {
+        rusk.set_pretty_print(Some(rusk::DEFAULT_WIDTH));
+    }
+    for dir in matches.values_of("lib-path").into_iter().flatten() {
+        rusk.add_library_path(dir);
+    }
+    if let Some(paths) = env::var_os(LIBRARY_PATH_VAR) {
+        env::split_paths(&paths).for_each(|dir| rusk.add_library_path(dir));
+    }
+
+    // The expressions given with `-e` and `-p`, in the order of the command line.
+    // Those given with `-p` have their result printed.
+    let mut exprs: Vec<(usize, bool, &str)> = Vec::new();
+    for &(flag, print) in &[("eval", false), ("print", true)] {
+        if let (Some(indices), Some(values)) = (matches.indices_of(flag), matches.values_of(flag)) {
+            exprs.extend(indices.zip(values).map(|(i, expr)| (i, print, expr)));
+        }
+    }
+    exprs.sort_unstable();
+
+    let input = matches.value_of("INPUT");
+    // A script sees itself and its arguments as the command line.
+    if let Some(path) = input {
+        let args = matches.values_of("ARGS").into_iter().flatten();
+        let command_line = Some(path).into_iter().chain(args).map(String::from);
+        rusk.set_command_line(command_line.collect());
+    }
+    // The REPL is run if asked for, or if there is nothing else to run.
+    let repl = matches.is_present("repl") || (input.is_none() && exprs.is_empty());
+    if repl {
+        println!("{}", WELCOME_BANNER);
+    }
 
-    // REPL mode
-    let run_repl = || {
-        let mut inport = Input::new();
-        run::repl(&mut inport, &mut std::io::stdout(), &global_env)
+    let exit = |code: i32| -> ! {
+        let _ = io::stdout().flush();
+        process::exit(code)
     };
 
-    let res = {
-        // Load stdlib
-        read_source_file_verbose(STDLIB_PATH);
+    // Exit on an error, with the code given to `exit` or 1.
+    let check = |res: Result<Exp, ScmErr>| -> Exp {
+        match res {
+            Ok(exp) => exp,
+            Err(e) => match e.kind() {
+                ErrKind::Exit(code) => exit(code),
+                _ => {
+                    let _ = io::stdout().flush();
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+            },
+        }
+    };
 
-        if let Some(path) = matches.value_of("INPUT") {
-            if matches.is_present("repl") {
-                read_source_file_verbose(&path);
-                run_repl()
-            } else {
-                read_source_file(&path)
+    // Interpret source file, or run a compiled image.
+    // `-` reads the source from stdin.
+    let read_source_file = |rusk: &mut Interpreter, path: &str| {
+        if repl {
+            print!(".. Reading `{}`: ", path);
+        }
+        let res = if path == "-" {
+            let mut src = String::new();
+            match io::stdin().read_to_string(&mut src) {
+                Ok(_) => rusk.eval_str(&src),
+                Err(e) => Err(ScmErr::from(&format!("`-`: {}", e))),
             }
         } else {
-            run_repl()
+            rusk.eval_file(path)
+        };
+        check(res);
+        if repl {
+            println!("Done.");
         }
     };
 
-    if let Err(e) = res {
-        eprintln!("run: {}", e);
-        process::exit(1);
-    };
+    // Load stdlib
+    // An image has the standard library compiled in, so it isn't loaded again,
+    // unless expressions given with `-e` and `-p` are run before the image.
+    let image = exprs.is_empty()
+        && input.is_some_and(|path| path != "-" && rusk::is_image_file(Path::new(path)));
+    if let Some(path) = matches.value_of("stdlib") {
+        read_source_file(&mut rusk, path);
+    } else if !(matches.is_present("no-stdlib") || image) {
+        check(rusk.load_stdlib().map(|_| Exp::Empty));
+    }
+
+    for (_, print, expr) in exprs {
+        let res = check(rusk.eval_str(expr));
+        match res {
+            // The unspecified value of definitions and the like isn't shown.
+            Exp::Empty => (),
+            _ if print && pretty => {
+                println!("{}", rusk::pretty_print(&res, 0, rusk::DEFAULT_WIDTH))
+            }
+            _ if print => println!(
+                "{}",
+                Printer::new(&res, Style::Write, Labels::Cycles).print(&res)
+            ),
+            _ => (),
+        }
+    }
+
+    if let Some(path) = input {
+        read_source_file(&mut rusk, path);
+    }
+
+    if repl {
+        match rusk.repl() {
+            Ok(0) => (),
+            Ok(code) => exit(code),
+            Err(e) => {
+                eprintln!("run: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    exit(0);
 }
@@ -3,28 +3,121 @@ use regex::Regex;
 // use std::fs::{File, OpenOptions};
 // use std::io::{BufRead, BufReader};
 
+pub mod cst;
+mod include;
 mod inport;
-pub use inport::{InFile, InPort, Input};
+pub use include::{expand_includes, expand_located, Location};
+pub use inport::{InFile, InPort, InScm, InStr, Input};
 
 // * Parsing, refactored
 
 lazy_static! {
-    pub static ref TOKENIZER: Regex =
-        Regex::new(r#"\s*(,@|[('`,)]|"(?:[\\].|[^\\"])*"|;.*|[^\s('"`,;)]*)(.*)"#).unwrap();
+    pub static ref TOKENIZER: Regex = Regex::new(
+        r#"\s*(#\\.[^\s('"`,;)]*|,@|#\(|[('`,)]|"(?:[\\].|[^\\"])*"|;.*|[^\s('"`,;)]*)(.*)"#
+    )
+    .unwrap();
 }
 
-pub fn atom(token: &str) -> Exp {
+/// Whether a line is the shebang line of an executable script, like `#!/usr/bin/env rusk`.
+/// It is skipped when it is the first line of a source.
+pub fn is_shebang(line: &str) -> bool {
+    line.starts_with("#!/") || line.starts_with("#! ")
+}
+
+pub fn atom(token: &str) -> Result<Exp, ScmErr> {
+    if token.starts_with('"') {
+        return string_literal(token).map(Exp::String);
+    }
+    if let Some(name) = token.strip_prefix("#\\") {
+        return char_name(name).map(Exp::Char);
+    }
+    // Directives like `#!fold-case` aren't supported, and a shebang is only skipped on the first line.
+    if token.starts_with("#!") {
+        return Err(ScmErr::from(&format!(
+            "parser: unsupported directive {}",
+            token
+        )));
+    }
+    Ok(match token {
+        "#t" | "#true" => Exp::Bool(true),
+        "#f" | "#false" => Exp::Bool(false),
+        "+inf.0" => Exp::Number(f64::INFINITY),
+        "-inf.0" => Exp::Number(f64::NEG_INFINITY),
+        "+nan.0" | "-nan.0" => Exp::Number(f64::NAN),
+        _ => number_or_symbol(token),
+    })
+}
+
+fn number_or_symbol(token: &str) -> Exp {
     match token.parse::<f64>() {
         Ok(num) => Exp::Number(num),
-        Err(_) => Exp::Symbol(token.into()),
+        Err(_) => Exp::Symbol(Symbol::intern(token)),
+    }
+}
+
+/// Parse the name of a char literal, following `#\`.
+fn char_name(name: &str) -> Result<char, ScmErr> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => return Ok(c),
+        (Some('x'), Some(_)) => {
+            if let Some(c) = u32::from_str_radix(&name[1..], 16)
+                .ok()
+                .and_then(std::char::from_u32)
+            {
+                return Ok(c);
+            }
+        }
+        _ => (),
     }
+    CHAR_NAMES
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, c)| c)
+        .ok_or_else(|| ScmErr::from(&format!("parser: unknown char #\\{}", name)))
+}
+
+/// Parse a string literal token, quotes included.
+fn string_literal(token: &str) -> Result<String, ScmErr> {
+    let mut res = String::new();
+    let mut chars = token[1..token.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some('a') => res.push('\u{7}'),
+            Some('0') => res.push('\0'),
+            Some(c @ '\\') | Some(c @ '"') => res.push(c),
+            Some('x') => {
+                let hex: String = chars.by_ref().take_while(|&c| c != ';').collect();
+                let c = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| ScmErr::from(&format!("parser: invalid escape \\x{};", hex)))?;
+                res.push(c);
+            }
+            Some(c) => {
+                return Err(ScmErr::from(&format!(
+                    "parser: invalid escape \\{} in string",
+                    c
+                )))
+            }
+            None => return Err(ScmErr::from("parser: unterminated string")),
+        }
+    }
+    Ok(res)
 }
 
 /// Handles syntax sugar forms.
 // ! This is EXTREMELY DIRTY.
 // TODO: refactor this function in a more elegant way.
 pub fn desugar(exp: Exp) -> Result<Exp, ScmErr> {
-    fn require_len(list: &Vec<Exp>, min_len: usize) -> Result<(), ScmErr> {
+    fn require_len(list: &[Exp], min_len: usize) -> Result<(), ScmErr> {
         let len = list.len();
         if len < min_len {
             Err(ScmErr::from(&format!(
@@ -39,9 +132,9 @@ pub fn desugar(exp: Exp) -> Result<Exp, ScmErr> {
     match exp.clone() {
         Exp::List(list) => {
             // println!("Sugar debug: {:?}", list);
-            match list.get(0) {
-                Some(Exp::Symbol(s)) => match s.as_ref() {
-                    "define" => {
+            match list.first() {
+                Some(&Exp::Symbol(s)) => match s {
+                    Symbol::DEFINE => {
                         // (define (f . args) body+) => (define f (lambda args body+))
                         require_len(&list, 3)?;
                         let f: Exp; // Symbol
@@ -50,7 +143,7 @@ pub fn desugar(exp: Exp) -> Result<Exp, ScmErr> {
                         match &list[1] {
                             Exp::List(f_args) => {
                                 require_len(f_args, 1)?;
-                                f = match f_args[0].clone() {
+                                f = match f_args[0] {
                                     Exp::Symbol(s) => Exp::Symbol(s),
                                     _ => {
                                         return Err(ScmErr::from(
@@ -66,38 +159,42 @@ pub fn desugar(exp: Exp) -> Result<Exp, ScmErr> {
                                 body = list.iter().skip(2).cloned().collect();
                                 desugar(Exp::List({
                                     let lambda_args_body: Vec<Exp> =
-                                        [Exp::Symbol("lambda".into()), args]
+                                        [Exp::Symbol(Symbol::LAMBDA), args]
                                             .iter()
                                             .cloned()
-                                            .chain(body.into_iter())
+                                            .chain(body)
                                             .collect();
-                                    let res: Vec<Exp> = [
-                                        Exp::Symbol("define".into()),
+                                    let res: Vec<Exp> = vec![
+                                        Exp::Symbol(Symbol::DEFINE),
                                         f,
                                         Exp::List(lambda_args_body),
-                                    ]
-                                    .iter()
-                                    .cloned()
-                                    .collect();
+                                    ];
                                     // println!("Sugar debug: {:?}", res);
                                     res
                                 }))
                             }
                             _ => {
-                                let res: Vec<Exp> =
-                                    list.iter().map(|x| desugar(x.clone()).unwrap()).collect();
+                                let res: Result<Vec<Exp>, ScmErr> =
+                                    list.into_iter().map(desugar).collect();
                                 // println!("Sugar debug: {:?}", res);
-                                Ok(Exp::List(res))
+                                Ok(Exp::List(res?))
                             }
                         }
                     }
 
-                    _ => Ok(exp),
+                    // Quoted data is left as is.
+                    Symbol::QUOTE => Ok(exp),
+
+                    // Internal definitions in lambda bodies are desugared as well.
+                    _ => {
+                        let res: Result<Vec<Exp>, ScmErr> = list.into_iter().map(desugar).collect();
+                        Ok(Exp::List(res?))
+                    }
                 },
 
                 _ => {
-                    let res: Vec<Exp> = list.iter().map(|x| desugar(x.clone()).unwrap()).collect();
-                    Ok(Exp::List(res))
+                    let res: Result<Vec<Exp>, ScmErr> = list.into_iter().map(desugar).collect();
+                    Ok(Exp::List(res?))
                 }
             }
         }
@@ -0,0 +1,225 @@
+use crate::printer::Doc;
+use crate::types::*;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// * Concrete syntax
+//
+// Unlike `InPort`, this reader keeps the source as it is written:
+// atoms keep their spelling, and comments and blank lines are kept as trivia.
+// It is used by the formatter, which lays the result out again.
+
+/// Read a source into its top-level forms, with their comments and blank lines.
+pub fn read_source(src: &str) -> Result<Vec<Doc>, ScmErr> {
+    let mut reader = Reader {
+        chars: src.chars().peekable(),
+        line: 1,
+    };
+    let items = reader.items()?;
+    match reader.chars.peek() {
+        None => Ok(items),
+        Some(_) => Err(reader.error("unexpected \")\"")),
+    }
+}
+
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()\";'`,".contains(c)
+}
+
+impl Reader<'_> {
+    fn error(&self, msg: &str) -> ScmErr {
+        ScmErr::from(&format!("fmt: {} at line {}", msg, self.line))
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// Skip whitespace, returning the number of line breaks skipped.
+    fn whitespace(&mut self) -> usize {
+        let mut lines = 0;
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            if c == '\n' {
+                lines += 1;
+            }
+            self.next();
+        }
+        lines
+    }
+
+    /// Read items up to a closing parenthesis or the end of the source, which are left unread.
+    fn items(&mut self) -> Result<Vec<Doc>, ScmErr> {
+        let mut items = Vec::new();
+        loop {
+            let lines = self.whitespace();
+            match self.chars.peek() {
+                None | Some(')') => return Ok(items),
+                _ => (),
+            }
+            if lines >= 2 && !items.is_empty() {
+                items.push(Doc::Blank);
+            }
+            // A comment is trailing if it's on the line of the previous item.
+            let trailing = lines == 0 && !items.is_empty();
+            items.push(match self.comment()? {
+                Some(text) => Doc::Comment { text, trailing },
+                None => self.datum(String::new())?,
+            });
+        }
+    }
+
+    /// Read a line or block comment, if there is one.
+    fn comment(&mut self) -> Result<Option<String>, ScmErr> {
+        let mut ahead = self.chars.clone();
+        match (ahead.next(), ahead.next()) {
+            (Some(';'), _) => {
+                let mut text = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    text.push(c);
+                    self.next();
+                }
+                Ok(Some(text.trim_end().into()))
+            }
+            (Some('#'), Some('|')) => {
+                let mut text = String::new();
+                loop {
+                    match self.next() {
+                        Some(c) => text.push(c),
+                        None => return Err(self.error("unterminated block comment")),
+                    }
+                    if text.len() > 2 && text.ends_with("|#") {
+                        return Ok(Some(text));
+                    }
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Read a datum, after the given prefixes like quotes.
+    fn datum(&mut self, mut prefix: String) -> Result<Doc, ScmErr> {
+        let c = match self.chars.peek() {
+            Some(&c) => c,
+            None => return Err(self.error("unexpected end of input")),
+        };
+        match c {
+            '\'' | '`' | ',' => {
+                prefix.push(c);
+                self.next();
+                if c == ',' && self.chars.peek() == Some(&'@') {
+                    prefix.push('@');
+                    self.next();
+                }
+                self.datum(prefix)
+            }
+            '(' => {
+                self.next();
+                self.list(prefix + "(")
+            }
+            '"' => {
+                prefix.push_str(&self.string()?);
+                Ok(Doc::Atom(prefix))
+            }
+            ')' => Err(self.error("unexpected \")\"")),
+            _ => {
+                let atom = self.atom();
+                match atom.as_str() {
+                    // Datum comments and vectors are prefixes as well.
+                    "#;" => {
+                        self.whitespace();
+                        self.datum(prefix + "#;")
+                    }
+                    "#" if self.chars.peek() == Some(&'(') => {
+                        self.next();
+                        self.list(prefix + "#(")
+                    }
+                    _ => Ok(Doc::Atom(prefix + &atom)),
+                }
+            }
+        }
+    }
+
+    fn list(&mut self, open: String) -> Result<Doc, ScmErr> {
+        let line = self.line;
+        let items = self.items()?;
+        match self.next() {
+            Some(')') => Ok(Doc::List {
+                open,
+                items,
+                close: ")".into(),
+            }),
+            _ => Err(ScmErr::from(&format!(
+                "fmt: unclosed \"(\" from line {}",
+                line
+            ))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ScmErr> {
+        let line = self.line;
+        let mut res = String::new();
+        res.extend(self.next());
+        loop {
+            match self.next() {
+                Some('\\') => {
+                    res.push('\\');
+                    res.extend(self.next());
+                }
+                Some('"') => {
+                    res.push('"');
+                    return Ok(res);
+                }
+                Some(c) => res.push(c),
+                None => {
+                    return Err(ScmErr::from(&format!(
+                        "fmt: unterminated string from line {}",
+                        line
+                    )))
+                }
+            }
+        }
+    }
+
+    fn atom(&mut self) -> String {
+        let mut res = String::new();
+        // The char after `#\` is part of the atom, even if it's a delimiter.
+        // `#;` is read on its own.
+        if self.chars.peek() == Some(&'#') {
+            res.extend(self.next());
+            match self.chars.peek() {
+                Some('\\') => {
+                    res.extend(self.next());
+                    res.extend(self.next());
+                }
+                Some(';') => {
+                    res.extend(self.next());
+                    return res;
+                }
+                _ => (),
+            }
+        }
+        while let Some(&c) = self.chars.peek() {
+            if is_delimiter(c) {
+                break;
+            }
+            res.push(c);
+            self.next();
+        }
+        res
+    }
+}
@@ -0,0 +1,157 @@
+use super::{desugar, InFile, InPort};
+use crate::types::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// * `include` and `include-ci`, following R7RS
+//
+// `(include "file" ...)` is replaced by `(begin ...)` with the forms read from the files,
+// before the top-level form holding it is evaluated.
+// The paths are resolved against the directory of the including file.
+// `include-ci` folds the case of the Symbols read, like `#!fold-case`.
+// A file which includes itself, directly or not, is an error.
+
+/// Where a form was read from: the path of its file, and the line of its first token.
+pub type Location = (PathBuf, usize);
+
+/// Splice in the files included by the form, resolving their paths against `dir`.
+pub fn expand_includes(exp: Exp, dir: &Path) -> Result<Exp, ScmErr> {
+    expand(exp, dir, &mut Vec::new())
+}
+
+/// Splice in the files included by a top-level form, keeping where each form was read from.
+/// The forms of a top-level `include` are given one by one, like those of a `begin`,
+/// so that an error in one of them can be reported at its own file and line.
+/// Other forms are given with no location.
+pub fn expand_located(exp: Exp, dir: &Path) -> Result<Vec<(Exp, Option<Location>)>, ScmErr> {
+    let mut active = Vec::new();
+    match included(&exp) {
+        Some((files, fold_case)) => Ok(include_files(files, fold_case, dir, &mut active)?
+            .into_iter()
+            .map(|(exp, at)| (exp, Some(at)))
+            .collect()),
+        None => Ok(vec![(expand(exp, dir, &mut active)?, None)]),
+    }
+}
+
+/// The files of an `include` or `include-ci` form, and whether to fold their case.
+fn included(exp: &Exp) -> Option<(&[Exp], bool)> {
+    match exp {
+        Exp::List(list) => match list.first() {
+            Some(&Exp::Symbol(Symbol::INCLUDE)) => Some((&list[1..], false)),
+            Some(&Exp::Symbol(Symbol::INCLUDE_CI)) => Some((&list[1..], true)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Splice in the included files, given the files being included already.
+fn expand(exp: Exp, dir: &Path, active: &mut Vec<PathBuf>) -> Result<Exp, ScmErr> {
+    if let Some((files, fold_case)) = included(&exp) {
+        let forms = include_files(files, fold_case, dir, active)?;
+        let begin = std::iter::once(Exp::Symbol(Symbol::BEGIN));
+        return Ok(Exp::List(
+            begin.chain(forms.into_iter().map(|(exp, _)| exp)).collect(),
+        ));
+    }
+    match exp {
+        Exp::List(list) => match list.first() {
+            // Quoted data is left as is.
+            Some(&Exp::Symbol(Symbol::QUOTE)) => Ok(Exp::List(list)),
+            _ => {
+                let res: Result<Vec<Exp>, ScmErr> = list
+                    .into_iter()
+                    .map(|exp| expand(exp, dir, active))
+                    .collect();
+                Ok(Exp::List(res?))
+            }
+        },
+        _ => Ok(exp),
+    }
+}
+
+/// Read the forms of the included files, with their locations.
+fn include_files(
+    files: &[Exp],
+    fold_case: bool,
+    dir: &Path,
+    active: &mut Vec<PathBuf>,
+) -> Result<Vec<(Exp, Location)>, ScmErr> {
+    let name = if fold_case { "include-ci" } else { "include" };
+    if files.is_empty() {
+        return Err(ScmErr::from(&format!("{}: expected file names", name)));
+    }
+    let mut res = Vec::new();
+    for file in files {
+        match file {
+            Exp::String(file) => {
+                let path = dir.join(file);
+                // The same file can be reached through different paths.
+                let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if active.contains(&key) {
+                    return Err(ScmErr::from(&format!(
+                        "{}: `{}` includes itself",
+                        name,
+                        path.display()
+                    )));
+                }
+                active.push(key);
+                let forms = read_file(&path, fold_case, active);
+                active.pop();
+                res.extend(forms?);
+            }
+            _ => {
+                return Err(ScmErr::from(&format!(
+                    "{}: expected a String, got {}",
+                    name, file
+                )))
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Read the forms of a file, with their own includes spliced in.
+/// The forms of a top-level `include` in the file keep their own locations.
+fn read_file(
+    path: &Path,
+    fold_case: bool,
+    active: &mut Vec<PathBuf>,
+) -> Result<Vec<(Exp, Location)>, ScmErr> {
+    let mut inport = InFile::new(&path.to_string_lossy())
+        .map_err(|e| ScmErr::from(&format!("`{}`: {}", path.display(), e)))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut res = Vec::new();
+    loop {
+        let token = match inport.next_token() {
+            Ok(None) => return Ok(res),
+            Ok(Some(token)) => token,
+            Err(e) => return Err(inport.locate(ScmErr::from(&format!("{}", e)))),
+        };
+        let line = inport.line_number();
+        let exp = inport
+            .read_ahead(&token)
+            .map(|exp| if fold_case { fold(exp) } else { exp })
+            .and_then(desugar)
+            .map_err(|e| inport.locate(e))?;
+        match included(&exp) {
+            Some((files, fold_case)) => {
+                let forms = include_files(files, fold_case, dir, active);
+                res.extend(forms.map_err(|e| inport.locate(e))?);
+            }
+            None => {
+                let exp = expand(exp, dir, active).map_err(|e| inport.locate(e))?;
+                res.push((exp, (path.into(), line)));
+            }
+        }
+    }
+}
+/// Fold the case of the Symbols in a datum.
+fn fold(exp: Exp) -> Exp {
+    match exp {
+        Exp::Symbol(s) => Exp::Symbol(Symbol::intern(&s.as_str().to_lowercase())),
+        Exp::List(list) => Exp::List(list.into_iter().map(fold).collect()),
+        _ => exp,
+    }
+}
@@ -4,9 +4,13 @@ use std::error::Error;
 
 pub mod infile;
 pub mod input;
+pub mod inscm;
+pub mod instr;
 
 pub use infile::InFile;
 pub use input::Input;
+pub use inscm::InScm;
+pub use instr::InStr;
 
 pub trait InPort {
     // * An input port/stream based on the implementation on http://norvig.com/lispy2.html
@@ -22,13 +26,10 @@ pub trait InPort {
 
     fn next_token(&mut self) -> Result<Option<String>, Box<dyn Error>> {
         loop {
-            if self.line() == None {
+            if self.line().is_none() {
                 return Ok(None);
             } else if self.line().unwrap().is_empty() {
-                self.set_line(match self.read_line() {
-                    Ok(x) => x,
-                    Err(e) => return Err(e),
-                });
+                self.set_line(self.read_line()?);
                 continue;
             } else {
                 let line = self.line().unwrap();
@@ -40,31 +41,84 @@ pub trait InPort {
                 self.set_line(Some(rest));
                 match token.chars().nth(0) {
                     Some(';') | None => (),
-                    _ => return Ok(Some(token.into())),
+                    _ => return Ok(Some(token)),
                 };
             }
         }
     }
 
     fn read_ahead(&mut self, token: &str) -> Result<Exp, ScmErr> {
+        self.read_datum(token, self.cons_lists())
+    }
+
+    /// Read an Exp starting from the given token.
+    /// In `data` mode, Lists are built with `cons`.
+    fn read_datum(&mut self, token: &str, data: bool) -> Result<Exp, ScmErr> {
         match token {
-            "(" => {
-                let mut l: Vec<Exp> = Vec::new();
-                loop {
-                    let next = self.next_token();
-                    match next {
-                        Ok(Some(t)) => match t.as_ref() {
-                            ")" => return Ok(Exp::List(l)),
-                            _ => l.push(self.read_ahead(&t)?),
-                        },
-                        Ok(None) => return Err(ScmErr::from("parser: Unexpected EOF")),
-                        Err(e) => return Err(ScmErr::from(&format!("{}", e))),
-                    }
-                }
-            }
+            "(" => self.read_list(data),
+            // Vector literals are self-evaluating, so their items are data.
+            "#(" => self.read_items(true).map(Exp::vector),
+            "." => Err(ScmErr::from("parser: unexpected \".\"")),
             ")" => Err(ScmErr::from("parser: Extra \")\" found")),
             // TODO: quote
-            _ => Ok(atom(token)),
+            _ => atom(token),
+        }
+    }
+
+    /// Whether proper Lists are built with `cons` like dotted ones, instead of being read as flat Lists.
+    /// Code is read as flat Lists, while data read by `read` or quoted in code is built with `cons`,
+    /// so that both kinds of data have the same structure.
+    fn cons_lists(&self) -> bool {
+        false
+    }
+
+    /// Read a List, which may be dotted like `(a b . c)`.
+    /// The argument of a `quote` form is read as data.
+    fn read_list(&mut self, data: bool) -> Result<Exp, ScmErr> {
+        let mut l: Vec<Exp> = Vec::new();
+        let mut quoted = data;
+        loop {
+            let next = self.next_token();
+            match next {
+                Ok(Some(t)) => match t.as_ref() {
+                    ")" if data => return Ok(cons_list(l, Exp::List(Vec::new()))),
+                    ")" => return Ok(Exp::List(l)),
+                    "." if !l.is_empty() => break,
+                    _ => {
+                        l.push(self.read_datum(&t, quoted)?);
+                        quoted |= matches!(l[..], [Exp::Symbol(Symbol::QUOTE)]);
+                    }
+                },
+                Ok(None) => return Err(ScmErr::from("parser: Unexpected EOF")),
+                Err(e) => return Err(ScmErr::from(&format!("{}", e))),
+            }
+        }
+        let tail = match self.next_token() {
+            Ok(Some(t)) => self.read_datum(&t, quoted)?,
+            Ok(None) => return Err(ScmErr::from("parser: Unexpected EOF")),
+            Err(e) => return Err(ScmErr::from(&format!("{}", e))),
+        };
+        match self.next_token() {
+            Ok(Some(t)) if t == ")" => Ok(cons_list(l, tail)),
+            _ => Err(ScmErr::from(
+                "parser: expected \")\" after the tail of a dotted List",
+            )),
+        }
+    }
+
+    /// Read the items of a List up to the closing ")".
+    fn read_items(&mut self, data: bool) -> Result<Vec<Exp>, ScmErr> {
+        let mut l: Vec<Exp> = Vec::new();
+        loop {
+            let next = self.next_token();
+            match next {
+                Ok(Some(t)) => match t.as_ref() {
+                    ")" => return Ok(l),
+                    _ => l.push(self.read_datum(&t, data)?),
+                },
+                Ok(None) => return Err(ScmErr::from("parser: Unexpected EOF")),
+                Err(e) => return Err(ScmErr::from(&format!("{}", e))),
+            }
         }
     }
 
@@ -83,8 +137,17 @@ pub trait InPort {
     }
 
     /// Read an Exp starting from the next token.
+    #[allow(dead_code)]
     fn read_next_exp(&mut self) -> Result<Exp, ScmErr> {
         let next = self.next_token();
         self.read_exp(next)
     }
 }
+
+/// Build a List with `cons`, ending with the given tail.
+fn cons_list(items: Vec<Exp>, tail: Exp) -> Exp {
+    items
+        .into_iter()
+        .rev()
+        .fold(tail, |list, item| Exp::List(vec![item, list]))
+}
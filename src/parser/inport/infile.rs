@@ -1,28 +1,48 @@
 use super::InPort;
-use std::cell::RefCell;
+use crate::parser::is_shebang;
+use crate::types::{ErrKind, ScmErr};
+use std::cell::{Cell, RefCell};
 use std::error::Error;
 use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
 
 pub struct InFile {
     pub file_str: String,
     line: Option<String>,
+    /// The number of lines read so far.
+    line_number: Cell<usize>,
     reader: RefCell<BufReader<File>>,
 }
 
 impl InFile {
-    pub fn new(file_str: &str) -> Self {
-        InFile {
+    pub fn new(file_str: &str) -> io::Result<Self> {
+        Ok(InFile {
             file_str: file_str.into(),
             line: Some("".into()),
+            line_number: Cell::new(0),
             reader: {
-                let file = OpenOptions::new()
-                    .read(true)
-                    .write(false)
-                    .open(file_str)
-                    .unwrap();
+                let file = OpenOptions::new().read(true).write(false).open(file_str)?;
                 RefCell::new(BufReader::new(file))
             },
+        })
+    }
+
+    /// The number of the line being read, starting from 1.
+    pub fn line_number(&self) -> usize {
+        self.line_number.get()
+    }
+
+    /// Prefix an error with the path and the current line of the file.
+    /// Only ordinary errors are changed, so that `exit` and the like go through.
+    pub fn locate(&self, e: ScmErr) -> ScmErr {
+        match e.kind() {
+            ErrKind::Error => ScmErr::from(&format!(
+                "`{}`:{}: {}",
+                self.file_str,
+                self.line_number(),
+                e
+            )),
+            _ => e,
         }
     }
 }
@@ -40,7 +60,13 @@ impl InPort for InFile {
         let mut line = String::new();
         match self.reader.borrow_mut().read_line(&mut line) {
             Ok(0) => Ok(None),
-            Ok(_) => Ok(Some(line)),
+            Ok(_) => {
+                self.line_number.set(self.line_number.get() + 1);
+                if self.line_number.get() == 1 && is_shebang(&line) {
+                    line = "\n".into();
+                }
+                Ok(Some(line))
+            }
             Err(e) => Err(Box::new(e)),
         }
     }
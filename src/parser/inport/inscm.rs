@@ -0,0 +1,67 @@
+use super::InPort;
+use crate::types::*;
+use std::cell::Cell;
+use std::error::Error;
+
+/// An input port reading data from a Scheme input port, for `read`.
+/// The chars which are left on the current line are given back to the port by `finish`.
+pub struct InScm<'a> {
+    port: &'a Port,
+    line: Option<String>,
+    /// Whether the current line ended with a newline.
+    newline: Cell<bool>,
+}
+
+impl<'a> InScm<'a> {
+    pub fn new(port: &'a Port) -> Self {
+        InScm {
+            port,
+            line: Some("".into()),
+            newline: Cell::new(false),
+        }
+    }
+
+    /// Give back the unread chars to the port.
+    pub fn finish(self) -> Result<(), ScmErr> {
+        match self.line {
+            Some(mut rest) => {
+                if self.newline.get() {
+                    rest.push('\n');
+                }
+                self.port.unread(&rest)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl InPort for InScm<'_> {
+    fn line(&self) -> Option<String> {
+        self.line.clone()
+    }
+
+    fn set_line(&mut self, new_line: Option<String>) {
+        self.line = new_line;
+    }
+
+    fn cons_lists(&self) -> bool {
+        true
+    }
+
+    fn read_line(&self) -> Result<Option<String>, Box<dyn Error>> {
+        let mut line = String::new();
+        loop {
+            match self.port.read_char()? {
+                Some('\n') => {
+                    self.newline.set(true);
+                    return Ok(Some(line));
+                }
+                Some(c) => line.push(c),
+                None if line.is_empty() => return Ok(None),
+                None => break,
+            }
+        }
+        self.newline.set(false);
+        Ok(Some(line))
+    }
+}
@@ -0,0 +1,38 @@
+use super::InPort;
+use crate::parser::is_shebang;
+use std::cell::RefCell;
+use std::error::Error;
+use std::vec::IntoIter;
+
+/// An input port reading from a string.
+pub struct InStr {
+    line: Option<String>,
+    lines: RefCell<IntoIter<String>>,
+}
+
+impl InStr {
+    pub fn new(src: &str) -> Self {
+        let mut lines: Vec<String> = src.lines().map(String::from).collect();
+        if lines.first().is_some_and(|line| is_shebang(line)) {
+            lines[0].clear();
+        }
+        InStr {
+            line: Some("".into()),
+            lines: RefCell::new(lines.into_iter()),
+        }
+    }
+}
+
+impl InPort for InStr {
+    fn line(&self) -> Option<String> {
+        self.line.clone()
+    }
+
+    fn set_line(&mut self, new_line: Option<String>) {
+        self.line = new_line;
+    }
+
+    fn read_line(&self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.lines.borrow_mut().next())
+    }
+}
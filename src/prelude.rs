@@ -1,6 +1,22 @@
+use crate::convert::{list_to_vec, vec_to_list};
+use crate::gc;
+use crate::interp::Interp;
+use crate::printer::list_parts;
+use crate::run;
 pub use crate::types::make_env_ptr;
 use crate::types::*;
+use std::env;
+use std::io::{self, Write};
+use std::mem;
 use std::process;
+use std::rc::Rc;
+
+mod collections;
+mod json;
+mod ports;
+use collections::*;
+use json::*;
+use ports::*;
 
 // * Primitive operators
 
@@ -8,7 +24,7 @@ use std::process;
 // in order to fit into Exp::Primitive(fn(&[Exp]) -> Option<Exp>)
 
 fn add(args: &[Exp]) -> Result<Exp, ScmErr> {
-    let mut res = 0 as f64;
+    let mut res = 0.0;
     for arg in args {
         if let &Exp::Number(x) = arg {
             res += x;
@@ -27,7 +43,7 @@ fn sub(pair: &[Exp]) -> Result<Exp, ScmErr> {
 }
 
 fn mul(args: &[Exp]) -> Result<Exp, ScmErr> {
-    let mut res = 1 as f64;
+    let mut res = 1.0;
     for arg in args {
         if let &Exp::Number(x) = arg {
             res *= x;
@@ -84,9 +100,9 @@ fn car(args: &[Exp]) -> Result<Exp, ScmErr> {
     if args.len() != 1 {
         return Err(ScmErr::from("car: nothing to car"));
     }
-    let pair = args.get(0).unwrap();
+    let pair = args.first().unwrap();
     match pair {
-        Exp::List(list) => match list.get(0) {
+        Exp::List(list) => match list.first() {
             Some(res) => Ok(res.clone()),
             None => Err(ScmErr::from("car: expected a List of length 2")),
         },
@@ -98,7 +114,7 @@ fn cdr(args: &[Exp]) -> Result<Exp, ScmErr> {
     if args.len() != 1 {
         return Err(ScmErr::from("cdr: nothing to cdr"));
     }
-    let pair = args.get(0).unwrap();
+    let pair = args.first().unwrap();
     match pair {
         Exp::List(list) => match list.get(1) {
             Some(res) => Ok(res.clone()),
@@ -110,7 +126,7 @@ fn cdr(args: &[Exp]) -> Result<Exp, ScmErr> {
 
 fn cons(pair: &[Exp]) -> Result<Exp, ScmErr> {
     match pair {
-        [a, b] => Ok(Exp::List([a, b].iter().cloned().cloned().collect())),
+        [a, b] => Ok(Exp::List(vec![a.clone(), b.clone()])),
         _ => Err(ScmErr::from("cons: expected two Exp to cons")),
     }
 }
@@ -119,7 +135,7 @@ fn is_null(args: &[Exp]) -> Result<Exp, ScmErr> {
     if args.is_empty() {
         return Err(ScmErr::from("null?: nothing to check"));
     }
-    match args.get(0) {
+    match args.first() {
         Some(Exp::List(list)) => match list.len() {
             0 => Ok(Exp::Bool(true)),
             _ => Ok(Exp::Bool(false)),
@@ -128,39 +144,214 @@ fn is_null(args: &[Exp]) -> Result<Exp, ScmErr> {
     }
 }
 
-fn display(args: &[Exp]) -> Result<Exp, ScmErr> {
-    if args.len() != 1 {
-        return Err(ScmErr::from("display: nothing to display"));
+fn gc(args: &[Exp]) -> Result<Exp, ScmErr> {
+    if !args.is_empty() {
+        return Err(ScmErr::from("gc: too many arguments"));
     }
-    let res = args.first().unwrap();
-    print!("{:?}", *res);
-    Ok(Exp::Empty)
+    Ok(Exp::Number(gc::collect() as f64))
 }
 
-fn newline(args: &[Exp]) -> Result<Exp, ScmErr> {
+fn heap_stats(args: &[Exp]) -> Result<Exp, ScmErr> {
     if !args.is_empty() {
-        return Err(ScmErr::from("newline: too many arguments"));
+        return Err(ScmErr::from("heap-stats: too many arguments"));
+    }
+    let stats = gc::stats();
+    let entry = |key: &str, val: usize| {
+        Exp::List(vec![
+            Exp::Symbol(Symbol::intern(key)),
+            Exp::Number(val as f64),
+        ])
+    };
+    Ok(vec_to_list(vec![
+        entry("live", stats.live),
+        entry("peak", stats.peak),
+        entry("allocated", stats.allocated),
+        entry("freed", stats.freed),
+        entry("collections", stats.collections),
+    ]))
+}
+
+fn is_eq(pair: &[Exp]) -> Result<Exp, ScmErr> {
+    match pair {
+        [a, b] => Ok(Exp::Bool(match (a, b) {
+            (Exp::Symbol(a), Exp::Symbol(b)) => a == b,
+            (Exp::Number(a), Exp::Number(b)) => a == b,
+            (Exp::Bool(a), Exp::Bool(b)) => a == b,
+            (Exp::Char(a), Exp::Char(b)) => a == b,
+            (Exp::List(a), Exp::List(b)) => a.is_empty() && b.is_empty(),
+            (Exp::Vector(a), Exp::Vector(b)) => Rc::ptr_eq(a, b),
+            (Exp::HashTable(a), Exp::HashTable(b)) => Rc::ptr_eq(a, b),
+            (Exp::Port(a), Exp::Port(b)) => Rc::ptr_eq(a, b),
+            (Exp::Environment(a), Exp::Environment(b)) => Rc::ptr_eq(a, b),
+            (Exp::Eof, Exp::Eof) | (Exp::Empty, Exp::Empty) => true,
+            _ => false,
+        })),
+        _ => Err(ScmErr::from("eq?: expected two Exp to compare")),
+    }
+}
+
+/// Structural equality, following the cons structure of Lists.
+fn equal(a: &Exp, b: &Exp) -> bool {
+    match (a, b) {
+        (Exp::List(_), Exp::List(_)) => {
+            let ((a_items, a_tail), (b_items, b_tail)) = (list_parts(a), list_parts(b));
+            a_items.len() == b_items.len()
+                && a_items.iter().zip(b_items).all(|(a, b)| equal(a, b))
+                && match (a_tail, b_tail) {
+                    (None, None) => true,
+                    (Some(a), Some(b)) => equal(a, b),
+                    _ => false,
+                }
+        }
+        (Exp::Vector(a), Exp::Vector(b)) => {
+            Rc::ptr_eq(a, b) || {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b))
+            }
+        }
+        (Exp::String(a), Exp::String(b)) => a == b,
+        _ => matches!(is_eq(&[a.clone(), b.clone()]), Ok(Exp::Bool(true))),
+    }
+}
+
+fn is_equal(pair: &[Exp]) -> Result<Exp, ScmErr> {
+    match pair {
+        [a, b] => Ok(Exp::Bool(equal(a, b))),
+        _ => Err(ScmErr::from("equal?: expected two Exp to compare")),
     }
-    println!();
-    Ok(Exp::Empty)
 }
 
+/// Get the exit code of `exit` and `emergency-exit`, following R7RS.
+fn exit_code(args: &[Exp], name: &str) -> Result<i32, ScmErr> {
+    match args {
+        [] | [Exp::Bool(true)] => Ok(0),
+        [Exp::Bool(false)] => Ok(1),
+        [Exp::Number(n)] => Ok(*n as i32),
+        _ => Err(ScmErr::from(&format!("{}: invalid exit code", name))),
+    }
+}
+
+/// Exiting unwinds the evaluation up to the REPL or the host, running the `dynamic-wind` after thunks.
 fn exit(args: &[Exp]) -> Result<Exp, ScmErr> {
-    let mut exit_code: i32 = 0;
+    Err(ScmErr::exit(exit_code(args, "exit")?))
+}
+
+/// Exit the process right away, without unwinding.
+fn emergency_exit(args: &[Exp]) -> Result<Exp, ScmErr> {
+    let code = exit_code(args, "emergency-exit")?;
+    let _ = io::stdout().flush();
+    process::exit(code);
+}
+
+/// `(command-line)` gives the command name or the script, followed by its arguments.
+fn command_line(_args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let args = interp.command_line.iter().cloned().map(Exp::String);
+    Ok(vec_to_list(args.collect()))
+}
+
+fn get_environment_variable(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::String(name)] => Ok(env::var(name).map_or(Exp::Bool(false), Exp::String)),
+        _ => Err(ScmErr::from("get-environment-variable: expected a String")),
+    }
+}
+
+/// The environment variables, as an association List of Strings.
+/// Those which aren't valid Unicode are left out.
+fn get_environment_variables(args: &[Exp]) -> Result<Exp, ScmErr> {
     if !args.is_empty() {
-        exit_code = match args[0] {
-            Exp::Number(n) => n as i32,
-            _ => return Err(ScmErr::from("exit: invalid exit code")),
-        };
+        return Err(ScmErr::from(
+            "get-environment-variables: expected no arguments",
+        ));
     }
-    process::exit(exit_code);
+    let vars = env::vars_os().filter_map(|(name, value)| {
+        let pair = vec![
+            Exp::String(name.into_string().ok()?),
+            Exp::String(value.into_string().ok()?),
+        ];
+        Some(Exp::List(pair))
+    });
+    Ok(vec_to_list(vars.collect()))
+}
+
+/// `(load filename [environment])` evaluates a source file, by default in the global Environment.
+/// A relative path is resolved against the file being loaded, if any.
+fn load(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let path = match &args[0] {
+        Exp::String(path) => interp.dir().join(path),
+        _ => return Err(ScmErr::from("load: expected a String")),
+    };
+    let env = match args.get(1) {
+        None => return run::load_file(&path, interp),
+        Some(Exp::Environment(env)) => Rc::clone(env),
+        Some(_) => return Err(ScmErr::from("load: expected an Environment")),
+    };
+    let global = mem::replace(&mut interp.env, env);
+    let res = run::load_file(&path, interp);
+    interp.env = global;
+    res
+}
+
+fn interaction_environment(_args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    Ok(Exp::Environment(Rc::clone(&interp.env)))
+}
+
+/// `(dynamic-wind before thunk after)` calls `after` even if `thunk` fails or exits.
+fn dynamic_wind(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    interp.apply(&args[0], &[])?;
+    let res = interp.apply(&args[1], &[]);
+    interp.apply(&args[2], &[])?;
+    res
 }
 
 // * Prelude
 
+// * Native functions
+
+// Native functions get access to the interpreter, so they can call back into Scheme.
+
+fn apply(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    // (apply f arg1 ... args)
+    let (func, func_args) = args.split_first().unwrap();
+    let (list, func_args) = func_args.split_last().unwrap();
+    let mut func_args = func_args.to_vec();
+    func_args.extend(list_to_vec(list).map_err(|e| ScmErr::from(&format!("apply: {}", e)))?);
+    interp.apply(func, &func_args)
+}
+
+fn sort(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    /// A stable merge sort, since the comparator may fail.
+    fn merge_sort(
+        mut items: Vec<Exp>,
+        less: &Exp,
+        interp: &mut Interp,
+    ) -> Result<Vec<Exp>, ScmErr> {
+        if items.len() <= 1 {
+            return Ok(items);
+        }
+        let right = items.split_off(items.len() / 2);
+        let mut left = merge_sort(items, less, interp)?.into_iter().peekable();
+        let mut right = merge_sort(right, less, interp)?.into_iter().peekable();
+        let mut res = Vec::with_capacity(left.len() + right.len());
+        while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+            // Only take from the right when it's strictly less.
+            match interp.apply(less, &[b.clone(), a.clone()])? {
+                Exp::Bool(true) => res.extend(right.next()),
+                Exp::Bool(false) => res.extend(left.next()),
+                _ => return Err(ScmErr::from("sort: expected Exp::Bool")),
+            }
+        }
+        res.extend(left.chain(right));
+        Ok(res)
+    }
+
+    let items = list_to_vec(&args[0]).map_err(|e| ScmErr::from(&format!("sort: {}", e)))?;
+    Ok(vec_to_list(merge_sort(items, &args[1], interp)?))
+}
+
 pub fn get_prelude() -> Env {
-    let mut res = Env::from_outer(None);
-    res.data = [
+    let mut res = Env::new();
+    [
         ("+", Exp::Primitive(add)),
         ("-", Exp::Primitive(sub)),
         ("*", Exp::Primitive(mul)),
@@ -174,16 +365,338 @@ pub fn get_prelude() -> Env {
         ("cdr", Exp::Primitive(cdr)),
         ("cons", Exp::Primitive(cons)),
         ("null?", Exp::Primitive(is_null)),
-        ("display", Exp::Primitive(display)),
-        ("newline", Exp::Primitive(newline)),
+        ("eq?", Exp::Primitive(is_eq)),
+        ("equal?", Exp::Primitive(is_equal)),
+        (
+            "display",
+            Exp::native("display", Arity::Between(1, 2), display),
+        ),
+        ("write", Exp::native("write", Arity::Between(1, 2), write)),
+        (
+            "write-shared",
+            Exp::native("write-shared", Arity::Between(1, 2), write_shared),
+        ),
+        (
+            "pretty-print",
+            Exp::native("pretty-print", Arity::Between(1, 2), pretty_print),
+        ),
+        (
+            "write-simple",
+            Exp::native("write-simple", Arity::Between(1, 2), write_simple),
+        ),
+        (
+            "newline",
+            Exp::native("newline", Arity::Between(0, 1), newline),
+        ),
+        (
+            "write-string",
+            Exp::native("write-string", Arity::Between(1, 2), write_string),
+        ),
+        (
+            "flush-output-port",
+            Exp::native("flush-output-port", Arity::Between(0, 1), flush_output_port),
+        ),
+        (
+            "current-input-port",
+            Exp::native(
+                "current-input-port",
+                Arity::Between(0, 1),
+                current_input_port,
+            ),
+        ),
+        (
+            "current-output-port",
+            Exp::native(
+                "current-output-port",
+                Arity::Between(0, 1),
+                current_output_port,
+            ),
+        ),
+        (
+            "current-error-port",
+            Exp::native(
+                "current-error-port",
+                Arity::Between(0, 1),
+                current_error_port,
+            ),
+        ),
+        ("open-input-file", Exp::Primitive(open_input_file)),
+        ("open-output-file", Exp::Primitive(open_output_file)),
+        ("open-input-string", Exp::Primitive(open_input_string)),
+        ("open-output-string", Exp::Primitive(open_output_string)),
+        ("get-output-string", Exp::Primitive(get_output_string)),
+        (
+            "open-input-bytevector",
+            Exp::Primitive(open_input_bytevector),
+        ),
+        (
+            "open-output-bytevector",
+            Exp::Primitive(open_output_bytevector),
+        ),
+        (
+            "get-output-bytevector",
+            Exp::Primitive(get_output_bytevector),
+        ),
+        ("close-port", Exp::Primitive(close_port)),
+        ("close-input-port", Exp::Primitive(close_port)),
+        ("close-output-port", Exp::Primitive(close_port)),
+        ("read", Exp::native("read", Arity::Between(0, 1), read)),
+        (
+            "read-char",
+            Exp::native("read-char", Arity::Between(0, 1), read_char),
+        ),
+        (
+            "peek-char",
+            Exp::native("peek-char", Arity::Between(0, 1), peek_char),
+        ),
+        (
+            "read-line",
+            Exp::native("read-line", Arity::Between(0, 1), read_line),
+        ),
+        (
+            "read-string",
+            Exp::native("read-string", Arity::Between(1, 2), read_string),
+        ),
+        (
+            "char-ready?",
+            Exp::native("char-ready?", Arity::Between(0, 1), is_char_ready),
+        ),
+        ("eof-object", Exp::Primitive(eof_object)),
+        ("eof-object?", Exp::Primitive(is_eof_object)),
+        ("port?", Exp::Primitive(is_port)),
+        ("input-port?", Exp::Primitive(is_input_port)),
+        ("output-port?", Exp::Primitive(is_output_port)),
+        (
+            "call-with-output-string",
+            Exp::native(
+                "call-with-output-string",
+                Arity::Exactly(1),
+                call_with_output_string,
+            ),
+        ),
+        (
+            "with-output-to-file",
+            Exp::native(
+                "with-output-to-file",
+                Arity::Exactly(2),
+                with_output_to_file,
+            ),
+        ),
         ("exit", Exp::Primitive(exit)),
+        ("emergency-exit", Exp::Primitive(emergency_exit)),
+        (
+            "command-line",
+            Exp::native("command-line", Arity::Exactly(0), command_line),
+        ),
+        (
+            "get-environment-variable",
+            Exp::Primitive(get_environment_variable),
+        ),
+        (
+            "get-environment-variables",
+            Exp::Primitive(get_environment_variables),
+        ),
+        ("load", Exp::native("load", Arity::Between(1, 2), load)),
+        (
+            "interaction-environment",
+            Exp::native(
+                "interaction-environment",
+                Arity::Exactly(0),
+                interaction_environment,
+            ),
+        ),
+        (
+            "dynamic-wind",
+            Exp::native("dynamic-wind", Arity::Exactly(3), dynamic_wind),
+        ),
+        ("gc", Exp::Primitive(gc)),
+        ("heap-stats", Exp::Primitive(heap_stats)),
+        ("apply", Exp::native("apply", Arity::AtLeast(2), apply)),
+        ("sort", Exp::native("sort", Arity::Exactly(2), sort)),
+        ("vector", Exp::Primitive(vector)),
+        ("make-vector", Exp::Primitive(make_vector)),
+        ("vector?", Exp::Primitive(is_vector)),
+        ("vector-length", Exp::Primitive(vector_length)),
+        ("vector-ref", Exp::Primitive(vector_ref)),
+        ("vector-set!", Exp::Primitive(vector_set)),
+        ("vector->list", Exp::Primitive(vector_to_list)),
+        ("list->vector", Exp::Primitive(list_to_vector)),
+        ("make-hash-table", Exp::Primitive(make_hash_table)),
+        ("hash-table?", Exp::Primitive(is_hash_table)),
+        ("hash-table-set!", Exp::Primitive(hash_table_set)),
+        (
+            "hash-table-ref",
+            Exp::native("hash-table-ref", Arity::Between(2, 3), hash_table_ref),
+        ),
+        (
+            "hash-table-ref/default",
+            Exp::Primitive(hash_table_ref_default),
+        ),
+        ("hash-table-delete!", Exp::Primitive(hash_table_delete)),
+        ("hash-table-contains?", Exp::Primitive(hash_table_contains)),
+        ("hash-table-count", Exp::Primitive(hash_table_count)),
+        ("hash-table-keys", Exp::Primitive(hash_table_keys)),
+        ("hash-table-values", Exp::Primitive(hash_table_values)),
+        ("hash-table->alist", Exp::Primitive(hash_table_to_alist)),
+        (
+            "json-read",
+            Exp::native("json-read", Arity::Between(0, 2), json_read),
+        ),
+        (
+            "json-write",
+            Exp::native("json-write", Arity::Between(1, 2), json_write),
+        ),
+        ("string->json", Exp::Primitive(string_to_json)),
+        ("json->string", Exp::Primitive(json_to_string)),
+        ("json-null?", Exp::Primitive(is_json_null)),
         ("#t", Exp::Bool(true)),
         ("#f", Exp::Bool(false)),
         ("null", Exp::List(Vec::new())),
     ]
     .iter()
-    .map(|(key, val)| (key.to_string(), val.clone()))
-    .collect();
+    .for_each(|(key, val)| res.define(Symbol::intern(key), val.clone()));
+
+    res
+}
+
+/// The primitives which give access to the host, left out of the restricted Prelude.
+const UNRESTRICTED: &[&str] = &[
+    "exit",
+    "emergency-exit",
+    "command-line",
+    "get-environment-variable",
+    "get-environment-variables",
+    "load",
+    "open-input-file",
+    "open-output-file",
+    "with-output-to-file",
+];
 
+/// Get a Prelude for untrusted code, without the primitives which give access to the host.
+pub fn get_restricted_prelude() -> Env {
+    let mut res = get_prelude();
+    UNRESTRICTED
+        .iter()
+        .for_each(|name| res.undefine(Symbol::intern(name)));
     res
 }
+
+/// The standard libraries, with the Prelude definitions they export.
+/// The Prelude itself is the global Environment of the REPL, where every library has been imported.
+pub const LIBRARIES: &[(&str, &[&str])] = &[
+    (
+        "scheme base",
+        &[
+            "+",
+            "-",
+            "*",
+            "/",
+            "=",
+            "<",
+            "<=",
+            ">",
+            ">=",
+            "car",
+            "cdr",
+            "cons",
+            "null?",
+            "eq?",
+            "equal?",
+            "apply",
+            "dynamic-wind",
+            "newline",
+            "write-string",
+            "flush-output-port",
+            "current-input-port",
+            "current-output-port",
+            "current-error-port",
+            "open-input-string",
+            "open-output-string",
+            "get-output-string",
+            "open-input-bytevector",
+            "open-output-bytevector",
+            "get-output-bytevector",
+            "close-port",
+            "close-input-port",
+            "close-output-port",
+            "read-char",
+            "peek-char",
+            "read-line",
+            "read-string",
+            "char-ready?",
+            "eof-object",
+            "eof-object?",
+            "port?",
+            "input-port?",
+            "output-port?",
+            "vector",
+            "make-vector",
+            "vector?",
+            "vector-length",
+            "vector-ref",
+            "vector-set!",
+            "vector->list",
+            "list->vector",
+            "#t",
+            "#f",
+            "null",
+        ],
+    ),
+    (
+        "scheme write",
+        &["display", "write", "write-shared", "write-simple"],
+    ),
+    ("scheme read", &["read"]),
+    (
+        "scheme file",
+        &["open-input-file", "open-output-file", "with-output-to-file"],
+    ),
+    (
+        "scheme process-context",
+        &[
+            "exit",
+            "emergency-exit",
+            "command-line",
+            "get-environment-variable",
+            "get-environment-variables",
+        ],
+    ),
+    ("scheme load", &["load"]),
+    ("scheme repl", &["interaction-environment"]),
+    (
+        "rusk hash-table",
+        &[
+            "make-hash-table",
+            "hash-table?",
+            "hash-table-set!",
+            "hash-table-ref",
+            "hash-table-ref/default",
+            "hash-table-delete!",
+            "hash-table-contains?",
+            "hash-table-count",
+            "hash-table-keys",
+            "hash-table-values",
+            "hash-table->alist",
+        ],
+    ),
+    (
+        "rusk json",
+        &[
+            "json-read",
+            "json-write",
+            "string->json",
+            "json->string",
+            "json-null?",
+        ],
+    ),
+    (
+        "rusk",
+        &[
+            "sort",
+            "pretty-print",
+            "call-with-output-string",
+            "gc",
+            "heap-stats",
+        ],
+    ),
+];
@@ -0,0 +1,198 @@
+use crate::convert::{list_to_vec, vec_to_list};
+use crate::interp::Interp;
+use crate::types::*;
+use std::collections::BTreeMap;
+
+// * Vectors and hash tables
+
+/// Check that an Exp is a valid index into a collection of the given length.
+fn index(exp: &Exp, len: usize, name: &str) -> Result<usize, ScmErr> {
+    match *exp {
+        Exp::Number(n) if n >= 0.0 && n.fract() == 0.0 && (n as usize) < len => Ok(n as usize),
+        Exp::Number(n) => Err(ScmErr::from(&format!("{}: index {} out of range", name, n))),
+        _ => Err(ScmErr::from(&format!("{}: expected Exp::Number", name))),
+    }
+}
+
+pub fn vector(args: &[Exp]) -> Result<Exp, ScmErr> {
+    Ok(Exp::vector(args.to_vec()))
+}
+
+pub fn make_vector(args: &[Exp]) -> Result<Exp, ScmErr> {
+    let (len, fill) = match args {
+        [Exp::Number(n)] => (*n, Exp::Bool(false)),
+        [Exp::Number(n), fill] => (*n, fill.clone()),
+        _ => return Err(ScmErr::from("make-vector: expected a length and a fill")),
+    };
+    if len < 0.0 || len.fract() != 0.0 {
+        return Err(ScmErr::from("make-vector: invalid length"));
+    }
+    Ok(Exp::vector(vec![fill; len as usize]))
+}
+
+pub fn is_vector(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [exp] => Ok(Exp::Bool(matches!(exp, Exp::Vector(_)))),
+        _ => Err(ScmErr::from("vector?: expected one Exp to check")),
+    }
+}
+
+pub fn vector_length(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Vector(v)] => Ok(Exp::Number(v.borrow().len() as f64)),
+        _ => Err(ScmErr::from("vector-length: expected a Vector")),
+    }
+}
+
+pub fn vector_ref(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Vector(v), i] => {
+            let v = v.borrow();
+            Ok(v[index(i, v.len(), "vector-ref")?].clone())
+        }
+        _ => Err(ScmErr::from("vector-ref: expected a Vector and an index")),
+    }
+}
+
+pub fn vector_set(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Vector(v), i, val] => {
+            let mut v = v.borrow_mut();
+            let i = index(i, v.len(), "vector-set!")?;
+            v[i] = val.clone();
+            Ok(Exp::Empty)
+        }
+        _ => Err(ScmErr::from(
+            "vector-set!: expected a Vector, an index and a value",
+        )),
+    }
+}
+
+pub fn vector_to_list(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Vector(v)] => Ok(vec_to_list(v.borrow().clone())),
+        _ => Err(ScmErr::from("vector->list: expected a Vector")),
+    }
+}
+
+pub fn list_to_vector(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [list] => list_to_vec(list)
+            .map(Exp::vector)
+            .map_err(|e| ScmErr::from(&format!("list->vector: {}", e))),
+        _ => Err(ScmErr::from("list->vector: expected a List")),
+    }
+}
+
+pub fn make_hash_table(args: &[Exp]) -> Result<Exp, ScmErr> {
+    if !args.is_empty() {
+        return Err(ScmErr::from("make-hash-table: too many arguments"));
+    }
+    Ok(Exp::hash_table(BTreeMap::new()))
+}
+
+pub fn is_hash_table(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [exp] => Ok(Exp::Bool(matches!(exp, Exp::HashTable(_)))),
+        _ => Err(ScmErr::from("hash-table?: expected one Exp to check")),
+    }
+}
+
+pub fn hash_table_set(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t), key, val] => {
+            t.borrow_mut().insert(HashKey::from_exp(key)?, val.clone());
+            Ok(Exp::Empty)
+        }
+        _ => Err(ScmErr::from(
+            "hash-table-set!: expected a hash table, a key and a value",
+        )),
+    }
+}
+
+/// `(hash-table-ref table key [failure])` calls `failure` if the key is missing.
+pub fn hash_table_ref(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let found = match &args[..2] {
+        [Exp::HashTable(t), key] => t.borrow().get(&HashKey::from_exp(key)?).cloned(),
+        _ => return Err(ScmErr::from("hash-table-ref: expected a hash table")),
+    };
+    match (found, args.get(2)) {
+        (Some(val), _) => Ok(val),
+        (None, Some(failure)) => interp.apply(failure, &[]),
+        (None, None) => Err(ScmErr::from(&format!(
+            "hash-table-ref: key {} not found",
+            args[1]
+        ))),
+    }
+}
+
+pub fn hash_table_ref_default(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t), key, default] => Ok(t
+            .borrow()
+            .get(&HashKey::from_exp(key)?)
+            .cloned()
+            .unwrap_or_else(|| default.clone())),
+        _ => Err(ScmErr::from(
+            "hash-table-ref/default: expected a hash table, a key and a default",
+        )),
+    }
+}
+
+pub fn hash_table_delete(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t), key] => {
+            t.borrow_mut().remove(&HashKey::from_exp(key)?);
+            Ok(Exp::Empty)
+        }
+        _ => Err(ScmErr::from(
+            "hash-table-delete!: expected a hash table and a key",
+        )),
+    }
+}
+
+pub fn hash_table_contains(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t), key] => {
+            Ok(Exp::Bool(t.borrow().contains_key(&HashKey::from_exp(key)?)))
+        }
+        _ => Err(ScmErr::from(
+            "hash-table-contains?: expected a hash table and a key",
+        )),
+    }
+}
+
+pub fn hash_table_count(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t)] => Ok(Exp::Number(t.borrow().len() as f64)),
+        _ => Err(ScmErr::from("hash-table-count: expected a hash table")),
+    }
+}
+
+pub fn hash_table_keys(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t)] => Ok(vec_to_list(
+            t.borrow().keys().map(HashKey::to_exp).collect(),
+        )),
+        _ => Err(ScmErr::from("hash-table-keys: expected a hash table")),
+    }
+}
+
+pub fn hash_table_values(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t)] => Ok(vec_to_list(t.borrow().values().cloned().collect())),
+        _ => Err(ScmErr::from("hash-table-values: expected a hash table")),
+    }
+}
+
+pub fn hash_table_to_alist(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::HashTable(t)] => Ok(vec_to_list(
+            t.borrow()
+                .iter()
+                .map(|(k, v)| Exp::List(vec![k.to_exp(), v.clone()]))
+                .collect(),
+        )),
+        _ => Err(ScmErr::from("hash-table->alist: expected a hash table")),
+    }
+}
@@ -0,0 +1,479 @@
+use super::ports::port_arg;
+use crate::convert::{list_to_vec, vec_to_list};
+use crate::interp::Interp;
+use crate::types::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::iter::Peekable;
+
+// * JSON, following the conventions of SRFI-180
+//
+// Objects are read as association Lists with Symbol keys (or as hash tables on request),
+// arrays as Vectors, and `null` as the Symbol `null`.
+// Objects are written from association Lists or hash tables with Symbol or String keys.
+
+/// How JSON objects are represented in Scheme.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ObjectStyle {
+    Alist,
+    HashTable,
+}
+
+impl ObjectStyle {
+    fn from_args(args: &[Exp], name: &str) -> Result<Self, ScmErr> {
+        match args {
+            [] => Ok(ObjectStyle::Alist),
+            [Exp::Symbol(s)] if s.as_str() == "alist" => Ok(ObjectStyle::Alist),
+            [Exp::Symbol(s)] if s.as_str() == "hash-table" => Ok(ObjectStyle::HashTable),
+            _ => Err(ScmErr::from(&format!(
+                "{}: expected 'alist or 'hash-table as the object style",
+                name
+            ))),
+        }
+    }
+}
+
+fn null() -> Exp {
+    Exp::Symbol(Symbol::intern("null"))
+}
+
+/// A source of chars with one char of lookahead.
+pub trait CharSource {
+    fn peek(&mut self) -> Option<char>;
+    fn next(&mut self) -> Option<char>;
+}
+
+impl<I: Iterator<Item = char>> CharSource for Peekable<I> {
+    fn peek(&mut self) -> Option<char> {
+        Peekable::peek(self).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        Iterator::next(self)
+    }
+}
+
+/// The chars of an input port, which are only consumed when they are read.
+/// The first I/O error ends the input, and is kept to be reported.
+pub struct PortChars<'a> {
+    port: &'a Port,
+    err: Option<ScmErr>,
+}
+
+impl<'a> PortChars<'a> {
+    pub fn new(port: &'a Port) -> Self {
+        PortChars { port, err: None }
+    }
+
+    fn check<T>(&mut self, res: Result<Option<T>, ScmErr>) -> Option<T> {
+        res.unwrap_or_else(|e| {
+            self.err.get_or_insert(e);
+            None
+        })
+    }
+}
+
+impl CharSource for PortChars<'_> {
+    fn peek(&mut self) -> Option<char> {
+        let res = self.port.peek_char();
+        self.check(res)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let res = self.port.read_char();
+        self.check(res)
+    }
+}
+
+/// A JSON reader over a source of chars, keeping track of the current position for errors.
+pub struct JsonReader<S: CharSource> {
+    chars: S,
+    line: usize,
+    column: usize,
+    style: ObjectStyle,
+}
+
+impl<I: Iterator<Item = char>> JsonReader<Peekable<I>> {
+    pub fn new(chars: I, style: ObjectStyle) -> Self {
+        JsonReader::from_source(chars.peekable(), style)
+    }
+}
+
+impl<S: CharSource> JsonReader<S> {
+    pub fn from_source(chars: S, style: ObjectStyle) -> Self {
+        JsonReader {
+            chars,
+            line: 1,
+            column: 1,
+            style,
+        }
+    }
+
+    fn error(&self, msg: &str) -> ScmErr {
+        ScmErr::from(&format!(
+            "json: {} at line {}, column {}",
+            msg, self.line, self.column
+        ))
+    }
+
+    fn unexpected(&mut self) -> ScmErr {
+        match self.chars.peek() {
+            Some(c) => self.error(&format!("unexpected character {:?}", c)),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.chars.peek() {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ScmErr> {
+        if self.chars.peek() == Some(expected) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Exp) -> Result<Exp, ScmErr> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+
+    /// Check that nothing but whitespace is left.
+    pub fn finish(&mut self) -> Result<(), ScmErr> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+
+    /// Read the next JSON value.
+    pub fn read(&mut self) -> Result<Exp, ScmErr> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Exp::String),
+            Some('t') => self.keyword("true", Exp::Bool(true)),
+            Some('f') => self.keyword("false", Exp::Bool(false)),
+            Some('n') => self.keyword("null", null()),
+            Some('-') | Some('0'..='9') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn array(&mut self) -> Result<Exp, ScmErr> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(']') {
+            self.next();
+            return Ok(Exp::vector(items));
+        }
+        loop {
+            items.push(self.read()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => break Ok(Exp::vector(items)),
+                _ => break Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Exp, ScmErr> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() != Some('}') {
+            loop {
+                self.skip_whitespace();
+                if self.chars.peek() != Some('"') {
+                    return Err(self.unexpected());
+                }
+                let key = Symbol::intern(&self.string()?);
+                self.skip_whitespace();
+                self.expect(':')?;
+                members.push((key, self.read()?));
+                self.skip_whitespace();
+                match self.next() {
+                    Some(',') => continue,
+                    Some('}') => break,
+                    _ => return Err(self.error("expected ',' or '}'")),
+                }
+            }
+        } else {
+            self.next();
+        }
+        Ok(match self.style {
+            ObjectStyle::Alist => vec_to_list(
+                members
+                    .into_iter()
+                    .map(|(k, v)| Exp::List(vec![Exp::Symbol(k), v]))
+                    .collect(),
+            ),
+            ObjectStyle::HashTable => Exp::hash_table(
+                members
+                    .into_iter()
+                    .map(|(k, v)| (HashKey::Symbol(k), v))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+        })
+    }
+
+    fn string(&mut self) -> Result<String, ScmErr> {
+        self.expect('"')?;
+        let mut res = String::new();
+        loop {
+            match self.next() {
+                Some('"') => break Ok(res),
+                Some('\\') => match self.next() {
+                    Some('"') => res.push('"'),
+                    Some('\\') => res.push('\\'),
+                    Some('/') => res.push('/'),
+                    Some('b') => res.push('\u{8}'),
+                    Some('f') => res.push('\u{c}'),
+                    Some('n') => res.push('\n'),
+                    Some('r') => res.push('\r'),
+                    Some('t') => res.push('\t'),
+                    Some('u') => {
+                        let hi = self.hex4()?;
+                        let code = if (0xD800..0xDC00).contains(&hi) {
+                            // A surrogate pair.
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let lo = self.hex4()?;
+                            if !(0xDC00..0xE000).contains(&lo) {
+                                return Err(self.error("invalid surrogate pair"));
+                            }
+                            0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+                        } else {
+                            hi
+                        };
+                        let c = std::char::from_u32(code)
+                            .ok_or_else(|| self.error("invalid unicode escape"))?;
+                        res.push(c);
+                    }
+                    _ => break Err(self.error("invalid escape in string")),
+                },
+                Some(c) if c < ' ' => break Err(self.error("control character in string")),
+                Some(c) => res.push(c),
+                None => break Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, ScmErr> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .peek()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("expected a hex digit"))?;
+            self.next();
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn digits(&mut self, buf: &mut String) -> Result<(), ScmErr> {
+        match self.chars.peek() {
+            Some(c) if c.is_ascii_digit() => (),
+            _ => return Err(self.error("expected a digit")),
+        }
+        while let Some(c) = self.chars.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            buf.push(c);
+            self.next();
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<Exp, ScmErr> {
+        let mut buf = String::new();
+        if self.chars.peek() == Some('-') {
+            buf.push('-');
+            self.next();
+        }
+        if self.chars.peek() == Some('0') {
+            buf.push('0');
+            self.next();
+        } else {
+            self.digits(&mut buf)?;
+        }
+        if self.chars.peek() == Some('.') {
+            buf.push('.');
+            self.next();
+            self.digits(&mut buf)?;
+        }
+        if let Some('e') | Some('E') = self.chars.peek() {
+            buf.push('e');
+            self.next();
+            if let Some(c @ '+') | Some(c @ '-') = self.chars.peek() {
+                buf.push(c);
+                self.next();
+            }
+            self.digits(&mut buf)?;
+        }
+        match buf.parse::<f64>() {
+            // Numbers too large for a f64 can't be written back as JSON.
+            Ok(n) if n.is_finite() => Ok(Exp::Number(n)),
+            Ok(_) => Err(self.error("number out of range")),
+            Err(_) => Err(self.error("invalid number")),
+        }
+    }
+}
+
+/// Write an Exp as JSON.
+pub fn write_json(exp: &Exp, out: &mut String) -> Result<(), ScmErr> {
+    let cannot = || ScmErr::from(&format!("json: cannot write {} as JSON", exp));
+    match exp {
+        Exp::Bool(b) => write!(out, "{}", b).unwrap(),
+        Exp::Number(n) if n.is_finite() => write!(out, "{}", n).unwrap(),
+        Exp::String(s) => write_json_string(s, out),
+        Exp::Symbol(s) if s.as_str() == "null" => out.push_str("null"),
+        Exp::Vector(v) => {
+            out.push('[');
+            for (i, item) in v.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(item, out)?;
+            }
+            out.push(']');
+        }
+        Exp::HashTable(t) => {
+            let members: Vec<(Exp, Exp)> = t
+                .borrow()
+                .iter()
+                .map(|(k, v)| (k.to_exp(), v.clone()))
+                .collect();
+            write_json_object(&members, out).map_err(|_| cannot())?;
+        }
+        Exp::List(_) => {
+            let members = list_to_vec(exp)
+                .map_err(|_| cannot())?
+                .into_iter()
+                .map(|pair| match pair {
+                    Exp::List(mut pair) if pair.len() == 2 => {
+                        let v = pair.pop().unwrap();
+                        Ok((pair.pop().unwrap(), v))
+                    }
+                    _ => Err(cannot()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            write_json_object(&members, out).map_err(|_| cannot())?;
+        }
+        _ => return Err(cannot()),
+    };
+    Ok(())
+}
+
+fn write_json_object(members: &[(Exp, Exp)], out: &mut String) -> Result<(), ScmErr> {
+    out.push('{');
+    for (i, (k, v)) in members.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        match k {
+            Exp::Symbol(s) => write_json_string(s.as_str(), out),
+            Exp::String(s) => write_json_string(s, out),
+            _ => return Err(ScmErr::from("json: invalid object key")),
+        }
+        out.push(':');
+        write_json(v, out)?;
+    }
+    out.push('}');
+    Ok(())
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// `(json-read [port [style]])` reads a JSON value from a port, the current input port by default.
+pub fn json_read(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = port_arg(args, 0, &interp.ports.input, "json-read")?;
+    let style = ObjectStyle::from_args(args.get(1..).unwrap_or_default(), "json-read")?;
+    let mut reader = JsonReader::from_source(PortChars::new(&port), style);
+    let res = reader.read();
+    match reader.chars.err.take() {
+        Some(e) => Err(e),
+        None => res,
+    }
+}
+
+/// `(json-write obj [port])` writes an Exp as JSON to a port, the current output port by default.
+pub fn json_write(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = port_arg(args, 1, &interp.ports.output, "json-write")?;
+    let mut out = String::new();
+    write_json(&args[0], &mut out)?;
+    port.write_str(&out)?;
+    Ok(Exp::Empty)
+}
+
+/// `(string->json str [style])` parses a String holding exactly one JSON value.
+pub fn string_to_json(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::String(s), rest @ ..] => {
+            let style = ObjectStyle::from_args(rest, "string->json")?;
+            let mut reader = JsonReader::new(s.chars(), style);
+            let res = reader.read()?;
+            reader.finish()?;
+            Ok(res)
+        }
+        _ => Err(ScmErr::from("string->json: expected a String")),
+    }
+}
+
+pub fn json_to_string(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [exp] => {
+            let mut out = String::new();
+            write_json(exp, &mut out)?;
+            Ok(Exp::String(out))
+        }
+        _ => Err(ScmErr::from("json->string: expected one Exp to write")),
+    }
+}
+
+pub fn is_json_null(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Symbol(s)] => Ok(Exp::Bool(s.as_str() == "null")),
+        [_] => Ok(Exp::Bool(false)),
+        _ => Err(ScmErr::from("json-null?: expected one Exp to check")),
+    }
+}
@@ -0,0 +1,328 @@
+use crate::interp::Interp;
+use crate::parser::{InPort, InScm};
+use crate::printer::{self, Labels, Printer, Style};
+use crate::types::*;
+use std::rc::Rc;
+
+// * Ports
+//
+// The printing procedures write to the current output port, unless they are given a port.
+// `current-input-port`, `current-output-port` and `current-error-port` are parameters:
+// they return the current port when called without arguments, and replace it when given one.
+
+/// Get the port at the given position of the arguments, or the default port if there is none.
+pub fn port_arg(
+    args: &[Exp],
+    i: usize,
+    default: &Rc<Port>,
+    name: &str,
+) -> Result<Rc<Port>, ScmErr> {
+    match args.get(i) {
+        None => Ok(Rc::clone(default)),
+        Some(Exp::Port(port)) => Ok(Rc::clone(port)),
+        Some(exp) => Err(ScmErr::from(&format!(
+            "{}: expected a port, got {}",
+            name, exp
+        ))),
+    }
+}
+
+fn output_port_arg(
+    args: &[Exp],
+    i: usize,
+    interp: &Interp,
+    name: &str,
+) -> Result<Rc<Port>, ScmErr> {
+    let port = port_arg(args, i, &interp.ports.output, name)?;
+    if !port.is_output() {
+        return Err(ScmErr::from(&format!("{}: expected an output port", name)));
+    }
+    Ok(port)
+}
+
+fn string_arg<'a>(args: &'a [Exp], name: &str) -> Result<&'a str, ScmErr> {
+    match args.first() {
+        Some(Exp::String(s)) => Ok(s),
+        _ => Err(ScmErr::from(&format!("{}: expected a String", name))),
+    }
+}
+
+fn parameter(args: &[Exp], current: &mut Rc<Port>, input: bool, name: &str) -> Result<Exp, ScmErr> {
+    match args {
+        [] => Ok(Exp::Port(Rc::clone(current))),
+        [Exp::Port(port)] if port.is_input() == input => {
+            *current = Rc::clone(port);
+            Ok(Exp::Empty)
+        }
+        _ => Err(ScmErr::from(&format!(
+            "{}: expected an {} port",
+            name,
+            if input { "input" } else { "output" }
+        ))),
+    }
+}
+
+pub fn current_input_port(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    parameter(args, &mut interp.ports.input, true, "current-input-port")
+}
+
+pub fn current_output_port(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    parameter(args, &mut interp.ports.output, false, "current-output-port")
+}
+
+pub fn current_error_port(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    parameter(args, &mut interp.ports.error, false, "current-error-port")
+}
+
+fn print(
+    args: &[Exp],
+    interp: &mut Interp,
+    name: &str,
+    style: Style,
+    labels: Labels,
+) -> Result<Exp, ScmErr> {
+    let port = output_port_arg(args, 1, interp, name)?;
+    port.write_str(&Printer::new(&args[0], style, labels).print(&args[0]))?;
+    Ok(Exp::Empty)
+}
+
+/// `(display obj [port])`
+pub fn display(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    print(args, interp, "display", Style::Display, Labels::Cycles)
+}
+
+/// `(write obj [port])`
+pub fn write(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    print(args, interp, "write", Style::Write, Labels::Cycles)
+}
+
+/// `(write-shared obj [port])`
+pub fn write_shared(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    print(args, interp, "write-shared", Style::Write, Labels::Shared)
+}
+
+/// `(write-simple obj [port])`
+pub fn write_simple(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    print(args, interp, "write-simple", Style::Write, Labels::Never)
+}
+
+/// `(pretty-print obj [port])` writes a datum laid out within the default width, and a newline.
+pub fn pretty_print(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = output_port_arg(args, 1, interp, "pretty-print")?;
+    let text = printer::pretty_print(&args[0], 0, printer::DEFAULT_WIDTH);
+    port.write_str(&text)?;
+    port.write_str("\n")?;
+    Ok(Exp::Empty)
+}
+
+/// `(newline [port])`
+pub fn newline(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    output_port_arg(args, 0, interp, "newline")?.write_str("\n")?;
+    Ok(Exp::Empty)
+}
+
+/// `(write-string string [port])`
+pub fn write_string(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let s = string_arg(args, "write-string")?;
+    output_port_arg(args, 1, interp, "write-string")?.write_str(s)?;
+    Ok(Exp::Empty)
+}
+
+/// `(flush-output-port [port])`
+pub fn flush_output_port(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    output_port_arg(args, 0, interp, "flush-output-port")?.flush()?;
+    Ok(Exp::Empty)
+}
+
+pub fn open_input_file(args: &[Exp]) -> Result<Exp, ScmErr> {
+    let path = string_arg(args, "open-input-file")?;
+    Ok(Exp::Port(Rc::new(Port::input_file(path)?)))
+}
+
+pub fn open_output_file(args: &[Exp]) -> Result<Exp, ScmErr> {
+    let path = string_arg(args, "open-output-file")?;
+    Ok(Exp::Port(Rc::new(Port::output_file(path)?)))
+}
+
+pub fn open_input_string(args: &[Exp]) -> Result<Exp, ScmErr> {
+    let s = string_arg(args, "open-input-string")?;
+    Ok(Exp::Port(Rc::new(Port::input_string(s))))
+}
+
+pub fn open_output_string(args: &[Exp]) -> Result<Exp, ScmErr> {
+    if !args.is_empty() {
+        return Err(ScmErr::from("open-output-string: too many arguments"));
+    }
+    Ok(Exp::Port(Rc::new(Port::output_string())))
+}
+
+pub fn get_output_string(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Port(port)] => port
+            .output_string_contents()
+            .map(Exp::String)
+            .ok_or_else(|| ScmErr::from("get-output-string: expected a string output port")),
+        _ => Err(ScmErr::from("get-output-string: expected a port")),
+    }
+}
+
+/// Byte buffers are represented as Vectors of bytes.
+pub fn open_input_bytevector(args: &[Exp]) -> Result<Exp, ScmErr> {
+    let bytes = match args {
+        [Exp::Vector(v)] => v
+            .borrow()
+            .iter()
+            .map(|b| match *b {
+                Exp::Number(n) if n.fract() == 0.0 && (0.0..256.0).contains(&n) => Ok(n as u8),
+                _ => Err(ScmErr::from("open-input-bytevector: invalid byte")),
+            })
+            .collect::<Result<Vec<u8>, ScmErr>>()?,
+        _ => return Err(ScmErr::from("open-input-bytevector: expected a Vector")),
+    };
+    Ok(Exp::Port(Rc::new(Port::input_bytes(bytes))))
+}
+
+pub fn open_output_bytevector(args: &[Exp]) -> Result<Exp, ScmErr> {
+    if !args.is_empty() {
+        return Err(ScmErr::from("open-output-bytevector: too many arguments"));
+    }
+    Ok(Exp::Port(Rc::new(Port::output_bytes())))
+}
+
+pub fn get_output_bytevector(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Port(port)] => port
+            .output_bytes_contents()
+            .map(|bytes| Exp::vector(bytes.into_iter().map(|b| Exp::Number(b as f64)).collect()))
+            .ok_or_else(|| {
+                ScmErr::from("get-output-bytevector: expected a bytevector output port")
+            }),
+        _ => Err(ScmErr::from("get-output-bytevector: expected a port")),
+    }
+}
+
+pub fn close_port(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [Exp::Port(port)] => {
+            port.close()?;
+            Ok(Exp::Empty)
+        }
+        _ => Err(ScmErr::from("close-port: expected a port")),
+    }
+}
+
+pub fn is_port(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [exp] => Ok(Exp::Bool(matches!(exp, Exp::Port(_)))),
+        _ => Err(ScmErr::from("port?: expected one Exp to check")),
+    }
+}
+
+pub fn is_input_port(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [exp] => Ok(Exp::Bool(matches!(exp, Exp::Port(p) if p.is_input()))),
+        _ => Err(ScmErr::from("input-port?: expected one Exp to check")),
+    }
+}
+
+pub fn is_output_port(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [exp] => Ok(Exp::Bool(matches!(exp, Exp::Port(p) if p.is_output()))),
+        _ => Err(ScmErr::from("output-port?: expected one Exp to check")),
+    }
+}
+
+/// `(call-with-output-string proc)` calls `proc` with a string port, and returns what it wrote.
+pub fn call_with_output_string(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = Rc::new(Port::output_string());
+    interp.apply(&args[0], &[Exp::Port(Rc::clone(&port))])?;
+    Ok(Exp::String(
+        port.output_string_contents().unwrap_or_default(),
+    ))
+}
+
+/// `(with-output-to-file path thunk)` calls `thunk` with the file as the current output port.
+/// The previous port is restored and the file is closed even if `thunk` fails,
+/// and a failure to close the file is only reported if `thunk` succeeds.
+pub fn with_output_to_file(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let path = string_arg(args, "with-output-to-file")?;
+    let port = Rc::new(Port::output_file(path)?);
+    let outer = std::mem::replace(&mut interp.ports.output, Rc::clone(&port));
+    let res = interp.apply(&args[1], &[]);
+    interp.ports.output = outer;
+    // An error or an exit of the thunk takes precedence over one in closing the file.
+    let closed = port.close();
+    let res = res?;
+    closed.map(|_| res)
+}
+
+// * Reading
+
+fn input_port_arg(args: &[Exp], i: usize, interp: &Interp, name: &str) -> Result<Rc<Port>, ScmErr> {
+    let port = port_arg(args, i, &interp.ports.input, name)?;
+    if !port.is_input() {
+        return Err(ScmErr::from(&format!("{}: expected an input port", name)));
+    }
+    Ok(port)
+}
+
+fn char_or_eof(c: Option<char>) -> Exp {
+    c.map_or(Exp::Eof, Exp::Char)
+}
+
+/// `(read [port])` reads a datum with the same reader as the REPL, without desugaring it.
+pub fn read(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = input_port_arg(args, 0, interp, "read")?;
+    let mut reader = InScm::new(&port);
+    let res = match reader.next_token() {
+        Ok(None) => Ok(Exp::Eof),
+        Ok(Some(token)) => reader.read_ahead(&token),
+        Err(e) => Err(ScmErr::from(&format!("read: {}", e))),
+    };
+    reader.finish()?;
+    res
+}
+
+pub fn read_char(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = input_port_arg(args, 0, interp, "read-char")?;
+    port.read_char().map(char_or_eof)
+}
+
+pub fn peek_char(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = input_port_arg(args, 0, interp, "peek-char")?;
+    port.peek_char().map(char_or_eof)
+}
+
+pub fn read_line(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = input_port_arg(args, 0, interp, "read-line")?;
+    Ok(port.read_line()?.map_or(Exp::Eof, Exp::String))
+}
+
+/// `(read-string k [port])`
+pub fn read_string(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let k = match args[0] {
+        Exp::Number(k) if k >= 0.0 && k.fract() == 0.0 => k as usize,
+        _ => return Err(ScmErr::from("read-string: expected a count")),
+    };
+    let port = input_port_arg(args, 1, interp, "read-string")?;
+    Ok(port.read_string(k)?.map_or(Exp::Eof, Exp::String))
+}
+
+pub fn is_char_ready(args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let port = input_port_arg(args, 0, interp, "char-ready?")?;
+    port.char_ready().map(Exp::Bool)
+}
+
+pub fn eof_object(args: &[Exp]) -> Result<Exp, ScmErr> {
+    if !args.is_empty() {
+        return Err(ScmErr::from("eof-object: too many arguments"));
+    }
+    Ok(Exp::Eof)
+}
+
+pub fn is_eof_object(args: &[Exp]) -> Result<Exp, ScmErr> {
+    match args {
+        [exp] => Ok(Exp::Bool(matches!(exp, Exp::Eof))),
+        _ => Err(ScmErr::from("eof-object?: expected one Exp to check")),
+    }
+}
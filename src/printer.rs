@@ -0,0 +1,235 @@
+use crate::types::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::rc::Rc;
+
+mod pretty;
+pub use pretty::{layout, layout_forms, pretty_print, Doc, DEFAULT_WIDTH};
+
+// * Printing data, following R7RS
+//
+// `write` prints data so that `read` gives back an `equal?` datum:
+// strings and chars are quoted, and Lists are printed with their cons structure.
+// `display` prints strings and chars as they are.
+// Objects which can't be read back are printed as `#<...>`.
+//
+// Vectors are the only data which can be shared or cyclic.
+// They are given datum labels like `#0=#(1 #0#)`: by `write` if they are part of a cycle,
+// and by `write-shared` if they appear more than once. `write-simple` never uses labels,
+// so it doesn't terminate on cyclic data.
+
+/// How strings and chars are printed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Style {
+    Write,
+    Display,
+}
+
+/// Which Vectors are given datum labels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Labels {
+    Never,
+    Cycles,
+    Shared,
+}
+
+type VectorPtr = *const RefCell<Vec<Exp>>;
+
+/// A printer for one datum, holding the datum labels.
+pub struct Printer {
+    style: Style,
+    /// The labelled Vectors, with their label once they have been printed.
+    labels: HashMap<VectorPtr, Option<usize>>,
+    next_label: usize,
+}
+
+/// Print a datum with `write`.
+pub fn write(exp: &Exp) -> String {
+    Printer::new(exp, Style::Write, Labels::Cycles).print(exp)
+}
+
+impl Printer {
+    /// Make a printer for the given datum, finding the Vectors which need labels.
+    pub fn new(exp: &Exp, style: Style, labels: Labels) -> Self {
+        let mut printer = Printer {
+            style,
+            labels: HashMap::new(),
+            next_label: 0,
+        };
+        if labels != Labels::Never {
+            let mut scan = Scan {
+                shared: labels == Labels::Shared,
+                seen: HashSet::new(),
+                visiting: HashSet::new(),
+                labels: &mut printer.labels,
+            };
+            scan.exp(exp);
+        }
+        printer
+    }
+
+    /// Print a datum, or a part of the datum given to `new`.
+    pub fn print(&mut self, exp: &Exp) -> String {
+        let mut out = String::new();
+        self.exp(exp, &mut out);
+        out
+    }
+
+    fn exp(&mut self, exp: &Exp, out: &mut String) {
+        match exp {
+            Exp::Bool(true) => out.push_str("#t"),
+            Exp::Bool(false) => out.push_str("#f"),
+            &Exp::Number(n) => out.push_str(&number(n)),
+            Exp::Symbol(s) => out.push_str(s.as_str()),
+            Exp::String(s) if self.style == Style::Display => out.push_str(s),
+            Exp::String(s) => string_literal(s, out),
+            &Exp::Char(c) if self.style == Style::Display => out.push(c),
+            &Exp::Char(c) => out.push_str(&char_literal(c)),
+            Exp::List(_) => self.list(exp, out),
+            Exp::Vector(v) => self.vector(v, out),
+            Exp::HashTable(_) => out.push_str("#<hash-table>"),
+            Exp::Port(port) => write!(out, "#{}", port).unwrap(),
+            Exp::Closure(_) | Exp::Compiled(_) | Exp::Primitive(_) => out.push_str("#<procedure>"),
+            Exp::Native(native) => write!(out, "#<procedure {}>", native.name).unwrap(),
+            Exp::Environment(_) => out.push_str("#<environment>"),
+            Exp::Eof => out.push_str("#<eof>"),
+            Exp::Empty => out.push_str("#<unspecified>"),
+        }
+    }
+
+    /// Print a List, following the cdrs in a loop so that long Lists don't overflow the stack.
+    fn list(&mut self, exp: &Exp, out: &mut String) {
+        let (items, tail) = list_parts(exp);
+        out.push('(');
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            self.exp(item, out);
+        }
+        if let Some(tail) = tail {
+            out.push_str(" . ");
+            self.exp(tail, out);
+        }
+        out.push(')');
+    }
+
+    /// Get the label to put before a Vector, or the reference to print instead of it
+    /// if it has already been printed.
+    fn label(&mut self, v: &RcRefCell<Vec<Exp>>) -> Result<String, String> {
+        let ptr = Rc::as_ptr(v);
+        match self.labels.get(&ptr) {
+            Some(&Some(label)) => Err(format!("#{}#", label)),
+            Some(None) => {
+                let label = self.next_label;
+                self.next_label += 1;
+                self.labels.insert(ptr, Some(label));
+                Ok(format!("#{}=", label))
+            }
+            None => Ok(String::new()),
+        }
+    }
+
+    fn vector(&mut self, v: &RcRefCell<Vec<Exp>>, out: &mut String) {
+        match self.label(v) {
+            Ok(prefix) => out.push_str(&prefix),
+            Err(reference) => return out.push_str(&reference),
+        }
+        out.push_str("#(");
+        for (i, item) in v.borrow().iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            self.exp(item, out);
+        }
+        out.push(')');
+    }
+}
+
+/// Split a List into its items and its tail, which is `None` for a proper List.
+/// Data Lists are built with `cons`, as are quoted and `read` Lists,
+/// so a flat List is only expected as the empty List.
+pub fn list_parts(exp: &Exp) -> (Vec<&Exp>, Option<&Exp>) {
+    let mut items = Vec::new();
+    let mut rest = exp;
+    loop {
+        match rest {
+            Exp::List(pair) if pair.len() == 2 => {
+                items.push(&pair[0]);
+                rest = &pair[1];
+            }
+            Exp::List(list) => {
+                items.extend(list.iter());
+                return (items, None);
+            }
+            tail => return (items, Some(tail)),
+        }
+    }
+}
+
+/// Finds the Vectors which need labels.
+struct Scan<'a> {
+    shared: bool,
+    seen: HashSet<VectorPtr>,
+    /// The Vectors being scanned, from the outermost one.
+    visiting: HashSet<VectorPtr>,
+    labels: &'a mut HashMap<VectorPtr, Option<usize>>,
+}
+
+impl Scan<'_> {
+    fn exp(&mut self, exp: &Exp) {
+        match exp {
+            Exp::List(_) => {
+                let (items, tail) = list_parts(exp);
+                items
+                    .into_iter()
+                    .chain(tail)
+                    .for_each(|item| self.exp(item));
+            }
+            Exp::Vector(v) => {
+                let ptr = Rc::as_ptr(v);
+                if self.visiting.contains(&ptr) || (self.shared && self.seen.contains(&ptr)) {
+                    self.labels.insert(ptr, None);
+                    return;
+                }
+                if !self.seen.insert(ptr) {
+                    return;
+                }
+                self.visiting.insert(ptr);
+                v.borrow().iter().for_each(|item| self.exp(item));
+                self.visiting.remove(&ptr);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Format a Number so that it reads back as the same Number.
+pub fn number(n: f64) -> String {
+    if n.is_nan() {
+        "+nan.0".into()
+    } else if n.is_infinite() {
+        if n > 0.0 { "+inf.0" } else { "-inf.0" }.into()
+    } else {
+        format!("{}", n)
+    }
+}
+
+/// Format a string literal, with the escapes understood by the reader.
+fn string_literal(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\u{7}' => out.push_str("\\a"),
+            c if c.is_control() => write!(out, "\\x{:x};", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,307 @@
+use super::{list_parts, Labels, Printer, Style};
+use crate::types::*;
+
+// * Pretty printing
+//
+// Data and code are laid out within a target width, following the usual Lisp indentation:
+// - a List which fits on the rest of the line is printed flat;
+// - special forms like `define`, `lambda` and `let` keep their distinguished arguments
+//   on the first line, and indent their body by 2 columns;
+// - a call keeps its first argument next to the operator, and aligns the others under it;
+// - a List of short items is filled, with as many items per line as fit,
+//   so that long Lists of data take as few lines as possible.
+//
+// The layout works on `Doc` trees, so that the formatter can use it on source code too.
+// Source code has comments and blank lines, which force the Lists holding them to be broken.
+
+/// The default target width.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// A tree to lay out.
+#[derive(Clone, Debug)]
+pub enum Doc {
+    /// Text which is never broken.
+    Atom(String),
+    /// A List, between its opening and closing delimiters.
+    List {
+        open: String,
+        items: Vec<Doc>,
+        close: String,
+    },
+    /// A comment, which is followed by a line break.
+    /// A trailing comment stays at the end of the line of the previous item.
+    Comment { text: String, trailing: bool },
+    /// A blank line between two items.
+    Blank,
+}
+
+/// The special forms with a body, and the number of their distinguished arguments.
+const BODY_FORMS: &[(&str, usize)] = &[
+    ("case", 1),
+    ("define", 1),
+    ("define-library", 1),
+    ("define-record-type", 2),
+    ("define-syntax", 1),
+    ("do", 2),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("let*-values", 1),
+    ("let-syntax", 1),
+    ("let-values", 1),
+    ("letrec", 1),
+    ("letrec*", 1),
+    ("letrec-syntax", 1),
+    ("parameterize", 1),
+    ("syntax-rules", 1),
+    ("unless", 1),
+    ("when", 1),
+    ("begin", 0),
+    ("cond", 0),
+];
+
+impl Doc {
+    fn atom(&self) -> Option<&str> {
+        match self {
+            Doc::Atom(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn is_comment(&self) -> bool {
+        matches!(self, Doc::Comment { .. })
+    }
+
+    /// Whether the Doc holds comments or blank lines, so that it can't be printed on one line.
+    fn breaks(&self) -> bool {
+        match self {
+            Doc::Atom(_) => false,
+            Doc::List { items, .. } => items.iter().any(Doc::breaks),
+            Doc::Comment { .. } | Doc::Blank => true,
+        }
+    }
+
+    /// The width of the Doc printed on one line.
+    fn flat_width(&self) -> usize {
+        match self {
+            Doc::Atom(s) | Doc::Comment { text: s, .. } => s.chars().count(),
+            Doc::List { open, items, close } => {
+                open.chars().count()
+                    + items.iter().map(Doc::flat_width).sum::<usize>()
+                    + items.len().saturating_sub(1)
+                    + close.chars().count()
+            }
+            Doc::Blank => 0,
+        }
+    }
+
+    fn flat(&self, out: &mut String) {
+        match self {
+            Doc::Atom(s) | Doc::Comment { text: s, .. } => out.push_str(s),
+            Doc::List { open, items, close } => {
+                out.push_str(open);
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    item.flat(out);
+                }
+                out.push_str(close);
+            }
+            Doc::Blank => (),
+        }
+    }
+
+    /// The number of distinguished arguments, if the Doc is a special form with a body.
+    fn body_form(&self) -> Option<usize> {
+        let items = match self {
+            Doc::List { open, items, .. } if open.ends_with('(') => items,
+            _ => return None,
+        };
+        let head = items.first()?.atom()?;
+        let n = BODY_FORMS.iter().find(|&&(name, _)| name == head)?.1;
+        // A named let has the name as an extra distinguished argument.
+        let named_let = head == "let" && items.get(1).and_then(Doc::atom).is_some();
+        Some(if named_let { n + 1 } else { n })
+    }
+}
+
+/// Lay out a Doc within the given width, starting at the given column.
+pub fn layout(doc: &Doc, col: usize, width: usize) -> String {
+    let mut layout = Layout {
+        out: String::new(),
+        col,
+        width,
+    };
+    layout.doc(doc);
+    layout.out
+}
+
+/// Lay out a sequence of top-level forms, one per line, ending with a newline.
+pub fn layout_forms(docs: &[Doc], width: usize) -> String {
+    let mut layout = Layout {
+        out: String::new(),
+        col: 0,
+        width,
+    };
+    layout.seq(docs, 1, Some(0), 0);
+    if !layout.out.is_empty() && !layout.out.ends_with('\n') {
+        layout.out.push('\n');
+    }
+    layout.out
+}
+
+struct Layout {
+    out: String,
+    /// The current column.
+    col: usize,
+    width: usize,
+}
+
+impl Layout {
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+        match s.rfind('\n') {
+            Some(i) => self.col = s[i + 1..].chars().count(),
+            None => self.col += s.chars().count(),
+        }
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.extend(std::iter::repeat_n(' ', indent));
+        self.col = indent;
+    }
+
+    fn fits(&self, doc: &Doc) -> bool {
+        !doc.breaks() && self.col + doc.flat_width() <= self.width
+    }
+
+    fn doc(&mut self, doc: &Doc) {
+        let (open, items, close) = match doc {
+            Doc::List { open, items, close } if !self.fits(doc) && !items.is_empty() => {
+                (open, items, close)
+            }
+            _ => {
+                let mut flat = String::new();
+                doc.flat(&mut flat);
+                return self.push(&flat);
+            }
+        };
+        let start = self.col;
+        self.push(open);
+        let inner = self.col;
+        let narrow = self.width / 8;
+        let short = |item: &Doc| item.atom().is_some() || item.flat_width() <= narrow;
+        if let Some(n) = doc.body_form() {
+            // The distinguished arguments stay on the first line, then comes the body.
+            self.seq(items, n + 1, Some(start + 2), inner);
+        } else if !doc.breaks() && items.iter().all(short) {
+            self.fill(items, inner);
+        } else {
+            match items.as_slice() {
+                // A call keeps its first argument next to the operator, unless it's too long.
+                [head, arg, ..]
+                    if head.atom().is_some()
+                        && !matches!(arg, Doc::Comment { .. } | Doc::Blank)
+                        && inner + head.flat_width() < self.width / 2 =>
+                {
+                    self.seq(items, 2, None, inner)
+                }
+                _ => self.seq(items, 1, Some(inner), inner),
+            }
+        }
+        self.push(close);
+    }
+
+    /// Lay out items, the first `same_line` of them on the current line and the others on their own lines.
+    /// They are indented by `indent`, or aligned with the second item if it's `None`.
+    /// Comments are followed by a line break, and blank lines are kept.
+    fn seq(&mut self, items: &[Doc], same_line: usize, indent: Option<usize>, fallback: usize) {
+        let mut indent = indent;
+        let mut after_comment = false;
+        let mut blank = false;
+        for (i, item) in items.iter().enumerate() {
+            if let Doc::Blank = item {
+                blank = true;
+                continue;
+            }
+            let on_same_line = !after_comment
+                && match item {
+                    Doc::Comment { trailing, .. } => *trailing,
+                    _ => i < same_line,
+                };
+            if on_same_line {
+                if i > 0 {
+                    self.push(" ");
+                }
+                if i == 1 && indent.is_none() {
+                    indent = Some(self.col);
+                }
+            } else if i > 0 {
+                if blank {
+                    self.out.push('\n');
+                }
+                self.newline(indent.unwrap_or(fallback));
+            }
+            blank = false;
+            self.doc(item);
+            after_comment = item.is_comment();
+        }
+        // Don't comment out what follows.
+        if after_comment {
+            self.newline(indent.unwrap_or(fallback));
+        }
+    }
+
+    /// Lay out items with as many of them per line as fit, aligned at the given column.
+    fn fill(&mut self, items: &[Doc], indent: usize) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                // Keep room for the closing delimiters after the last item.
+                if self.col + 1 + item.flat_width() < self.width {
+                    self.push(" ");
+                } else {
+                    self.newline(indent);
+                }
+            }
+            self.doc(item);
+        }
+    }
+}
+
+impl Printer {
+    /// Convert a datum to a Doc, with the same atoms and datum labels as `print`.
+    pub fn doc(&mut self, exp: &Exp) -> Doc {
+        match exp {
+            Exp::List(_) => {
+                let (items, tail) = list_parts(exp);
+                let mut items: Vec<Doc> = items.into_iter().map(|item| self.doc(item)).collect();
+                if let Some(tail) = tail {
+                    items.push(Doc::Atom(".".into()));
+                    items.push(self.doc(tail));
+                }
+                Doc::List {
+                    open: "(".into(),
+                    items,
+                    close: ")".into(),
+                }
+            }
+            Exp::Vector(v) => match self.label(v) {
+                Err(reference) => Doc::Atom(reference),
+                Ok(prefix) => Doc::List {
+                    open: format!("{}#(", prefix),
+                    items: v.borrow().iter().map(|item| self.doc(item)).collect(),
+                    close: ")".into(),
+                },
+            },
+            _ => Doc::Atom(self.print(exp)),
+        }
+    }
+}
+
+/// Pretty print a datum with `write` within the given width, starting at the given column.
+pub fn pretty_print(exp: &Exp, col: usize, width: usize) -> String {
+    let doc = Printer::new(exp, Style::Write, Labels::Cycles).doc(exp);
+    layout(&doc, col, width)
+}
@@ -1,31 +1,194 @@
-use crate::eval_apply::eval;
-use crate::parser::InPort;
-use crate::types::{Env, RcRefCell};
+use crate::compile::image::{self, MAGIC};
+use crate::compile::{compile, Code};
+use crate::interp::Interp;
+use crate::interpreter::STDLIB;
+use crate::parser::{expand_includes, expand_located, InFile, InPort, InStr, Location};
+use crate::printer;
+use crate::types::{make_env_ptr, Env, ErrKind, Exp, RcRefCell, ScmErr, Symbol};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 use std::rc::Rc;
 
+/// The way top-level forms are evaluated.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    /// The tree-walking interpreter in `eval_apply`, the reference implementation.
+    Eval,
+    /// The bytecode compiler and the stack VM.
+    Vm,
+}
+
+/// Run a REPL until the end of the input or a call to `exit`, returning the exit code.
+/// The results are written to the current output port of the interpreter,
+/// and pretty printed within the given width if any.
 pub fn repl(
     inport: &mut impl InPort,
-    outport: &mut impl std::io::Write,
-    env: &RcRefCell<Env>,
-) -> Result<(), std::io::Error> {
-    let global_env = Rc::clone(env);
+    interp: &mut Interp,
+    pretty: Option<usize>,
+) -> Result<i32, std::io::Error> {
+    let to_io = |e: ScmErr| std::io::Error::other(e.to_string());
     loop {
         let next_token = inport.next_token();
-        match next_token {
+        let line = match next_token {
             Ok(None) => break,
             Ok(Some(token_str)) => match inport.read_exp(Ok(Some(token_str))) {
                 Ok(exp) => {
-                    let val = eval(exp, Rc::clone(&global_env));
+                    // Forget about a Ctrl-C pressed before the evaluation.
+                    interp.interrupt.clear();
+                    let val = interp.eval(exp);
                     match val {
-                        Ok(exp) => writeln!(outport, "=> {}", exp)?,
-                        Err(e) => writeln!(outport, "Error: {:?}", e)?,
+                        // The unspecified value of definitions and the like isn't shown.
+                        Ok(Exp::Empty) => String::new(),
+                        Ok(exp) => match pretty {
+                            Some(width) => {
+                                format!("=> {}\n", printer::pretty_print(&exp, 3, width))
+                            }
+                            None => format!("=> {}\n", printer::write(&exp)),
+                        },
+                        Err(e) => match e.kind() {
+                            ErrKind::Exit(code) => {
+                                interp.ports.flush().map_err(to_io)?;
+                                return Ok(code);
+                            }
+                            _ => format!("Error: {:?}\n", e),
+                        },
                     }
-                    // writeln!(outport, "=> {:?}", val)?;
                 }
-                Err(e) => writeln!(outport, "Error: {:?}", e)?,
+                Err(e) => format!("Error: {:?}\n", e),
             },
             Err(e) => break eprintln!("Readline Error: {:?}", e),
+        };
+        let outport = &interp.ports.output;
+        outport
+            .write_str(&line)
+            .and_then(|_| outport.flush())
+            .map_err(to_io)?;
+    }
+    Ok(0)
+}
+
+/// Evaluate every form of a source file, returning the value of the last one.
+/// Relative paths are resolved against the file while it is loaded,
+/// and errors are prefixed with the path and the line of the file.
+pub fn load_file(path: &Path, interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let mut inport = InFile::new(&path.to_string_lossy())
+        .map_err(|e| ScmErr::from(&format!("`{}`: {}", path.display(), e)))?;
+    let outer = interp.file.replace(path.into());
+    let mut res = Ok(Exp::Empty);
+    loop {
+        let token = match inport.next_token() {
+            Ok(None) => break,
+            token => token,
+        };
+        // The line of the first token of the form.
+        let mut at = (path.to_path_buf(), inport.line_number());
+        res = inport
+            .read_exp(token)
+            .and_then(|exp| eval_located(exp, interp, &mut at));
+        if let Err(e) = res {
+            let (path, line) = at;
+            res = Err(match e.kind() {
+                ErrKind::Error => ScmErr::from(&format!("`{}`:{}: {}", path.display(), line, e)),
+                _ => e,
+            });
+            break;
         }
     }
+    interp.file = outer;
+    res
+}
+
+/// Evaluate a top-level form, one included form at a time,
+/// setting `at` to the location of the included form being evaluated.
+fn eval_located(exp: Exp, interp: &mut Interp, at: &mut Location) -> Result<Exp, ScmErr> {
+    // The sandbox has no access to the files.
+    if interp.sandbox.is_some() {
+        return interp.eval(exp);
+    }
+    let mut res = Ok(Exp::Empty);
+    for (exp, location) in expand_located(exp, &interp.dir())? {
+        if let Some(location) = location {
+            *at = location;
+        }
+        res = interp.eval(exp);
+        if res.is_err() {
+            break;
+        }
+    }
+    res
+}
+
+/// Compile every top-level form read from the port, without running them.
+/// Included files are resolved against `dir`, and compiled in as well.
+pub fn compile_all(
+    inport: &mut impl InPort,
+    env: &RcRefCell<Env>,
+    dir: &Path,
+) -> Result<Vec<Rc<Code>>, ScmErr> {
+    let mut res = Vec::new();
+    loop {
+        match inport.next_token() {
+            Ok(None) => break Ok(res),
+            token => {
+                let exp = expand_includes(inport.read_exp(token)?, dir)?;
+                check_libraries(&exp)?;
+                res.push(compile(&exp, env)?);
+            }
+        }
+    }
+}
+
+/// Libraries are defined and imported by the interpreter when the forms are evaluated,
+/// so an image can't hold them.
+fn check_libraries(exp: &Exp) -> Result<(), ScmErr> {
+    if let Exp::List(list) = exp {
+        match list.first() {
+            Some(&Exp::Symbol(s @ Symbol::IMPORT))
+            | Some(&Exp::Symbol(s @ Symbol::DEFINE_LIBRARY)) => {
+                return Err(ScmErr::from(&format!(
+                    "{}: libraries can't be compiled into an image",
+                    s
+                )))
+            }
+            Some(Exp::Symbol(Symbol::BEGIN)) => {
+                return list[1..].iter().try_for_each(check_libraries)
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Check if a file is a compiled image, from its first bytes.
+pub fn is_image_file(path: &Path) -> bool {
+    let mut magic = Vec::new();
+    let read =
+        File::open(path).and_then(|file| file.take(MAGIC.len() as u64).read_to_end(&mut magic));
+    read.is_ok() && image::is_image(&magic)
+}
+
+/// Compile a source file into an image.
+/// If `stdlib` is set, the bundled standard library is compiled in before the file,
+/// so that the image can be run without loading it.
+pub fn compile_file(
+    input: &str,
+    output: &Path,
+    stdlib: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The Env is only used to resolve the global variables, which are stored by name in the image.
+    let env = make_env_ptr(Env::new());
+    let mut codes = Vec::new();
+    if stdlib {
+        codes = compile_all(&mut InStr::new(STDLIB), &env, Path::new(""))
+            .map_err(|e| format!("stdlib: {}", e))?;
+    }
+    let mut inport = InFile::new(input)?;
+    let dir = Path::new(input).parent().unwrap_or_else(|| Path::new(""));
+    codes.extend(
+        compile_all(&mut inport, &env, dir).map_err(|e| format!("`{}`: {}", inport.file_str, e))?,
+    );
+    let mut file = File::create(output)?;
+    image::write_image(&codes, &mut file)?;
     Ok(())
 }
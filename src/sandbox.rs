@@ -0,0 +1,135 @@
+use crate::gc::{self, Account};
+use crate::types::*;
+
+// * Sandboxed evaluation
+//
+// A sandbox bounds the resources an evaluation can use, so that untrusted code can't hang
+// or crash the host. Exceeding a limit aborts the evaluation with an error of kind `ErrKind::Limit`.
+//
+// The heap objects allocated by a sandbox are charged to its account, whatever else runs on the thread,
+// and the Lists and Strings returned by primitives are counted by their size.
+// When the count goes over the limit, a collection forgets the dead objects, and the data is measured again
+// in the global variables and the live objects. The next collection waits for at least a quarter of the limit
+// to be allocated, so the count can go over the limit by that much before the evaluation is aborted.
+
+/// The resource limits of a sandbox. `None` means unlimited.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    /// The number of evaluation steps allowed, in total.
+    /// A step is an analyzed node in the tree-walking backend, and an instruction in the VM.
+    pub fuel: Option<u64>,
+    /// The maximum depth of nested procedure calls.
+    /// The tree-walking backend calls procedures on the Rust stack, so this also protects it from overflowing.
+    pub max_depth: Option<usize>,
+    /// The maximum number of objects on the garbage-collected heap
+    /// (Frames, cells, Closures, Vectors and hash tables) allocated by the sandbox,
+    /// plus the size of its Lists and Strings: one per List item, and one per 8 bytes of a String.
+    pub max_heap: Option<usize>,
+}
+
+/// The state of a sandbox.
+pub struct Sandbox {
+    limits: Limits,
+    fuel_left: u64,
+    depth: usize,
+    account: Account,
+    /// The size of the Lists and Strings allocated, as measured at the last collection
+    /// and counted since then.
+    data: usize,
+    /// The count above which the next collection is run.
+    next_collection: usize,
+}
+
+impl Sandbox {
+    pub fn new(limits: Limits) -> Self {
+        Sandbox {
+            limits,
+            fuel_left: limits.fuel.unwrap_or(0),
+            depth: 0,
+            account: Account::default(),
+            data: 0,
+            next_collection: limits.max_heap.unwrap_or(0),
+        }
+    }
+
+    /// The fuel left, if the fuel is limited.
+    pub fn fuel_left(&self) -> Option<u64> {
+        self.limits.fuel.map(|_| self.fuel_left)
+    }
+
+    /// The account to charge the heap objects to, if the heap is limited.
+    pub(crate) fn account(&self) -> Option<Account> {
+        self.limits.max_heap.map(|_| self.account.clone())
+    }
+
+    /// The heap objects and the size of the data allocated by the sandbox, as far as they are known.
+    fn heap(&self) -> usize {
+        self.account.count() + self.data
+    }
+
+    /// Take one evaluation step, given the global Environment to measure the data in.
+    pub fn step(&mut self, env: &RcRefCell<Env>) -> Result<(), ScmErr> {
+        if self.limits.fuel.is_some() {
+            if self.fuel_left == 0 {
+                return Err(ScmErr::limit(Limit::Fuel));
+            }
+            self.fuel_left -= 1;
+        }
+        if let Some(max_heap) = self.limits.max_heap {
+            if self.heap() > self.next_collection {
+                // Dead objects are only forgotten by a collection, so collect before giving up.
+                gc::collect();
+                let globals = env.try_borrow().map_or(0, |env| {
+                    env.values()
+                        .map(|value| value.as_ref().map_or(0, gc::data_size))
+                        .sum()
+                });
+                self.data = globals + gc::data_held(&self.account);
+                if self.heap() > max_heap {
+                    return Err(ScmErr::limit(Limit::Heap));
+                }
+                self.next_collection = max_heap.max(self.heap() + max_heap / 4);
+            }
+        }
+        Ok(())
+    }
+
+    /// Count the Lists and Strings in a value returned by a primitive,
+    /// failing at once if it is larger than the heap limit.
+    pub fn allocate(&mut self, exp: &Exp) -> Result<(), ScmErr> {
+        if let Some(max_heap) = self.limits.max_heap {
+            let size = gc::data_size(exp);
+            if size > max_heap {
+                return Err(ScmErr::limit(Limit::Heap));
+            }
+            self.data += size;
+        }
+        Ok(())
+    }
+
+    /// Enter a procedure call, failing if the calls are nested too deeply.
+    pub fn enter(&mut self) -> Result<(), ScmErr> {
+        match self.limits.max_depth {
+            Some(max_depth) if self.depth >= max_depth => Err(ScmErr::limit(Limit::Depth)),
+            _ => {
+                self.depth += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Leave a procedure call.
+    pub fn leave(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// The current call depth.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Restore the call depth after an evaluation has been aborted.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+}
@@ -0,0 +1,138 @@
+use crate::convert::list_to_vec;
+use crate::types::*;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// * Serde support for Exp
+//
+// Data is mapped to the serde data model as follows:
+// integral Numbers are serialized as `i64` and the others as `f64`,
+// Strings and Symbols as strings, Chars as chars, Lists and Vectors as sequences, and hash tables as maps.
+// `Empty` is serialized as unit.
+//
+// Improper Lists, procedures, ports and the end-of-file object cannot be serialized.
+// When deserializing, sequences become Vectors and strings become Strings.
+
+impl Serialize for Exp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Exp::Bool(b) => serializer.serialize_bool(*b),
+            &Exp::Number(n) if n.fract() == 0.0 && n.abs() <= i64::MAX as f64 => {
+                serializer.serialize_i64(n as i64)
+            }
+            Exp::Number(n) => serializer.serialize_f64(*n),
+            Exp::String(s) => serializer.serialize_str(s),
+            Exp::Symbol(s) => serializer.serialize_str(s.as_str()),
+            Exp::Char(c) => serializer.serialize_char(*c),
+            // A List is serialized with its items, and can't be improper.
+            Exp::List(_) => match list_to_vec(self) {
+                Ok(items) => serialize_seq(serializer, &items),
+                Err(_) => Err(ser::Error::custom(format!(
+                    "serialize: cannot serialize the improper List {}",
+                    self
+                ))),
+            },
+            Exp::Vector(v) => serialize_seq(serializer, &v.borrow()),
+            Exp::HashTable(t) => {
+                let t = t.borrow();
+                let mut map = serializer.serialize_map(Some(t.len()))?;
+                for (k, v) in t.iter() {
+                    map.serialize_entry(&k.to_exp(), v)?;
+                }
+                map.end()
+            }
+            Exp::Empty => serializer.serialize_unit(),
+            Exp::Closure(_)
+            | Exp::Compiled(_)
+            | Exp::Primitive(_)
+            | Exp::Native(_)
+            | Exp::Port(_)
+            | Exp::Environment(_)
+            | Exp::Eof => Err(ser::Error::custom(format!(
+                "serialize: cannot serialize {}",
+                self
+            ))),
+        }
+    }
+}
+
+fn serialize_seq<S: Serializer>(serializer: S, items: &[Exp]) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(items.len()))?;
+    for item in items {
+        seq.serialize_element(item)?;
+    }
+    seq.end()
+}
+
+impl<'de> Deserialize<'de> for Exp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ExpVisitor)
+    }
+}
+
+struct ExpVisitor;
+
+impl<'de> Visitor<'de> for ExpVisitor {
+    type Value = Exp;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a Scheme datum")
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Exp, E> {
+        Ok(Exp::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Exp, E> {
+        Ok(Exp::Number(n as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Exp, E> {
+        Ok(Exp::Number(n as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Exp, E> {
+        Ok(Exp::Number(n))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Exp, E> {
+        Ok(Exp::String(s.into()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Exp, E> {
+        Ok(Exp::vector(
+            bytes.iter().map(|&b| Exp::Number(b as f64)).collect(),
+        ))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Exp, E> {
+        Ok(Exp::Empty)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Exp, E> {
+        Ok(Exp::Empty)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Exp, D::Error> {
+        Exp::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Exp, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Exp::vector(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Exp, A::Error> {
+        let mut table = BTreeMap::new();
+        while let Some((k, v)) = map.next_entry::<Exp, Exp>()? {
+            let key = HashKey::from_exp(&k).map_err(de::Error::custom)?;
+            table.insert(key, v);
+        }
+        Ok(Exp::hash_table(table))
+    }
+}
@@ -1,61 +1,89 @@
 #[cfg(test)]
 mod helper {
-    use crate::eval_apply::eval;
-    use crate::parser::InPort;
-    use crate::prelude::{get_prelude, make_env_ptr};
+    use crate::compile::compile;
+    use crate::compile::image::{read_image, write_image};
+    use crate::interp::Interp;
+    use crate::parser::{InPort, InStr};
+    use crate::prelude::{get_prelude, get_restricted_prelude, make_env_ptr};
+    use crate::run::Backend;
+    use crate::sandbox::{Limits, Sandbox};
     use crate::types::*;
-    use std::cell::RefCell;
-    use std::error::Error;
-    use std::rc::Rc;
+    use crate::vm;
 
-    struct MockInput<'a> {
-        line: Option<String>,
-        lines: RefCell<std::str::Lines<'a>>,
-    }
+    pub const BACKENDS: [Backend; 2] = [Backend::Eval, Backend::Vm];
 
-    impl<'a> MockInput<'a> {
-        fn new(input: &'a str) -> Self {
-            MockInput {
-                line: Some("".into()),
-                lines: RefCell::new(input.lines()),
+    pub fn check_io_str(input: &str, output: &str, interp: &mut Interp) {
+        // let str_exp = input.to_string();
+        let mut mock = InStr::new(input);
+        let right = output.to_string();
+        let left = match mock.read_next_exp() {
+            Ok(exp) => {
+                let val = interp.eval(exp);
+                format!("{:?}", val)
             }
-        }
+            Err(e) => format!("Error: {:?}", e),
+        };
+        assert_eq!(left, right, "backend: {:?}", interp.backend);
     }
 
-    impl<'a> InPort for MockInput<'a> {
-        fn line(&self) -> Option<String> {
-            self.line.clone()
-        }
+    pub fn check_io_with(backend: Backend, pairs: &[(&str, &str)]) {
+        check_io_in(get_prelude(), backend, pairs);
+    }
+
+    /// Check the pairs in the given global Env.
+    pub fn check_io_in(env: Env, backend: Backend, pairs: &[(&str, &str)]) {
+        let mut interp = Interp::new(make_env_ptr(env), backend);
+        pairs
+            .iter()
+            .for_each(|(i, o)| check_io_str(i, o, &mut interp));
+    }
 
-        fn set_line(&mut self, new_line: Option<String>) {
-            self.line = new_line
+    /// Check the pairs in a sandbox with the given limits, against every backend.
+    pub fn check_sandboxed(limits: Limits, pairs: &[(&str, &str)]) {
+        for &backend in BACKENDS.iter() {
+            let mut interp = Interp::new(make_env_ptr(get_restricted_prelude()), backend);
+            interp.sandbox = Some(Sandbox::new(limits));
+            pairs
+                .iter()
+                .for_each(|(i, o)| check_io_str(i, o, &mut interp));
         }
+    }
 
-        fn read_line(&self) -> Result<Option<String>, Box<dyn Error>> {
-            match self.lines.borrow_mut().next() {
-                Some(line) => Ok(Some(line.into())),
-                None => Ok(None),
-            }
+    /// Check the pairs after compiling the inputs into an image and loading it into a fresh Env.
+    pub fn check_image(pairs: &[(&str, &str)]) {
+        let compile_env = make_env_ptr(Env::new());
+        let codes: Vec<_> = pairs
+            .iter()
+            .map(|(i, _)| {
+                let exp = InStr::new(i).read_next_exp().unwrap();
+                compile(&exp, &compile_env).unwrap()
+            })
+            .collect();
+        let mut bytes = Vec::new();
+        write_image(&codes, &mut bytes).unwrap();
+
+        let mut interp = Interp::new(make_env_ptr(get_prelude()), Backend::Vm);
+        let codes = read_image(&bytes, &interp.env).unwrap();
+        for (code, (_, o)) in codes.into_iter().zip(pairs.iter()) {
+            assert_eq!(format!("{:?}", vm::run(code, &mut interp)), o.to_string());
         }
     }
 
-    fn check_io_str(input: &str, output: &str, env: &RcRefCell<Env>) {
-        // let str_exp = input.to_string();
-        let mut mock = MockInput::new(input);
-        let right = output.to_string();
-        let left = match mock.read_next_exp() {
-            Ok(exp) => {
-                let val = eval(exp, Rc::clone(env));
-                format!("{:?}", val)
-            }
-            Err(e) => format!("Error: {:?}", e),
-        };
-        assert_eq!(left, right);
+    /// Make a temporary directory with the given files, and a `lib` subdirectory.
+    pub fn files(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rusk-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        for (file, src) in files {
+            std::fs::write(dir.join(file), src).unwrap();
+        }
+        dir
     }
 
+    /// Check the pairs against every backend, so that the VM is tested against the reference `eval`.
     pub fn check_io(pairs: Vec<(&str, &str)>) {
-        let env = make_env_ptr(get_prelude());
-        pairs.iter().for_each(|(i, o)| check_io_str(i, o, &env));
+        for &backend in BACKENDS.iter() {
+            check_io_with(backend, &pairs);
+        }
     }
 }
 
@@ -75,13 +103,13 @@ mod basics {
 
     #[test]
     fn quote() {
-        check_io(vec![("(quote (1 2 3))", "Ok([1, 2, 3])")]);
+        check_io(vec![("(quote (1 2 3))", "Ok((1 2 3))")]);
     }
 
     #[test]
     fn define_val() {
         check_io(vec![
-            ("(define x 3)", "Ok()"),
+            ("(define x 3)", "Ok(#<unspecified>)"),
             ("x", "Ok(3)"),
             ("(+ x 1)", "Ok(4)"),
         ]);
@@ -90,9 +118,9 @@ mod basics {
     #[test]
     fn define_proc_basic() {
         check_io(vec![
-            ("(define x 3)", "Ok()"),
+            ("(define x 3)", "Ok(#<unspecified>)"),
             ("x", "Ok(3)"),
-            ("(define one (lambda () 1))", "Ok()"),
+            ("(define one (lambda () 1))", "Ok(#<unspecified>)"),
             ("(one)", "Ok(1)"),
             ("(+ (one) (+ 2 x))", "Ok(6)"),
         ]);
@@ -101,9 +129,9 @@ mod basics {
     #[test]
     fn define_proc_call_prim() {
         check_io(vec![
-            ("(define x 3)", "Ok()"),
+            ("(define x 3)", "Ok(#<unspecified>)"),
             ("x", "Ok(3)"),
-            ("(define inc (lambda (x) (+ x 1)))", "Ok()"),
+            ("(define inc (lambda (x) (+ x 1)))", "Ok(#<unspecified>)"),
             ("(inc 100)", "Ok(101)"),
             ("(inc x)", "Ok(4)"),
         ]);
@@ -122,26 +150,52 @@ mod basics {
     #[test]
     fn eq() {
         check_io(vec![
-            ("(define one (lambda () 1))", "Ok()"),
-            ("(= 1 1)", "Ok(true)"),
-            ("(= 1 (one))", "Ok(true)"),
+            ("(define one (lambda () 1))", "Ok(#<unspecified>)"),
+            ("(= 1 1)", "Ok(#t)"),
+            ("(= 1 (one))", "Ok(#t)"),
             ("(if (= 1 (one)) 123 wtf)", "Ok(123)"),
             ("(if (= (one) (+ 4 5)) wtf 123)", "Ok(123)"),
         ]);
     }
 
+    #[test]
+    fn eq_symbol() {
+        check_io(vec![
+            ("(eq? (quote foo) (quote foo))", "Ok(#t)"),
+            ("(eq? (quote foo) (quote bar))", "Ok(#f)"),
+            ("(eq? (quote foo) 1)", "Ok(#f)"),
+        ]);
+    }
+
+    #[test]
+    fn string() {
+        check_io(vec![
+            (r#""hello, world""#, r#"Ok("hello, world")"#),
+            (r#""tab\tquote\"\x41;""#, r#"Ok("tab\tquote\"A")"#),
+            (r#"(define s "a (b) ;c")"#, "Ok(#<unspecified>)"),
+            ("s", r#"Ok("a (b) ;c")"#),
+            (r#""\q""#, r#"Error: parser: invalid escape \q in string"#),
+        ]);
+    }
+
     #[test]
     fn cons_car_cdr() {
         check_io(vec![
             ("(car (cons 123 456))", "Ok(123)"),
             ("(cdr (cons 123 456))", "Ok(456)"),
-            ("(define p (cons (cons 1 2) (cons 3 4)))", "Ok()"),
+            (
+                "(define p (cons (cons 1 2) (cons 3 4)))",
+                "Ok(#<unspecified>)",
+            ),
             ("(cdr (car p))", "Ok(2)"),
-            ("(cdr p)", "Ok([3, 4])"),
-            ("p", "Ok([[1, 2], [3, 4]])"),
-            ("(define l (cons 1 (cons 2 (cons 3 null))))", "Ok()"),
+            ("(cdr p)", "Ok((3 . 4))"),
+            ("p", "Ok(((1 . 2) 3 . 4))"),
+            (
+                "(define l (cons 1 (cons 2 (cons 3 null))))",
+                "Ok(#<unspecified>)",
+            ),
             ("(car (cdr l))", "Ok(2)"),
-            ("(cdr (cdr (cdr l)))", "Ok([])"),
+            ("(cdr (cdr (cdr l)))", "Ok(())"),
         ]);
     }
 
@@ -214,7 +268,7 @@ mod sugar {
                 "(define (add3 x y z)
                     (+ x
                        (+ y z)))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
             (
                 "(add3 101 
@@ -233,11 +287,16 @@ mod sugar {
                     (quote whatever)
                     (define one (lambda () 1))
                     (+ (one) 2))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
             ("(three)", "Ok(3)"),
         ]);
     }
+
+    #[test]
+    fn sugar_quote_untouched() {
+        check_io(vec![("(quote (define (f x) x))", "Ok((define (f x) x))")]);
+    }
 }
 
 #[cfg(test)]
@@ -247,11 +306,11 @@ mod environment {
     #[test]
     fn set_simple() {
         check_io(vec![
-            ("(define inc (lambda (x) (+ x 1)))", "Ok()"),
-            ("(define x 3)", "Ok()"),
-            ("(set! x (inc x))", "Ok()"),
+            ("(define inc (lambda (x) (+ x 1)))", "Ok(#<unspecified>)"),
+            ("(define x 3)", "Ok(#<unspecified>)"),
+            ("(set! x (inc x))", "Ok(#<unspecified>)"),
             ("x", "Ok(4)"),
-            ("(set! x (inc x))", "Ok()"),
+            ("(set! x (inc x))", "Ok(#<unspecified>)"),
             ("x", "Ok(5)"),
         ]);
     }
@@ -266,9 +325,9 @@ mod environment {
                             (begin 
                                 (set! bal (+ bal amt)) 
                                 bal))))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
-            ("(define a1 (account 100))", "Ok()"),
+            ("(define a1 (account 100))", "Ok(#<unspecified>)"),
             ("(a1 0)", "Ok(100)"),
             ("(a1 10)", "Ok(110)"),
             ("(a1 10)", "Ok(120)"),
@@ -276,6 +335,120 @@ mod environment {
     }
 }
 
+#[cfg(test)]
+mod scope {
+    use super::helper::check_io;
+
+    #[test]
+    fn shadowing() {
+        check_io(vec![
+            ("(define x 1)", "Ok(#<unspecified>)"),
+            ("(define (f x) (+ x 10))", "Ok(#<unspecified>)"),
+            ("(f 2)", "Ok(12)"),
+            ("x", "Ok(1)"),
+            ("((lambda (x) ((lambda (x) x) 3)) 2)", "Ok(3)"),
+        ]);
+    }
+
+    #[test]
+    fn nested_access() {
+        check_io(vec![
+            (
+                "(define (adder a)
+                    (lambda (b)
+                        (lambda (c) (+ a (+ b c)))))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(((adder 1) 10) 100)", "Ok(111)"),
+        ]);
+    }
+
+    #[test]
+    fn internal_define() {
+        check_io(vec![
+            ("(define y 1)", "Ok(#<unspecified>)"),
+            (
+                "(define (f x)
+                    (define y (* x 2))
+                    (set! x (+ x y))
+                    x)",
+                "Ok(#<unspecified>)",
+            ),
+            ("(f 5)", "Ok(15)"),
+            ("y", "Ok(1)"),
+        ]);
+    }
+
+    #[test]
+    fn internal_mutual_recursion() {
+        check_io(vec![
+            (
+                "(define (parity n)
+                    (define (even? n) (if (= n 0) (quote even) (odd? (- n 1))))
+                    (define (odd? n) (if (= n 0) (quote odd) (even? (- n 1))))
+                    (even? n))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(parity 10)", "Ok(even)"),
+            ("(parity 7)", "Ok(odd)"),
+        ]);
+    }
+
+    #[test]
+    fn internal_defined_later() {
+        check_io(vec![
+            (
+                "(define (f)
+                    (define (g) (h))
+                    (define h-early (g))
+                    h-early)",
+                "Ok(#<unspecified>)",
+            ),
+            ("(f)", "Err(eval: Symbol h undefined)"),
+            (
+                "(define (f)
+                    (define (g) (h))
+                    (define (h) 42)
+                    (g))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(f)", "Ok(42)"),
+        ]);
+    }
+
+    #[test]
+    fn shared_counter() {
+        check_io(vec![
+            (
+                "(define (make-counter)
+                    (define n 0)
+                    (define (inc) (set! n (+ n 1)) n)
+                    (define (get) n)
+                    (cons inc (cons get null)))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(define c (make-counter))", "Ok(#<unspecified>)"),
+            ("((car c))", "Ok(1)"),
+            ("((car c))", "Ok(2)"),
+            ("((car (cdr c)))", "Ok(2)"),
+            ("(define d (make-counter))", "Ok(#<unspecified>)"),
+            ("((car d))", "Ok(1)"),
+            ("((car (cdr c)))", "Ok(2)"),
+            ("((car (cdr d)))", "Ok(1)"),
+        ]);
+    }
+
+    #[test]
+    fn global_defined_later() {
+        check_io(vec![
+            ("(define (f) (g))", "Ok(#<unspecified>)"),
+            ("(f)", "Err(eval: Symbol g undefined)"),
+            ("(define (g) 42)", "Ok(#<unspecified>)"),
+            ("(f)", "Ok(42)"),
+        ]);
+    }
+}
+
 #[cfg(test)]
 mod general {
     use super::helper::check_io;
@@ -283,21 +456,24 @@ mod general {
     #[test]
     fn sqrt_200() {
         check_io(vec![
-            ("(define (abs x) (if (>= x 0) x (- 0 x)))", "Ok()"),
+            (
+                "(define (abs x) (if (>= x 0) x (- 0 x)))",
+                "Ok(#<unspecified>)",
+            ),
             (
                 "(define (newton guess function derivative epsilon)
                     (define guess2 (- guess (/ (function guess) (derivative guess))))
                     (if (< (abs (- guess guess2)) epsilon) guess2
                         (newton guess2 function derivative epsilon)))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
             (
                 "(define (square-root a)
                     (newton 1 (lambda (x) (- (* x x) a)) (lambda (x) (* 2 x)) 1e-8))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
-            ("(> (square-root 200) 14.14213)", "Ok(true)"),
-            ("(< (square-root 200) 14.14215)", "Ok(true)"),
+            ("(> (square-root 200) 14.14213)", "Ok(#t)"),
+            ("(< (square-root 200) 14.14215)", "Ok(#t)"),
         ]);
     }
 
@@ -306,7 +482,7 @@ mod general {
         check_io(vec![
             (
                 "(define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
             (
                 "(fib 20)", 
@@ -314,20 +490,19 @@ mod general {
             ),
             (
                 "(define range (lambda (a b) (if (= a b) (quote ()) (cons a (range (+ a 1) b)))))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
             (
                 "(define map (lambda (f l) (if (null? l) null (cons (f (car l)) (map f (cdr l))))))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
             (
                 "(range 0 10)",
-                "Ok([0, [1, [2, [3, [4, [5, [6, [7, [8, [9, []]]]]]]]]]])",
+                "Ok((0 1 2 3 4 5 6 7 8 9))",
             ),
             (
                 "(map fib (range 0 10))",
-                "Ok([1, [1, [2, [3, [5, [8, [13, [21, [34, [55, \
-                 []]]]]]]]]]])",
+                "Ok((1 1 2 3 5 8 13 21 34 55))",
             ),
         ]);
     }
@@ -339,7 +514,7 @@ mod general {
                 "(define A (lambda (k x1 x2 x3 x4 x5) 
                     (define B (lambda () (set! k (- k 1)) (A k B x1 x2 x3 x4))) 
                     (if (<= k 0) (+ (x4) (x5)) (B))))",
-                "Ok()",
+                "Ok(#<unspecified>)",
             ),
             (
                 "(A 4 (lambda () 1) (lambda () -1) (lambda () -1) (lambda () 1) (lambda () 0))",
@@ -350,48 +525,1478 @@ mod general {
 }
 
 #[cfg(test)]
-mod big {
+mod vm {
+    use super::helper::{check_image, check_io_with};
+    use crate::compile::image::{read_image, write_image};
+    use crate::compile::{Capture, Code, Op};
+    use crate::run::Backend;
+    use crate::types::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn tail_call() {
+        // The tree-walking `eval` would overflow the stack here.
+        check_io_with(
+            Backend::Vm,
+            &[
+                (
+                    "(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(count 100000 0)", "Ok(100000)"),
+            ],
+        );
+    }
+
+    #[test]
+    fn tail_call_mutual() {
+        check_io_with(
+            Backend::Vm,
+            &[
+                (
+                    "(define (even? n) (if (= n 0) #t (odd? (- n 1))))",
+                    "Ok(#<unspecified>)",
+                ),
+                (
+                    "(define (odd? n) (if (= n 0) #f (even? (- n 1))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(even? 100001)", "Ok(#f)"),
+            ],
+        );
+    }
+
+    #[test]
+    fn image_roundtrip() {
+        check_image(&[
+            ("(define (inc x) (+ x 1))", "Ok(#<unspecified>)"),
+            ("(inc 41)", "Ok(42)"),
+            (
+                "(define account
+                    (lambda (bal)
+                        (lambda (amt) (set! bal (+ bal amt)) bal)))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(define a1 (account 100))", "Ok(#<unspecified>)"),
+            ("(a1 10)", "Ok(110)"),
+            ("(a1 10)", "Ok(120)"),
+            ("(cond (#f 1) (else (quote (1 foo))))", "Ok((1 foo))"),
+            (r#""caf\xe9;""#, r#"Ok("café")"#),
+            ("#(1 (quote a))", "Ok(#(1 (quote a)))"),
+            (
+                "(undefined-thing)",
+                "Err(eval: Symbol undefined-thing undefined)",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn image_validation() {
+        let code = |ops: Vec<Op>, upvals: Vec<(Symbol, Capture)>, protos: Vec<Rc<Code>>| {
+            Rc::new(Code {
+                params: Vec::new(),
+                locals: Vec::new(),
+                cells: Vec::new(),
+                upvals,
+                ops,
+                consts: vec![Exp::Number(1.0)],
+                protos,
+                globals: Vec::new(),
+            })
+        };
+        let load = |code: Rc<Code>| {
+            let mut bytes = Vec::new();
+            write_image(&[code], &mut bytes).unwrap();
+            read_image(&bytes, &make_env_ptr(Env::new())).map(|_| ())
+        };
+        let x = Symbol::intern("x");
+        let proto = |capture| {
+            code(
+                vec![Op::UpvalGet(0), Op::Return],
+                vec![(x, capture)],
+                vec![],
+            )
+        };
+
+        assert!(load(code(vec![Op::Const(0), Op::Return], vec![], vec![])).is_ok());
+        let invalid = vec![
+            // Stack underflows.
+            code(vec![Op::Return], vec![], vec![]),
+            code(
+                vec![Op::Const(0), Op::Pop, Op::Pop, Op::Const(0), Op::Return],
+                vec![],
+                vec![],
+            ),
+            code(vec![Op::Const(0), Op::Call(1), Op::Return], vec![], vec![]),
+            // The branches of a conditional leave different depths.
+            code(
+                vec![
+                    Op::Const(0),
+                    Op::JumpIfFalse(4, Symbol::IF),
+                    Op::Const(0),
+                    Op::Const(0),
+                    Op::Const(0),
+                    Op::Return,
+                ],
+                vec![],
+                vec![],
+            ),
+            // A tail call with other operands below the function.
+            code(
+                vec![Op::Const(0), Op::Const(0), Op::TailCall(0), Op::Return],
+                vec![],
+                vec![],
+            ),
+            // Captures out of the range of the enclosing code object.
+            code(
+                vec![Op::Closure(0), Op::Return],
+                vec![],
+                vec![proto(Capture::Cell(0))],
+            ),
+            code(
+                vec![Op::Closure(0), Op::Return],
+                vec![],
+                vec![proto(Capture::Upval(3))],
+            ),
+            // A top-level form with captures.
+            proto(Capture::Upval(0)),
+        ];
+        for code in invalid {
+            assert_eq!(
+                load(code).unwrap_err().to_string(),
+                "image: invalid code object"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod native {
+    use super::helper::{check_io, check_io_in, BACKENDS};
+    use crate::prelude::get_prelude;
+    use crate::types::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn sort() {
+        check_io(vec![
+            (
+                "(define l (cons 3 (cons 1 (cons 2 null))))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(sort l <)", "Ok((1 2 3))"),
+            ("(sort l (lambda (a b) (> a b)))", "Ok((3 2 1))"),
+            ("(sort null <)", "Ok(())"),
+            (
+                "(sort l (lambda (a b) (undefined-thing)))",
+                "Err(eval: Symbol undefined-thing undefined)",
+            ),
+            ("(sort (quote (3 1 2)) <)", "Ok((1 2 3))"),
+            ("(sort (quote (2 1)) <)", "Ok((1 2))"),
+            ("(sort l)", "Err(sort: expected 2 arguments, got 1)"),
+        ]);
+    }
+
+    #[test]
+    fn apply() {
+        check_io(vec![
+            ("(apply + (cons 1 (cons 2 null)))", "Ok(3)"),
+            ("(apply + 1 2 (cons 3 null))", "Ok(6)"),
+            ("(apply + (quote (1 2 3)))", "Ok(6)"),
+            ("(apply + 1 (quote (2 3)))", "Ok(6)"),
+            ("(apply + (quote ()))", "Ok(0)"),
+            (
+                "(apply + (quote (1 . 2)))",
+                "Err(apply: expected a List, got (1 . 2))",
+            ),
+            (
+                "(apply (lambda (x y) (- x y)) (cons 3 (cons 1 null)))",
+                "Ok(2)",
+            ),
+            (
+                "(apply +)",
+                "Err(apply: expected at least 2 arguments, got 1)",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn stateful() {
+        for &backend in BACKENDS.iter() {
+            let count = Rc::new(Cell::new(0.0));
+            let mut env = get_prelude();
+            let counter = Rc::clone(&count);
+            env.define(
+                Symbol::intern("tick!"),
+                Exp::native("tick!", Arity::Exactly(0), move |_, _| {
+                    counter.set(counter.get() + 1.0);
+                    Ok(Exp::Number(counter.get()))
+                }),
+            );
+            check_io_in(
+                env,
+                backend,
+                &[
+                    ("(tick!)", "Ok(1)"),
+                    ("(define (twice f) (f) (f))", "Ok(#<unspecified>)"),
+                    ("(twice tick!)", "Ok(3)"),
+                    ("tick!", "Ok(#<procedure tick!>)"),
+                ],
+            );
+            assert_eq!(count.get(), 3.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod exit {
     use super::helper::check_io;
+    use crate::interp::Interp;
+    use crate::parser::InStr;
+    use crate::prelude::{get_prelude, make_env_ptr};
+    use crate::run::{repl, Backend};
+    use crate::types::*;
+    use std::rc::Rc;
 
     #[test]
-    #[ignore]
-    fn fibonacci_long() {
+    fn exit() {
         check_io(vec![
+            ("(exit)", "Err(exit: exited with code 0)"),
+            ("(exit #f)", "Err(exit: exited with code 1)"),
+            ("(if (exit 2) 1 2)", "Err(exit: exited with code 2)"),
+            ("(exit (quote foo))", "Err(exit: invalid exit code)"),
             (
-                "(define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))",
-                "Ok()",
+                "(emergency-exit (quote foo))",
+                "Err(emergency-exit: invalid exit code)",
             ),
+        ]);
+    }
+
+    #[test]
+    fn dynamic_wind() {
+        check_io(vec![
+            ("(define log 0)", "Ok(#<unspecified>)"),
             (
-                "(define range (lambda (a b) (if (= a b) (quote ()) (cons a (range (+ a 1) b)))))",
-                "Ok()",
+                "(dynamic-wind
+                    (lambda () (set! log 1))
+                    (lambda () (* log 2))
+                    (lambda () (set! log (+ log 10))))",
+                "Ok(2)",
             ),
+            ("log", "Ok(11)"),
             (
-                "(define map (lambda (f l) (if (null? l) null (cons (f (car l)) (map f (cdr l))))))",
-                "Ok()",
+                "(dynamic-wind
+                    (lambda () (set! log 1))
+                    (lambda () (exit 4) (set! log 100))
+                    (lambda () (set! log (+ log 10))))",
+                "Err(exit: exited with code 4)",
+            ),
+            ("log", "Ok(11)"),
+        ]);
+    }
+
+    #[test]
+    fn repl_exit() {
+        let mut interp = Interp::new(make_env_ptr(get_prelude()), Backend::Eval);
+        let out = Rc::new(Port::output_string());
+        interp.ports.output = Rc::clone(&out);
+        let mut input = InStr::new("(+ 1 2) (display \"hi\") (exit 5) (+ 3 4)");
+        let code = repl(&mut input, &mut interp, None).unwrap();
+        assert_eq!(code, 5);
+        assert_eq!(out.output_string_contents().unwrap(), "=> 3\nhi");
+    }
+}
+
+#[cfg(test)]
+mod interrupt {
+    use super::helper::{check_io_in, BACKENDS};
+    use crate::prelude::get_prelude;
+    use crate::types::*;
+
+    #[test]
+    fn interrupt() {
+        for &backend in BACKENDS.iter() {
+            let mut env = get_prelude();
+            env.define(
+                Symbol::intern("press-ctrl-c!"),
+                Exp::native("press-ctrl-c!", Arity::Exactly(0), |_, interp| {
+                    interp.interrupt.interrupt();
+                    Ok(Exp::Empty)
+                }),
+            );
+            check_io_in(
+                env,
+                backend,
+                &[
+                    ("(define x 1)", "Ok(#<unspecified>)"),
+                    ("(define (loop) (loop))", "Ok(#<unspecified>)"),
+                    ("(begin (press-ctrl-c!) (loop))", "Err(interrupted)"),
+                    ("(define y (begin (press-ctrl-c!) 2))", "Err(interrupted)"),
+                    ("(+ x 1)", "Ok(2)"),
+                    ("y", "Err(eval: Symbol y undefined)"),
+                ],
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod collections {
+    use super::helper::check_io;
+
+    #[test]
+    fn vector() {
+        check_io(vec![
+            ("(define v (make-vector 3 0))", "Ok(#<unspecified>)"),
+            ("(vector-set! v 1 (quote a))", "Ok(#<unspecified>)"),
+            ("v", "Ok(#(0 a 0))"),
+            ("(vector-ref v 1)", "Ok(a)"),
+            ("(vector-length (vector 1 2))", "Ok(2)"),
+            ("(vector? #(1 2))", "Ok(#t)"),
+            ("(vector->list #(1 2))", "Ok((1 2))"),
+            ("(list->vector (cons 1 null))", "Ok(#(1))"),
+            ("(list->vector (quote (1 2 3)))", "Ok(#(1 2 3))"),
+            ("(list->vector (quote (a ())))", "Ok(#(a ()))"),
+            ("(list->vector (quote ()))", "Ok(#())"),
+            ("(vector-ref v 3)", "Err(vector-ref: index 3 out of range)"),
+        ]);
+    }
+
+    #[test]
+    fn hash_table() {
+        check_io(vec![
+            ("(define t (make-hash-table))", "Ok(#<unspecified>)"),
+            ("(hash-table-set! t \"b\" 2)", "Ok(#<unspecified>)"),
+            ("(hash-table-set! t (quote a) 1)", "Ok(#<unspecified>)"),
+            ("(hash-table-ref t (quote a))", "Ok(1)"),
+            ("(hash-table-ref t 42 (lambda () 0))", "Ok(0)"),
+            ("(hash-table-ref/default t 42 #f)", "Ok(#f)"),
+            ("(hash-table-contains? t \"b\")", "Ok(#t)"),
+            ("(hash-table-count t)", "Ok(2)"),
+            ("(hash-table-keys t)", "Ok((\"b\" a))"),
+            ("(hash-table-delete! t \"b\")", "Ok(#<unspecified>)"),
+            ("(hash-table->alist t)", "Ok(((a . 1)))"),
+            ("(eq? t t)", "Ok(#t)"),
+            (
+                "(hash-table-ref t 42)",
+                "Err(hash-table-ref: key 42 not found)",
             ),
             (
-                "(map fib (range 0 20))",
-                "Ok([1, [1, [2, [3, [5, [8, [13, [21, [34, [55, [89, [144, \
-                 [233, [377, [610, [987, [1597, [2584, [4181, [6765, \
-                 []]]]]]]]]]]]]]]]]]]]])",
+                "(hash-table-set! t (vector) 1)",
+                "Err(hash-table: #() cannot be used as a key)",
             ),
         ]);
     }
+}
+
+#[cfg(test)]
+mod json {
+    use super::helper::check_io;
 
     #[test]
-    #[ignore]
-    fn man_or_boy_long() {
+    fn read() {
         check_io(vec![
             (
-                "(define A (lambda (k x1 x2 x3 x4 x5) 
-                    (define B (lambda () (set! k (- k 1)) (A k B x1 x2 x3 x4))) 
-                    (if (<= k 0) (+ (x4) (x5)) (B))))",
-                "Ok()",
+                r#"(string->json "{\"a\": [1, 2.5e1, true], \"b\": null}")"#,
+                "Ok(((a . #(1 25 #t)) (b . null)))",
             ),
             (
-                "(A 10 (lambda () 1) (lambda () -1) (lambda () -1) (lambda () 1) (lambda () 0))",
-                "Ok(-67)",
+                r#"(string->json "\"\\u00e9\\ud83d\\ude00\"")"#,
+                r#"Ok("é😀")"#,
+            ),
+            (r#"(json-null? (string->json "null"))"#, "Ok(#t)"),
+            (
+                r#"(define t (string->json "{\"k\": {}}" (quote hash-table)))"#,
+                "Ok(#<unspecified>)",
+            ),
+            ("(hash-table-count (hash-table-ref t (quote k)))", "Ok(0)"),
+        ]);
+    }
+
+    #[test]
+    fn read_error() {
+        check_io(vec![
+            (
+                r#"(string->json "[1,\n 2,]")"#,
+                "Err(json: unexpected character ']' at line 2, column 4)",
+            ),
+            (
+                r#"(string->json "{\"a\" 1}")"#,
+                "Err(json: unexpected character '1' at line 1, column 6)",
+            ),
+            (
+                r#"(string->json "01")"#,
+                "Err(json: unexpected character '1' at line 1, column 2)",
+            ),
+            (
+                r#"(string->json "[1")"#,
+                "Err(json: expected ',' or ']' at line 1, column 3)",
+            ),
+            (
+                r#"(string->json "[1e400]")"#,
+                "Err(json: number out of range at line 1, column 7)",
+            ),
+            (
+                r#"(string->json "-1e400")"#,
+                "Err(json: number out of range at line 1, column 7)",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn write() {
+        check_io(vec![
+            (
+                r#"(json->string (vector 1 1.5 "a\"b" #f (quote null)))"#,
+                r#"Ok("[1,1.5,\"a\\\"b\",false,null]")"#,
+            ),
+            (
+                "(json->string (cons (cons (quote a) (vector)) null))",
+                r#"Ok("{\"a\":[]}")"#,
+            ),
+            (
+                r#"(json->string (string->json "{\"x\":{\"y\":[null]}}"))"#,
+                r#"Ok("{\"x\":{\"y\":[null]}}")"#,
+            ),
+            ("(json->string null)", r#"Ok("{}")"#),
+            (
+                "(json->string car)",
+                "Err(json: cannot write #<procedure> as JSON)",
             ),
         ]);
     }
 }
+
+#[cfg(test)]
+mod ports {
+    use super::helper::check_io;
+
+    #[test]
+    fn string_port() {
+        check_io(vec![
+            ("(define p (open-output-string))", "Ok(#<unspecified>)"),
+            (r#"(display "x = " p)"#, "Ok(#<unspecified>)"),
+            ("(display 42 p)", "Ok(#<unspecified>)"),
+            ("(newline p)", "Ok(#<unspecified>)"),
+            ("(get-output-string p)", r#"Ok("x = 42\n")"#),
+            ("(output-port? p)", "Ok(#t)"),
+            ("(input-port? p)", "Ok(#f)"),
+            (
+                r#"(call-with-output-string (lambda (p) (write-string "hi" p) (display 1 p)))"#,
+                r#"Ok("hi1")"#,
+            ),
+            ("(port? 1)", "Ok(#f)"),
+        ]);
+    }
+
+    #[test]
+    fn current_output_port() {
+        check_io(vec![
+            (
+                "(define stdout (current-output-port))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(define p (open-output-string))", "Ok(#<unspecified>)"),
+            ("(current-output-port p)", "Ok(#<unspecified>)"),
+            (r#"(display "captured")"#, "Ok(#<unspecified>)"),
+            ("(json-write (vector 1 2))", "Ok(#<unspecified>)"),
+            ("(current-output-port stdout)", "Ok(#<unspecified>)"),
+            ("(get-output-string p)", r#"Ok("captured[1,2]")"#),
+            (
+                "(current-output-port (open-input-string \"\"))",
+                "Err(current-output-port: expected an output port)",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn input_port() {
+        check_io(vec![
+            (
+                r#"(define p (open-input-string "[1, 2] {\"a\": 3}"))"#,
+                "Ok(#<unspecified>)",
+            ),
+            ("(json-read p)", "Ok(#(1 2))"),
+            ("(json-read p)", "Ok(((a . 3)))"),
+            (
+                "(json-read (open-input-bytevector (vector 116 114 117 101)))",
+                "Ok(#t)",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn read() {
+        check_io(vec![
+            (
+                r#"(define p (open-input-string "(a #(1 \"s\") #\\x) ; comment\n  42 rest\n(b"))"#,
+                "Ok(#<unspecified>)",
+            ),
+            ("(read p)", r#"Ok((a #(1 "s") #\x))"#),
+            ("(read p)", "Ok(42)"),
+            ("(read-line p)", r#"Ok(" rest")"#),
+            ("(read p)", "Err(parser: Unexpected EOF)"),
+            ("(eof-object? (read p))", "Ok(#t)"),
+            (
+                "(read (open-input-string \"(define (f) . 1)\"))",
+                "Ok((define (f) . 1))",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn read_char() {
+        check_io(vec![
+            (
+                r#"(define p (open-input-string "ab\ncd\n\nlast"))"#,
+                "Ok(#<unspecified>)",
+            ),
+            ("(peek-char p)", r"Ok(#\a)"),
+            ("(read-char p)", r"Ok(#\a)"),
+            ("(read-line p)", r#"Ok("b")"#),
+            ("(read-string 4 p)", r#"Ok("cd\n\n")"#),
+            ("(char-ready? p)", "Ok(#t)"),
+            ("(read-line p)", r#"Ok("last")"#),
+            ("(eof-object? (read-line p))", "Ok(#t)"),
+            ("(eof-object? (read-char p))", "Ok(#t)"),
+            ("(eof-object? (eof-object))", "Ok(#t)"),
+            ("(eq? (peek-char p) (eof-object))", "Ok(#t)"),
+            (
+                r#"(eq? #\space (read-char (open-input-string " ")))"#,
+                "Ok(#t)",
+            ),
+            (r"#\x41", r"Ok(#\A)"),
+            (r#"(read-char (open-input-string "\n"))"#, r"Ok(#\newline)"),
+        ]);
+    }
+
+    #[test]
+    fn bytevector_port() {
+        check_io(vec![
+            ("(define p (open-output-bytevector))", "Ok(#<unspecified>)"),
+            (r#"(write-string "é" p)"#, "Ok(#<unspecified>)"),
+            ("(get-output-bytevector p)", "Ok(#(195 169))"),
+        ]);
+    }
+
+    #[test]
+    fn close() {
+        check_io(vec![
+            ("(define p (open-output-string))", "Ok(#<unspecified>)"),
+            ("(close-port p)", "Ok(#<unspecified>)"),
+            (
+                r#"(display "x" p)"#,
+                "Err(port: <output port string> is closed)",
+            ),
+            ("(close-output-port p)", "Ok(#<unspecified>)"),
+        ]);
+    }
+
+    #[test]
+    fn file_port() {
+        let path = std::env::temp_dir().join(format!("rusk-ports-{}.txt", std::process::id()));
+        let path_str = format!("{:?}", path.to_str().unwrap());
+        let write = format!(
+            r#"(with-output-to-file {} (lambda () (display "in file") (json-write null)))"#,
+            path_str
+        );
+        let read = format!("(json-read (open-input-file {}))", path_str);
+        check_io(vec![
+            (&write, "Ok(#<unspecified>)"),
+            (
+                &read,
+                "Err(json: unexpected character 'i' at line 1, column 1)",
+            ),
+        ]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "in file{}");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn file_port_close_error() {
+        // Writing to /dev/full only fails when the output is flushed, as the file is closed.
+        check_io(vec![
+            (
+                r#"(with-output-to-file "/dev/full" (lambda () (display "x") (car 1)))"#,
+                "Err(car: expected a List)",
+            ),
+            (
+                r#"(with-output-to-file "/dev/full" (lambda () (display "x")))"#,
+                "Err(`/dev/full`: No space left on device (os error 28))",
+            ),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod printer {
+    use super::helper::check_io;
+
+    #[test]
+    fn write() {
+        check_io(vec![
+            (
+                "(define (w x) (call-with-output-string (lambda (p) (write x p))))",
+                "Ok(#<unspecified>)",
+            ),
+            (r#"(w "a \"b\"\n")"#, r#"Ok("\"a \\\"b\\\"\\n\"")"#),
+            (r"(w #\a)", r##"Ok("#\\a")"##),
+            (r"(w #\space)", r##"Ok("#\\space")"##),
+            ("(w (cons 1 (cons 2 null)))", r#"Ok("(1 2)")"#),
+            ("(w (cons 1 2))", r#"Ok("(1 . 2)")"#),
+            ("(w (quote (a (b c d) #t)))", r#"Ok("(a (b c d) #t)")"#),
+            ("(w (quote (a b)))", r#"Ok("(a b)")"#),
+            ("(w (quote ((1 2) (3 4))))", r#"Ok("((1 2) (3 4))")"#),
+            ("(w (quote (1 (2) . 3)))", r#"Ok("(1 (2) . 3)")"#),
+            ("(w #(a (b c)))", r##"Ok("#(a (b c))")"##),
+            ("(w (vector 1.5 (quote x) null))", r##"Ok("#(1.5 x ())")"##),
+            ("(w (/ 1 0))", r#"Ok("+inf.0")"#),
+            ("(w car)", r##"Ok("#<procedure>")"##),
+            ("(w (eof-object))", r##"Ok("#<eof>")"##),
+        ]);
+    }
+
+    #[test]
+    fn display() {
+        check_io(vec![
+            (
+                "(define (d x) (call-with-output-string (lambda (p) (display x p))))",
+                "Ok(#<unspecified>)",
+            ),
+            (r#"(d (cons "a" (cons #\b null)))"#, r#"Ok("(a b)")"#),
+        ]);
+    }
+
+    #[test]
+    fn round_trip() {
+        check_io(vec![
+            (
+                "(define (w x) (call-with-output-string (lambda (p) (write x p))))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                r#"(define x (cons 1 (cons "two\t" (cons (vector #\x (cons 3 4)) null))))"#,
+                "Ok(#<unspecified>)",
+            ),
+            ("(equal? x (read (open-input-string (w x))))", "Ok(#t)"),
+            ("(equal? (quote (1 . 2)) (cons 1 2))", "Ok(#t)"),
+            ("(equal? (quote (1 2)) (cons 1 (cons 2 null)))", "Ok(#t)"),
+            ("(equal? (quote (1 2)) (cons 1 2))", "Ok(#f)"),
+            (
+                r#"(equal? (quote ((1 2) (3 4))) (read (open-input-string "((1 2) (3 4))")))"#,
+                "Ok(#t)",
+            ),
+            (
+                r#"(equal? (read (open-input-string "(1 2)")) (cons 1 (cons 2 null)))"#,
+                "Ok(#t)",
+            ),
+            ("(equal? (vector 1 2) (vector 1 3))", "Ok(#f)"),
+        ]);
+    }
+
+    #[test]
+    fn pretty_print() {
+        check_io(vec![
+            (
+                "(define (pp x) (call-with-output-string (lambda (p) (pretty-print x p))))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(pp (quote (a b c)))", r#"Ok("(a b c)\n")"#),
+            (
+                "(pp (quote (let ((a 1) (b 2)) (+ a b))))",
+                r#"Ok("(let ((a 1) (b 2)) (+ a b))\n")"#,
+            ),
+            (
+                "(pp (quote (let ((first-long-name (car xs)) (second-long-name 2)) (some-long-function first-long-name second-long-name))))",
+                r#"Ok("(let ((first-long-name (car xs)) (second-long-name 2))\n  (some-long-function first-long-name second-long-name))\n")"#,
+            ),
+            (
+                "(define (range a b) (if (>= a b) null (cons a (range (+ a 1) b))))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                "(pp (range 0 40))",
+                r#"Ok("(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28\n 29 30 31 32 33 34 35 36 37 38 39)\n")"#,
+            ),
+            (
+                r#"(pp (read (open-input-string "(define (f long-argument-name) (let loop ((i 0)) (when (< i long-argument-name) (display-the-number i) (loop (+ i 1)))))")))"#,
+                r#"Ok("(define (f long-argument-name)\n  (let loop ((i 0))\n    (when (< i long-argument-name) (display-the-number i) (loop (+ i 1)))))\n")"#,
+            ),
+            (
+                r#"(pp (read (open-input-string "(some-function (first-argument-is-long 1 2 3) (second-argument-is-long 4 5 6 7 8 9))")))"#,
+                r#"Ok("(some-function (first-argument-is-long 1 2 3)\n               (second-argument-is-long 4 5 6 7 8 9))\n")"#,
+            ),
+        ]);
+    }
+
+    #[test]
+    fn labels() {
+        check_io(vec![
+            ("(define v (vector 1 2))", "Ok(#<unspecified>)"),
+            ("(vector-set! v 1 v)", "Ok(#<unspecified>)"),
+            (
+                "(call-with-output-string (lambda (p) (write v p)))",
+                r##"Ok("#0=#(1 #0#)")"##,
+            ),
+            ("v", "Ok(#0=#(1 #0#))"),
+            ("(define u (vector 0))", "Ok(#<unspecified>)"),
+            ("(define w (vector u u))", "Ok(#<unspecified>)"),
+            (
+                "(call-with-output-string (lambda (p) (write w p)))",
+                r##"Ok("#(#(0) #(0))")"##,
+            ),
+            (
+                "(call-with-output-string (lambda (p) (write-shared w p)))",
+                r##"Ok("#(#0=#(0) #0#)")"##,
+            ),
+            (
+                "(call-with-output-string (lambda (p) (write-simple w p)))",
+                r##"Ok("#(#(0) #(0))")"##,
+            ),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod formatter {
+    use crate::formatter::{format_file, format_source};
+
+    #[test]
+    fn layout() {
+        let src = "(define (f x)\n    (if (null? x)  null\n  (cons 1 x)))\n(define g (lambda (a b c) (f (list a b c a b c a b c) (list a b c a b c a b c))))";
+        let res = "(define (f x) (if (null? x) null (cons 1 x)))\n(define g\n  (lambda (a b c) (f (list a b c a b c a b c) (list a b c a b c a b c))))\n";
+        assert_eq!(format_source(src).unwrap(), res);
+        assert_eq!(format_source(res).unwrap(), res);
+    }
+
+    #[test]
+    fn trivia() {
+        let src = ";; header\n\n\n(define (f x) ; f\n  ;; body\n  (g x   'y \"a ; b\" #\\)))\n#| block |#\n";
+        let res =
+            ";; header\n\n(define (f x) ; f\n  ;; body\n  (g x 'y \"a ; b\" #\\)))\n#| block |#\n";
+        assert_eq!(format_source(src).unwrap(), res);
+        assert_eq!(format_source(res).unwrap(), res);
+        // The closing parenthesis can't be commented out.
+        assert_eq!(format_source("(f x ; x\n)").unwrap(), "(f x ; x\n   )\n");
+    }
+
+    #[test]
+    fn error() {
+        assert_eq!(
+            format_source("(f\n(g x)").unwrap_err().to_string(),
+            "fmt: unclosed \"(\" from line 1"
+        );
+        assert_eq!(
+            format_source("(f))").unwrap_err().to_string(),
+            "fmt: unexpected \")\" at line 1"
+        );
+    }
+
+    #[test]
+    fn shebang() {
+        assert_eq!(
+            format_source("#!/usr/bin/env rusk\n\n(f  x)").unwrap(),
+            "#!/usr/bin/env rusk\n(f x)\n"
+        );
+        assert_eq!(
+            format_source("#!/usr/bin/env rusk\n\n(f  x)\n(g")
+                .unwrap_err()
+                .to_string(),
+            "fmt: unclosed \"(\" from line 4"
+        );
+        assert_eq!(
+            format_source("#!/usr/bin/env rusk").unwrap(),
+            "#!/usr/bin/env rusk\n"
+        );
+    }
+
+    #[test]
+    fn check() {
+        let path = std::env::temp_dir().join(format!("rusk-fmt-{}.scm", std::process::id()));
+        std::fs::write(&path, "(f  x)").unwrap();
+        assert!(!format_file(&path, true).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "(f  x)");
+        assert!(!format_file(&path, false).unwrap());
+        assert!(format_file(&path, true).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "(f x)\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod load {
+    use super::helper::{check_io, files};
+    use std::fs;
+
+    #[test]
+    fn load() {
+        let dir = files(
+            "load",
+            &[
+                ("main.scm", "(load \"lib/util.scm\") (define y (sq x))"),
+                (
+                    "lib/util.scm",
+                    "(define (sq x) (* x x))\n(load \"value.scm\")",
+                ),
+                ("lib/value.scm", "(define x 7)"),
+            ],
+        );
+        let load = format!("(load {:?})", dir.join("main.scm"));
+        check_io(vec![(&load, "Ok(#<unspecified>)"), ("y", "Ok(49)")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn environment() {
+        let dir = files("load-env", &[("lib/x.scm", "(define x 42)")]);
+        let load = format!(
+            "(load {:?} (interaction-environment))",
+            dir.join("lib/x.scm")
+        );
+        check_io(vec![(&load, "Ok(#<unspecified>)"), ("x", "Ok(42)")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include() {
+        let dir = files(
+            "include",
+            &[
+                (
+                    "main.scm",
+                    "(define (f y) (include \"lib/body.scm\") (+ y z))",
+                ),
+                ("lib/body.scm", "(include \"z.scm\")"),
+                ("lib/z.scm", "(define z 1)"),
+                ("lib/ci.scm", "(DEFINE Shout (QUOTE HeLLo))"),
+                ("lib/self.scm", "(include \"./self.scm\")"),
+                ("lib/a.scm", "(include \"b.scm\")"),
+                ("lib/b.scm", "(include \"../lib/a.scm\")"),
+            ],
+        );
+        let load = format!("(load {:?})", dir.join("main.scm"));
+        let include_ci = format!("(include-ci {:?})", dir.join("lib/ci.scm"));
+        let include_self = format!("(include {:?})", dir.join("lib/self.scm"));
+        let include_a = format!("(include {:?})", dir.join("lib/a.scm"));
+        let self_err = format!(
+            "Err(`{}`:1: include: `{}` includes itself)",
+            dir.join("lib/self.scm").display(),
+            dir.join("lib/./self.scm").display()
+        );
+        let a_err = format!(
+            "Err(`{}`:1: `{}`:1: include: `{}` includes itself)",
+            dir.join("lib/a.scm").display(),
+            dir.join("lib/b.scm").display(),
+            dir.join("lib/../lib/a.scm").display()
+        );
+        check_io(vec![
+            (&load, "Ok(#<unspecified>)"),
+            ("(f 2)", "Ok(3)"),
+            (&include_ci, "Ok(#<unspecified>)"),
+            ("shout", "Ok(hello)"),
+            (&include_self, &self_err),
+            (&include_a, &a_err),
+            (
+                "(quote (include \"none.scm\"))",
+                "Ok((include \"none.scm\"))",
+            ),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn error() {
+        let dir = files(
+            "load-error",
+            &[
+                ("main.scm", "(define x 1)\n\n(load \"lib/bad.scm\")"),
+                ("lib/bad.scm", "(define y 2)\n(car x)"),
+                ("lib/unclosed.scm", "(define y 2)\n(car x"),
+                (
+                    "includer.scm",
+                    "(define w 1)\n(include \"lib/included.scm\")",
+                ),
+                ("lib/included.scm", "(define v 3)\n\n(car v)"),
+            ],
+        );
+        let main = dir.join("main.scm");
+        let load = format!("(load {:?})", main);
+        let err = format!(
+            "Err(`{}`:3: `{}`:2: car: expected a List)",
+            main.display(),
+            dir.join("lib/bad.scm").display()
+        );
+        let unclosed = dir.join("lib/unclosed.scm");
+        let include = format!("(include {:?})", unclosed);
+        let parse_err = format!("Err(`{}`:2: parser: Unexpected EOF)", unclosed.display());
+        let load_includer = format!("(load {:?})", dir.join("includer.scm"));
+        let included_err = format!(
+            "Err(`{}`:3: car: expected a List)",
+            dir.join("lib/included.scm").display()
+        );
+        check_io(vec![
+            (&load, &err),
+            ("y", "Ok(2)"),
+            (&include, &parse_err),
+            (&load_includer, &included_err),
+            ("v", "Ok(3)"),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod library {
+    use super::helper::{check_io, check_sandboxed, files};
+    use crate::prelude::{get_prelude, LIBRARIES};
+    use crate::sandbox::Limits;
+    use crate::types::Symbol;
+    use std::fs;
+
+    #[test]
+    fn define_library() {
+        check_io(vec![
+            (
+                "(define-library (util) (export twice (rename helper add))
+                   (import (scheme base))
+                   (begin (define (helper x y) (+ x y)) (define (twice x) (helper x x))))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(import (util))", "Ok(#<unspecified>)"),
+            ("(twice 21)", "Ok(42)"),
+            ("(add 1 2)", "Ok(3)"),
+            ("helper", "Err(eval: Symbol helper undefined)"),
+            // Redefining an imported variable leaves the library alone.
+            ("(define (add x y) (* x y))", "Ok(#<unspecified>)"),
+            ("(cons (add 2 3) (twice 3))", "Ok((6 . 6))"),
+        ]);
+    }
+
+    #[test]
+    fn own_environment() {
+        check_io(vec![
+            (
+                "(define-library (a) (export x) (begin (define x 1)))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                "(define-library (b) (export y) (begin (define y (+ 1 1))))",
+                "Err((b): eval: Symbol + undefined)",
+            ),
+            (
+                "(define-library (c) (export z) (import (scheme base)) (begin (define y 1)))",
+                "Err(define-library: (c) exports z, which is undefined)",
+            ),
+            (
+                "(define-library (d) (export w) (exports))",
+                "Err((d): unknown declaration (exports))",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn import_sets() {
+        check_io(vec![
+            (
+                "(import (prefix (only (scheme base) car cdr) l:) (rename (scheme base) (cons kons)))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(l:car (kons 1 2))", "Ok(1)"),
+            (
+                "(define-library (e) (export first) (import (except (scheme base) cdr))
+                   (begin (define (first l) (car l)) (define (rest l) (cdr l))))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(import (only (e) rest))", "Err(import: rest isn't in the import set (e))"),
+            ("(import (nowhere))", "Err(import: library (nowhere) not found)"),
+            (r#"(import ("e"))"#, r#"Err(library: invalid name ("e"))"#),
+        ]);
+    }
+
+    #[test]
+    fn files_and_cycles() {
+        let dir = files(
+            "library",
+            &[
+                (
+                    "main.scm",
+                    "(import (lib count)) (import (lib count) (lib twice)) (define res (twice counted))",
+                ),
+                (
+                    "lib/count.sld",
+                    "(define-library (lib count) (export counted) (import (scheme base))
+                       (begin (define counted 0)))
+                     (define-library (lib side) (export bump) (import (scheme base) (lib count))
+                       (begin (define (bump) (set! counted (+ counted 1)))))",
+                ),
+                (
+                    "lib/twice.sld",
+                    "(define-library (lib twice) (export twice) (import (scheme base) (lib side))
+                       (begin (bump) (define (twice x) (* 2 x))))",
+                ),
+                ("cycle.scm", "(import (lib a))"),
+                (
+                    "lib/a.sld",
+                    "(define-library (lib a) (export a) (import (lib b)) (begin (define a 1)))",
+                ),
+                (
+                    "lib/b.sld",
+                    "(define-library (lib b) (export b) (import (lib a)) (begin (define b 1)))",
+                ),
+            ],
+        );
+        let main = dir.join("main.scm");
+        let load = format!("(load {:?})", main);
+        let cycle = dir.join("cycle.scm");
+        let import = format!("(load {:?})", cycle);
+        let err = format!(
+            "Err(`{}`:1: `{}`:1: (lib a): `{}`:1: (lib b): import: circular import (lib a) -> (lib b) -> (lib a))",
+            cycle.display(),
+            dir.join("lib/a.sld").display(),
+            dir.join("lib/b.sld").display()
+        );
+        check_io(vec![
+            (&load, "Ok(#<unspecified>)"),
+            // `(lib count)` has been loaded once, and `(lib side)` bumped its counter.
+            ("res", "Ok(2)"),
+            ("counted", "Ok(1)"),
+            (&import, &err),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sandbox() {
+        check_sandboxed(
+            Limits::default(),
+            &[
+                ("(import (only (scheme base) car))", "Ok(#<unspecified>)"),
+                (
+                    "(import (only (scheme file) open-input-file))",
+                    "Err(import: open-input-file isn't in the import set (scheme file))",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn standard_libraries() {
+        let prelude = get_prelude();
+        for (_, names) in LIBRARIES {
+            for name in names.iter() {
+                assert!(prelude.lookup(Symbol::intern(name)).is_some(), "{}", name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod process_context {
+    use super::helper::{check_io, files};
+    use std::fs;
+
+    #[test]
+    fn shebang() {
+        let dir = files(
+            "shebang",
+            &[
+                ("script.scm", "#!/usr/bin/env rusk\n(define x 1)\n(car x)"),
+                ("spaced.scm", "#! /usr/bin/env rusk\n(define y 2)"),
+                ("directive.scm", "#!fold-case\n(define z 3)"),
+            ],
+        );
+        let script = dir.join("script.scm");
+        let load = format!("(load {:?})", script);
+        let err = format!("Err(`{}`:3: car: expected a List)", script.display());
+        let load_spaced = format!("(load {:?})", dir.join("spaced.scm"));
+        // A directive on the first line isn't taken for a shebang.
+        let directive = dir.join("directive.scm");
+        let load_directive = format!("(load {:?})", directive);
+        let directive_err = format!(
+            "Err(`{}`:1: parser: unsupported directive #!fold-case)",
+            directive.display()
+        );
+        check_io(vec![
+            (&load, &err),
+            ("x", "Ok(1)"),
+            (&load_spaced, "Ok(#<unspecified>)"),
+            ("y", "Ok(2)"),
+            (&load_directive, &directive_err),
+            ("z", "Err(eval: Symbol z undefined)"),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn environment_variables() {
+        std::env::set_var("RUSK_TEST_VARIABLE", "value");
+        check_io(vec![
+            (
+                r#"(get-environment-variable "RUSK_TEST_VARIABLE")"#,
+                r#"Ok("value")"#,
+            ),
+            (r#"(get-environment-variable "RUSK_TEST_UNSET")"#, "Ok(#f)"),
+            ("(get-environment-variable 1)", "Err(get-environment-variable: expected a String)"),
+            (
+                "(define (lookup key alist) (if (null? alist) #f (if (equal? (car (car alist)) key) (cdr (car alist)) (lookup key (cdr alist)))))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                r#"(lookup "RUSK_TEST_VARIABLE" (get-environment-variables))"#,
+                r#"Ok("value")"#,
+            ),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod sandbox {
+    use super::helper::{check_io_str, check_sandboxed, BACKENDS};
+    use crate::gc;
+    use crate::interp::Interp;
+    use crate::prelude::{get_prelude, get_restricted_prelude, make_env_ptr};
+    use crate::run::Backend;
+    use crate::sandbox::{Limits, Sandbox};
+
+    #[test]
+    fn fuel() {
+        let limits = Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        };
+        check_sandboxed(
+            limits,
+            &[
+                ("(define (loop n) (loop (+ n 1)))", "Ok(#<unspecified>)"),
+                ("(+ 1 2)", "Ok(3)"),
+                ("(loop 0)", "Err(sandbox: out of fuel)"),
+                ("(+ 1 2)", "Err(sandbox: out of fuel)"),
+            ],
+        );
+    }
+
+    #[test]
+    fn depth() {
+        let limits = Limits {
+            max_depth: Some(100),
+            ..Limits::default()
+        };
+        check_sandboxed(
+            limits,
+            &[
+                (
+                    "(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1)))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(count 50)", "Ok(50)"),
+                (
+                    "(count 1000000)",
+                    "Err(sandbox: maximum recursion depth exceeded)",
+                ),
+                // The depth is restored after an aborted evaluation.
+                ("(count 50)", "Ok(50)"),
+            ],
+        );
+    }
+
+    #[test]
+    fn heap() {
+        let limits = Limits {
+            max_depth: Some(1000),
+            max_heap: Some(300),
+            ..Limits::default()
+        };
+        check_sandboxed(
+            limits,
+            &[
+                (
+                    "(define (hoard n acc) (if (= n 0) acc (hoard (- n 1) (vector acc))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(vector-length (hoard 50 #f))", "Ok(1)"),
+                ("(hoard 1000 #f)", "Err(sandbox: heap limit exceeded)"),
+                // Garbage doesn't count towards the limit.
+                (
+                    "(define (cycle) (define v (vector 0)) (vector-set! v 0 v))",
+                    "Ok(#<unspecified>)",
+                ),
+                (
+                    "(define (churn n) (if (= n 0) 0 (begin (cycle) (churn (- n 1)))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(churn 200)", "Ok(0)"),
+            ],
+        );
+    }
+
+    #[test]
+    fn heap_data() {
+        let limits = Limits {
+            max_heap: Some(100),
+            ..Limits::default()
+        };
+        check_sandboxed(
+            limits,
+            &[
+                (
+                    "(define (grow l n) (if (= n 0) 0 (grow (cons l l) (- n 1))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(grow 1 5)", "Ok(0)"),
+                ("(grow 1 24)", "Err(sandbox: heap limit exceeded)"),
+                (
+                    "(define (double s) (call-with-output-string (lambda (p) (write-string s p) (write-string s p))))",
+                    "Ok(#<unspecified>)",
+                ),
+                (
+                    "(define (grow-string s n) (if (= n 0) 0 (grow-string (double s) (- n 1))))",
+                    "Ok(#<unspecified>)",
+                ),
+                (r#"(grow-string "ab" 5)"#, "Ok(0)"),
+                (
+                    r#"(grow-string "ab" 30)"#,
+                    "Err(sandbox: heap limit exceeded)",
+                ),
+                // Lists which are kept alive are still counted after a collection.
+                (
+                    "(define (range a b) (if (= a b) null (cons a (range (+ a 1) b))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(define v (make-vector 10 #f))", "Ok(#<unspecified>)"),
+                (
+                    "(define (fill i) (if (= i 10) 0 (begin (vector-set! v i (range 0 30)) (fill (+ i 1)))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(fill 0)", "Err(sandbox: heap limit exceeded)"),
+            ],
+        );
+    }
+
+    #[test]
+    fn heap_per_sandbox() {
+        // The objects of other interpreters don't count towards the limit.
+        let mut other = Interp::new(make_env_ptr(get_prelude()), Backend::Vm);
+        check_io_str(
+            "(define (hoard n acc) (if (= n 0) acc (hoard (- n 1) (vector acc))))",
+            "Ok(#<unspecified>)",
+            &mut other,
+        );
+        check_io_str(
+            "(define kept (hoard 1000 #f))",
+            "Ok(#<unspecified>)",
+            &mut other,
+        );
+
+        let limits = Limits {
+            max_heap: Some(300),
+            ..Limits::default()
+        };
+        // With many live objects, collections are still only run once in a while.
+        for &backend in BACKENDS.iter() {
+            let mut interp = Interp::new(make_env_ptr(get_restricted_prelude()), backend);
+            interp.sandbox = Some(Sandbox::new(limits));
+            check_io_str(
+                "(define (hoard n acc) (if (= n 0) acc (hoard (- n 1) (vector acc))))",
+                "Ok(#<unspecified>)",
+                &mut interp,
+            );
+            check_io_str(
+                "(define kept (hoard 120 #f))",
+                "Ok(#<unspecified>)",
+                &mut interp,
+            );
+            check_io_str(
+                "(define (count n) (if (= n 0) 0 (count (- n 1))))",
+                "Ok(#<unspecified>)",
+                &mut interp,
+            );
+            check_io_str(
+                "(define (repeat n) (if (= n 0) 0 (begin (count 100) (repeat (- n 1)))))",
+                "Ok(#<unspecified>)",
+                &mut interp,
+            );
+            let before = gc::stats().collections;
+            check_io_str("(repeat 10)", "Ok(0)", &mut interp);
+            assert!(gc::stats().collections - before < 100);
+        }
+    }
+
+    #[test]
+    fn restricted_prelude() {
+        check_sandboxed(
+            Limits::default(),
+            &[("(exit)", "Err(eval: Symbol exit undefined)")],
+        );
+    }
+}
+
+#[cfg(test)]
+mod gc {
+    use super::helper::{check_io, check_io_with, BACKENDS};
+    use crate::gc;
+
+    #[test]
+    fn collect_cycle() {
+        check_io(vec![
+            (
+                "(define (make-cycle) (define self (lambda () self)) self)",
+                "Ok(#<unspecified>)",
+            ),
+            ("(make-cycle)", "Ok(#<procedure>)"),
+            ("(> (gc) 0)", "Ok(#t)"),
+            ("(gc)", "Ok(0)"),
+        ]);
+    }
+
+    #[test]
+    fn collect_vector_cycle() {
+        check_io(vec![
+            (
+                "(define (f) (define v (vector 0)) (vector-set! v 0 v))",
+                "Ok(#<unspecified>)",
+            ),
+            ("(f)", "Ok(#<unspecified>)"),
+            ("(> (gc) 0)", "Ok(#t)"),
+            ("(gc)", "Ok(0)"),
+        ]);
+    }
+
+    #[test]
+    fn million_cycles() {
+        // Every call of `make-cycle` creates a Closure which refers to itself through its Environment.
+        for &backend in BACKENDS.iter() {
+            check_io_with(
+                backend,
+                &[
+                    (
+                        "(define (make-cycle) (define self (lambda () self)) self)",
+                        "Ok(#<unspecified>)",
+                    ),
+                    (
+                        "(define (repeat n f) (if (= n 0) 0 (begin (f) (repeat (- n 1) f))))",
+                        "Ok(#<unspecified>)",
+                    ),
+                    // Keep the recursion shallow for `eval`.
+                    (
+                        "(define (repeat-1000 f)
+                            (repeat 10 (lambda () (repeat 10 (lambda () (repeat 10 f))))))",
+                        "Ok(#<unspecified>)",
+                    ),
+                    (
+                        "(repeat-1000 (lambda () (repeat-1000 make-cycle)))",
+                        "Ok(0)",
+                    ),
+                ],
+            );
+        }
+        let stats = gc::stats();
+        assert!(stats.allocated > 2_000_000, "{:?}", stats);
+        assert!(stats.peak < 100_000, "{:?}", stats);
+    }
+}
+
+#[cfg(test)]
+mod big {
+    use super::helper::check_io;
+
+    #[test]
+    #[ignore]
+    fn fibonacci_long() {
+        check_io(vec![
+            (
+                "(define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                "(define range (lambda (a b) (if (= a b) (quote ()) (cons a (range (+ a 1) b)))))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                "(define map (lambda (f l) (if (null? l) null (cons (f (car l)) (map f (cdr l))))))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                "(map fib (range 0 20))",
+                "Ok((1 1 2 3 5 8 13 21 34 55 89 144 233 377 610 987 1597 2584 4181 6765))",
+            ),
+        ]);
+    }
+
+    #[test]
+    #[ignore]
+    fn man_or_boy_long() {
+        check_io(vec![
+            (
+                "(define A (lambda (k x1 x2 x3 x4 x5) 
+                    (define B (lambda () (set! k (- k 1)) (A k B x1 x2 x3 x4))) 
+                    (if (<= k 0) (+ (x4) (x5)) (B))))",
+                "Ok(#<unspecified>)",
+            ),
+            (
+                "(A 10 (lambda () 1) (lambda () -1) (lambda () -1) (lambda () 1) (lambda () 0))",
+                "Ok(-67)",
+            ),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    use super::helper::{check_io_with, BACKENDS};
+    use std::thread;
+    use std::time::Instant;
+
+    /// Runs a benchmark on every backend, on a thread with a larger stack,
+    /// since deep recursion may overflow the default one.
+    fn bench(name: &'static str, pairs: &'static [(&'static str, &'static str)]) {
+        thread::Builder::new()
+            .stack_size(1 << 30)
+            .spawn(move || {
+                for &backend in BACKENDS.iter() {
+                    let start = Instant::now();
+                    check_io_with(backend, pairs);
+                    println!("bench {} ({:?}): {:?}", name, backend, start.elapsed());
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn fib_25() {
+        bench(
+            "fib_25",
+            &[
+                (
+                    "(define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))",
+                    "Ok(#<unspecified>)",
+                ),
+                ("(fib 25)", "Ok(121393)"),
+            ],
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn man_or_boy_10() {
+        bench(
+            "man_or_boy_10",
+            &[
+                (
+                    "(define A (lambda (k x1 x2 x3 x4 x5)
+                        (define B (lambda () (set! k (- k 1)) (A k B x1 x2 x3 x4)))
+                        (if (<= k 0) (+ (x4) (x5)) (B))))",
+                    "Ok(#<unspecified>)",
+                ),
+                (
+                    "(A 10 (lambda () 1) (lambda () -1) (lambda () -1) (lambda () 1) (lambda () 0))",
+                    "Ok(-67)",
+                ),
+            ],
+        );
+    }
+}
@@ -1,7 +1,13 @@
 mod env;
 mod error;
 mod exp;
+mod port;
+mod symbol;
+mod table;
 
-pub use env::{make_env_ptr, Env, RcRefCell};
-pub use error::ScmErr;
-pub use exp::{Exp, ScmClosure};
+pub use env::{make_env_ptr, Env, Frame, GlobalCell, RcRefCell};
+pub use error::{ErrKind, Limit, ScmErr};
+pub use exp::{char_literal, Arity, Exp, ScmClosure, CHAR_NAMES};
+pub use port::Port;
+pub use symbol::Symbol;
+pub use table::{HashKey, HashTable};
@@ -1,31 +1,136 @@
 use super::exp::Exp;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use super::symbol::Symbol;
+use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
-/// The Scheme Environment Model.
-#[derive(Clone)]
+/// The global Environment.
+/// Every global variable lives in its own cell. Analyzed code holds on to the cells directly,
+/// so that looking up a global variable doesn't need to go through the HashMap.
 pub struct Env {
-    pub data: HashMap<String, Exp>,
-    pub outer: Option<RcRefCell<Env>>,
+    data: HashMap<Symbol, Rc<GlobalCell>>,
+    /// The variables imported from libraries, whose cells belong to the libraries.
+    imported: HashSet<Symbol>,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Env::new()
+    }
+}
+
+impl Drop for Env {
+    /// Global variables can take part in cycles too (a global function refers to its own cell),
+    /// so their values are cleared when the Env goes away.
+    /// The imported variables are left to their libraries.
+    fn drop(&mut self) {
+        self.data
+            .iter()
+            .filter(|(symbol, _)| !self.imported.contains(symbol))
+            .for_each(|(_, cell)| {
+                cell.value.replace(None);
+            });
+    }
+}
+
+/// A global variable.
+pub struct GlobalCell {
+    pub symbol: Symbol,
+    /// `None` if the variable has been referred to, but not defined yet.
+    pub value: RefCell<Option<Exp>>,
 }
 
 impl Env {
-    pub fn from_outer(outer: Option<RcRefCell<Env>>) -> Self {
+    pub fn new() -> Self {
         Env {
             data: HashMap::new(),
-            outer,
+            imported: HashSet::new(),
+        }
+    }
+
+    /// Bind a symbol to a value, overwriting the previous definition if any.
+    pub fn define(&mut self, symbol: Symbol, value: Exp) {
+        self.definition_cell(symbol).value.replace(Some(value));
+    }
+
+    /// Remove the definition of a symbol, if any.
+    pub fn undefine(&mut self, symbol: Symbol) {
+        if self.imported.remove(&symbol) {
+            self.data.remove(&symbol);
+        } else if let Some(cell) = self.data.get(&symbol) {
+            cell.value.replace(None);
+        }
+    }
+
+    /// Bind a symbol to the cell of a variable exported by a library.
+    pub fn import(&mut self, symbol: Symbol, cell: Rc<GlobalCell>) {
+        self.data.insert(symbol, cell);
+        self.imported.insert(symbol);
+    }
+
+    /// Get the cell to define a symbol in.
+    /// Defining an imported variable shadows it with a new cell, leaving the library's one alone.
+    pub fn definition_cell(&mut self, symbol: Symbol) -> Rc<GlobalCell> {
+        if self.imported.remove(&symbol) {
+            self.data.remove(&symbol);
+        }
+        self.cell(symbol)
+    }
+
+    /// Find the value of a global variable.
+    pub fn lookup(&self, symbol: Symbol) -> Option<Exp> {
+        self.data.get(&symbol)?.value.borrow().clone()
+    }
+
+    /// The values of the global variables, `None` for the undefined ones.
+    pub fn values(&self) -> impl Iterator<Item = Ref<'_, Option<Exp>>> {
+        self.data.values().map(|cell| cell.value.borrow())
+    }
+
+    /// Get the cell of a symbol, creating an undefined one if necessary.
+    pub fn cell(&mut self, symbol: Symbol) -> Rc<GlobalCell> {
+        let cell = self.data.entry(symbol).or_insert_with(|| {
+            Rc::new(GlobalCell {
+                symbol,
+                value: RefCell::new(None),
+            })
+        });
+        Rc::clone(cell)
+    }
+}
+
+/// A local Environment, created for every call of a Closure.
+/// The variables are resolved to (depth, index) pairs during the analysis,
+/// so a Frame only needs to store their values.
+pub struct Frame {
+    /// `None` if the variable is an internal definition which hasn't been evaluated yet.
+    pub slots: Vec<Option<Exp>>,
+    pub outer: Option<RcRefCell<Frame>>,
+}
+
+impl Frame {
+    pub fn from_outer(slots: Vec<Option<Exp>>, outer: Option<RcRefCell<Frame>>) -> Self {
+        Frame { slots, outer }
+    }
+
+    /// Find the value of the variable at the given address.
+    pub fn lookup(&self, depth: usize, index: usize) -> Option<Exp> {
+        match depth {
+            0 => self.slots[index].clone(),
+            _ => self.outer.as_ref()?.borrow().lookup(depth - 1, index),
         }
     }
 
-    /// Find the definition of a symbol.
-    pub fn lookup(&self, symbol: &Exp) -> Option<Exp> {
-        match symbol {
-            Exp::Symbol(s) => match self.data.get(s) {
-                Some(def) => Some(def.clone()),
-                None => self.outer.as_ref().and_then(|o| o.borrow().lookup(symbol)),
-            },
-            _ => None,
+    /// Set the value of the variable at the given address.
+    pub fn set(&mut self, depth: usize, index: usize, value: Exp) {
+        match depth {
+            0 => self.slots[index] = Some(value),
+            _ => self
+                .outer
+                .as_ref()
+                .expect("frame: invalid lexical address")
+                .borrow_mut()
+                .set(depth - 1, index, value),
         }
     }
 }
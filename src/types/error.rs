@@ -1,26 +1,79 @@
 use std::fmt;
 
+/// The kind of an error, so that the host can tell ordinary Scheme errors apart.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrKind {
+    /// An ordinary error raised by the evaluation.
+    Error,
+    /// A resource limit of a sandboxed evaluation has been exceeded.
+    Limit(Limit),
+    /// The evaluation has been interrupted, by Ctrl-C for example.
+    Interrupted,
+    /// `exit` has been called with the given exit code.
+    Exit(i32),
+}
+
+/// A resource limit of a sandboxed evaluation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Limit {
+    Fuel,
+    Depth,
+    Heap,
+}
+
 pub struct ScmErr {
     reason: String,
+    kind: ErrKind,
 }
 
 impl ScmErr {
     pub fn from(reason: &str) -> Self {
         ScmErr {
             reason: String::from(reason),
+            kind: ErrKind::Error,
+        }
+    }
+
+    pub fn limit(limit: Limit) -> Self {
+        let reason = match limit {
+            Limit::Fuel => "sandbox: out of fuel",
+            Limit::Depth => "sandbox: maximum recursion depth exceeded",
+            Limit::Heap => "sandbox: heap limit exceeded",
+        };
+        ScmErr {
+            reason: String::from(reason),
+            kind: ErrKind::Limit(limit),
         }
     }
+
+    pub fn interrupted() -> Self {
+        ScmErr {
+            reason: String::from("interrupted"),
+            kind: ErrKind::Interrupted,
+        }
+    }
+
+    pub fn exit(code: i32) -> Self {
+        ScmErr {
+            reason: format!("exit: exited with code {}", code),
+            kind: ErrKind::Exit(code),
+        }
+    }
+
+    pub fn kind(&self) -> ErrKind {
+        self.kind
+    }
 }
 
 impl fmt::Display for ScmErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", &self.reason)
+        write!(f, "{}", self.reason)
     }
 }
 
 impl fmt::Debug for ScmErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", &self.reason)
+        write!(f, "{}", self.reason)
     }
 }
 
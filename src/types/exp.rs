@@ -1,5 +1,11 @@
-use super::{Env, ScmErr};
+use super::{Env, Frame, HashTable, Port, RcRefCell, ScmErr, Symbol};
+use crate::analyzer::Lambda;
+use crate::gc;
+use crate::interp::Interp;
+use crate::printer;
+use crate::vm::VmClosure;
 use std::fmt;
+use std::rc::Rc;
 
 /// The Scheme Expression type.
 #[derive(Clone)]
@@ -7,52 +13,141 @@ pub enum Exp {
     /// A Bool (#t/#f).
     Bool(bool),
     /// A Symbol.
-    Symbol(String),
+    Symbol(Symbol),
     /// A Number. Actually a f64.
     Number(f64), // ! int unimplemented
+    /// A String.
+    String(String),
+    /// A Char.
+    Char(char),
     /// A List. Also used as AST.
     List(Vec<Exp>),
+    /// A Vector. Vectors are mutable, and shared between their copies.
+    Vector(RcRefCell<Vec<Exp>>),
+    /// A hash table. Hash tables are mutable, and shared between their copies.
+    HashTable(RcRefCell<HashTable>),
+    /// An input or output port.
+    Port(Rc<Port>),
     /// A user-defined function.
     Closure(ScmClosure),
+    /// A user-defined function compiled to bytecode. Called by the VM.
+    Compiled(Rc<VmClosure>),
     /// A Primitive function. Provided by the Prelude.
     Primitive(fn(&[Exp]) -> Result<Exp, ScmErr>),
+    /// A native function which can capture state and call back into the interpreter.
+    Native(Rc<Native>),
+    /// An Environment to evaluate code in, as given to `load`.
+    Environment(RcRefCell<Env>),
+    /// The end-of-file object, returned by the reading procedures at the end of the input.
+    Eof,
     Empty,
 }
 
+/// Exps are displayed as `write` prints them, with labels on cyclic Vectors.
 impl fmt::Display for Exp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let res = match self {
-            Exp::Bool(b) => format!("{}", b),
-            Exp::Symbol(s) => format!("'{}", s),
-            Exp::Number(n) => format!("{}", n),
-            Exp::List(l) => format!("{:?}", l),
-            Exp::Closure(_) => "<Closure>".into(),
-            Exp::Primitive(_) => "<Primitive>".into(),
-            Exp::Empty => "()".into(),
-        };
-        write!(f, "{}", res)
+        write!(f, "{}", printer::write(self))
     }
 }
 
 impl fmt::Debug for Exp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let res = match self {
-            Exp::Bool(b) => format!("{}", b),
-            Exp::Symbol(s) => format!("'{}", s),
-            Exp::Number(n) => format!("{}", n),
-            Exp::List(l) => format!("{:?}", l),
-            Exp::Closure(_) => "<Closure>".into(),
-            Exp::Primitive(_) => "<Primitive>".into(),
-            Exp::Empty => String::new(),
-        };
-        write!(f, "{}", res)
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// The names of the chars which have one, following R7RS.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("alarm", '\u{7}'),
+    ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'),
+    ("escape", '\u{1b}'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
+/// Format a char as a literal, like `#\a` or `#\space`.
+pub fn char_literal(c: char) -> String {
+    match CHAR_NAMES.iter().find(|&&(_, named)| named == c) {
+        Some((name, _)) => format!("#\\{}", name),
+        None if c.is_control() => format!("#\\x{:x}", c as u32),
+        None => format!("#\\{}", c),
     }
 }
 
 /// A Closure is a user-defined function.
-/// It has a function body and a captured environment.
+/// It has an analyzed lambda expression and a captured environment.
 #[derive(Clone)]
 pub struct ScmClosure {
-    pub body: Box<Exp>,
-    pub env: Env,
+    pub lambda: Rc<Lambda>,
+    pub env: Option<RcRefCell<Frame>>,
+}
+
+/// The number of arguments accepted by a Native function.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    /// Check the number of arguments given to the function with the given name.
+    pub fn check(self, name: &str, argc: usize) -> Result<(), ScmErr> {
+        let (ok, expected) = match self {
+            Arity::Exactly(n) => (argc == n, format!("{}", n)),
+            Arity::AtLeast(n) => (argc >= n, format!("at least {}", n)),
+            Arity::Between(min, max) => (
+                min <= argc && argc <= max,
+                format!("between {} and {}", min, max),
+            ),
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(ScmErr::from(&format!(
+                "{}: expected {} arguments, got {}",
+                name, expected, argc
+            )))
+        }
+    }
+}
+
+/// The signature of a Native function.
+pub type NativeFn = dyn Fn(&[Exp], &mut Interp) -> Result<Exp, ScmErr>;
+
+/// A Native function is a Rust closure, provided by the Prelude or by an embedder.
+/// Unlike a Primitive, it can capture state, and it is given the interpreter to call back into Scheme.
+pub struct Native {
+    pub name: String,
+    pub arity: Arity,
+    pub func: Box<NativeFn>,
+}
+
+impl Exp {
+    /// Make a Native function.
+    pub fn native(
+        name: &str,
+        arity: Arity,
+        func: impl Fn(&[Exp], &mut Interp) -> Result<Exp, ScmErr> + 'static,
+    ) -> Exp {
+        Exp::Native(Rc::new(Native {
+            name: name.into(),
+            arity,
+            func: Box::new(func),
+        }))
+    }
+
+    /// Make a new Vector.
+    pub fn vector(items: Vec<Exp>) -> Exp {
+        Exp::Vector(gc::alloc_vector(items))
+    }
+
+    /// Make a new hash table.
+    pub fn hash_table(table: HashTable) -> Exp {
+        Exp::HashTable(gc::alloc_table(table))
+    }
 }
@@ -0,0 +1,299 @@
+use super::ScmErr;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Write};
+use std::path::Path;
+
+/// A textual port, reading from or writing to a file, a string or a byte buffer.
+/// Ports are shared between their copies, and their state is mutable.
+pub struct Port {
+    name: String,
+    input: bool,
+    state: RefCell<State>,
+}
+
+enum State {
+    Input {
+        source: Box<dyn BufRead>,
+        /// The chars read from the source but not consumed yet.
+        pending: VecDeque<char>,
+        /// Whether reading from the source may block, waiting for more input.
+        blocking: bool,
+    },
+    Output(Sink),
+    Closed,
+}
+
+enum Sink {
+    Stdout,
+    Stderr,
+    File(BufWriter<File>),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+fn io_err(name: &str, e: io::Error) -> ScmErr {
+    ScmErr::from(&format!("{}: {}", name, e))
+}
+
+impl Port {
+    fn new(name: &str, input: bool, state: State) -> Self {
+        Port {
+            name: name.into(),
+            input,
+            state: RefCell::new(state),
+        }
+    }
+
+    /// Make an input port reading from the given source.
+    /// Reading from an interactive source may block, so `char_ready` doesn't trust it.
+    pub fn input(name: &str, source: impl BufRead + 'static, interactive: bool) -> Self {
+        let state = State::Input {
+            source: Box::new(source),
+            pending: VecDeque::new(),
+            blocking: interactive,
+        };
+        Port::new(name, true, state)
+    }
+
+    pub fn stdin() -> Self {
+        Port::input("stdin", BufReader::new(io::stdin()), true)
+    }
+
+    pub fn input_string(s: &str) -> Self {
+        Port::input("string", Cursor::new(s.to_string().into_bytes()), false)
+    }
+
+    /// Make an input port decoding the bytes as UTF-8.
+    pub fn input_bytes(bytes: Vec<u8>) -> Self {
+        Port::input("bytevector", Cursor::new(bytes), false)
+    }
+
+    pub fn input_file(path: impl AsRef<Path>) -> Result<Self, ScmErr> {
+        let path = path.as_ref();
+        let name = format!("`{}`", path.display());
+        let file = File::open(path).map_err(|e| io_err(&name, e))?;
+        Ok(Port::input(&name, BufReader::new(file), false))
+    }
+
+    pub fn stdout() -> Self {
+        Port::new("stdout", false, State::Output(Sink::Stdout))
+    }
+
+    pub fn stderr() -> Self {
+        Port::new("stderr", false, State::Output(Sink::Stderr))
+    }
+
+    /// Make an output port accumulating a String, which can be taken with `output_string`.
+    pub fn output_string() -> Self {
+        Port::new("string", false, State::Output(Sink::String(String::new())))
+    }
+
+    /// Make an output port accumulating bytes, which can be taken with `output_bytes`.
+    pub fn output_bytes() -> Self {
+        Port::new("bytevector", false, State::Output(Sink::Bytes(Vec::new())))
+    }
+
+    /// Make an output port writing to a file, truncating it first.
+    pub fn output_file(path: impl AsRef<Path>) -> Result<Self, ScmErr> {
+        let path = path.as_ref();
+        let name = format!("`{}`", path.display());
+        let file = File::create(path).map_err(|e| io_err(&name, e))?;
+        Ok(Port::new(
+            &name,
+            false,
+            State::Output(Sink::File(BufWriter::new(file))),
+        ))
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.input
+    }
+
+    pub fn is_output(&self) -> bool {
+        !self.input
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state.borrow(), State::Closed)
+    }
+
+    fn closed(&self) -> ScmErr {
+        ScmErr::from(&format!("port: {} is closed", self))
+    }
+
+    /// Write a string to an output port.
+    pub fn write_str(&self, s: &str) -> Result<(), ScmErr> {
+        let res = match &mut *self.state.borrow_mut() {
+            State::Output(Sink::Stdout) => io::stdout().write_all(s.as_bytes()),
+            State::Output(Sink::Stderr) => io::stderr().write_all(s.as_bytes()),
+            State::Output(Sink::File(file)) => file.write_all(s.as_bytes()),
+            State::Output(Sink::String(buf)) => {
+                buf.push_str(s);
+                Ok(())
+            }
+            State::Output(Sink::Bytes(buf)) => {
+                buf.extend_from_slice(s.as_bytes());
+                Ok(())
+            }
+            State::Input { .. } => {
+                return Err(ScmErr::from(&format!(
+                    "port: {} is not an output port",
+                    self
+                )))
+            }
+            State::Closed => return Err(self.closed()),
+        };
+        res.map_err(|e| io_err(&self.name, e))
+    }
+
+    /// Flush the buffered output, if any.
+    pub fn flush(&self) -> Result<(), ScmErr> {
+        let res = match &mut *self.state.borrow_mut() {
+            State::Output(Sink::Stdout) => io::stdout().flush(),
+            State::Output(Sink::Stderr) => io::stderr().flush(),
+            State::Output(Sink::File(file)) => file.flush(),
+            _ => Ok(()),
+        };
+        res.map_err(|e| io_err(&self.name, e))
+    }
+
+    /// Get the String accumulated by a string output port.
+    pub fn output_string_contents(&self) -> Option<String> {
+        match &*self.state.borrow() {
+            State::Output(Sink::String(buf)) => Some(buf.clone()),
+            _ => None,
+        }
+    }
+
+    /// Get the bytes accumulated by a bytevector output port.
+    pub fn output_bytes_contents(&self) -> Option<Vec<u8>> {
+        match &*self.state.borrow() {
+            State::Output(Sink::Bytes(buf)) => Some(buf.clone()),
+            _ => None,
+        }
+    }
+
+    /// Close the port, flushing it first. Closing a closed port does nothing.
+    pub fn close(&self) -> Result<(), ScmErr> {
+        let res = self.flush();
+        self.state.replace(State::Closed);
+        res
+    }
+
+    /// Make sure that there are pending chars, unless the input has ended.
+    fn fill(&self) -> Result<(), ScmErr> {
+        match &mut *self.state.borrow_mut() {
+            State::Input {
+                source, pending, ..
+            } => {
+                if pending.is_empty() {
+                    let mut line = String::new();
+                    source
+                        .read_line(&mut line)
+                        .map_err(|e| io_err(&self.name, e))?;
+                    pending.extend(line.chars());
+                }
+                Ok(())
+            }
+            State::Output(_) => Err(ScmErr::from(&format!(
+                "port: {} is not an input port",
+                self
+            ))),
+            State::Closed => Err(self.closed()),
+        }
+    }
+
+    /// Read the next char, or `None` at the end of the input.
+    pub fn read_char(&self) -> Result<Option<char>, ScmErr> {
+        self.fill()?;
+        match &mut *self.state.borrow_mut() {
+            State::Input { pending, .. } => Ok(pending.pop_front()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get the next char without consuming it, or `None` at the end of the input.
+    pub fn peek_char(&self) -> Result<Option<char>, ScmErr> {
+        self.fill()?;
+        match &*self.state.borrow() {
+            State::Input { pending, .. } => Ok(pending.front().cloned()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Read the rest of the current line, without the line ending,
+    /// or `None` at the end of the input.
+    pub fn read_line(&self) -> Result<Option<String>, ScmErr> {
+        self.fill()?;
+        match &mut *self.state.borrow_mut() {
+            State::Input { pending, .. } if pending.is_empty() => Ok(None),
+            State::Input { pending, .. } => {
+                // The pending chars always hold the rest of a line, unless it's the last one.
+                let end = pending.iter().position(|&c| c == '\n');
+                let mut line: String = pending.drain(..end.unwrap_or(pending.len())).collect();
+                // Drop the newline, if any.
+                pending.pop_front();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                Ok(Some(line))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Read at most `k` chars, or `None` at the end of the input.
+    pub fn read_string(&self, k: usize) -> Result<Option<String>, ScmErr> {
+        let mut res = String::new();
+        for _ in 0..k {
+            match self.read_char()? {
+                Some(c) => res.push(c),
+                None => break,
+            }
+        }
+        Ok(if res.is_empty() && k > 0 {
+            None
+        } else {
+            Some(res)
+        })
+    }
+
+    /// Put chars back in front of the input, to be read again.
+    pub fn unread(&self, s: &str) -> Result<(), ScmErr> {
+        match &mut *self.state.borrow_mut() {
+            State::Input { pending, .. } => {
+                s.chars().rev().for_each(|c| pending.push_front(c));
+                Ok(())
+            }
+            State::Output(_) => Err(ScmErr::from(&format!(
+                "port: {} is not an input port",
+                self
+            ))),
+            State::Closed => Err(self.closed()),
+        }
+    }
+
+    /// Whether a char can be read without blocking.
+    pub fn char_ready(&self) -> Result<bool, ScmErr> {
+        match &*self.state.borrow() {
+            State::Input {
+                pending, blocking, ..
+            } => Ok(!pending.is_empty() || !blocking),
+            State::Output(_) => Err(ScmErr::from(&format!(
+                "port: {} is not an input port",
+                self
+            ))),
+            State::Closed => Err(self.closed()),
+        }
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dir = if self.input { "input" } else { "output" };
+        write!(f, "<{} port {}>", dir, self.name)
+    }
+}
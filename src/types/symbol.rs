@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// An interned Symbol.
+/// It is only an index into the Symbol table, so it's cheap to copy, compare and hash.
+///
+/// The Symbol table is thread-local, so a Symbol only makes sense on the thread which interned it:
+/// it is neither `Send` nor `Sync`, and should be sent to another thread by name.
+///
+/// ```compile_fail
+/// # use rusk::Symbol;
+/// let symbol = Symbol::intern("x");
+/// std::thread::spawn(move || symbol.as_str());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32, PhantomData<*const ()>);
+
+/// The Symbol table.
+/// Interned names are never freed, so they can be handed out as `&'static str`.
+struct SymbolTable {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+}
+
+impl SymbolTable {
+    fn new() -> Self {
+        let mut res = SymbolTable {
+            names: Vec::new(),
+            ids: HashMap::new(),
+        };
+        for name in KEYWORDS.iter() {
+            res.intern(name);
+        }
+        res
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&sym) = self.ids.get(name) {
+            return sym;
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let sym = Symbol(self.names.len() as u32, PhantomData);
+        self.names.push(name);
+        self.ids.insert(name, sym);
+        sym
+    }
+}
+
+thread_local! {
+    static SYMBOL_TABLE: RefCell<SymbolTable> = RefCell::new(SymbolTable::new());
+}
+
+/// Declares the Symbols which are pre-interned in a fixed order,
+/// so that they can be used as constants (and in patterns).
+macro_rules! keywords {
+    ($($id:expr => $konst:ident: $name:expr,)*) => {
+        static KEYWORDS: &[&str] = &[$($name,)*];
+
+        impl Symbol {
+            $(pub const $konst: Symbol = Symbol($id, PhantomData);)*
+        }
+    };
+}
+
+keywords! {
+    0 => QUOTE: "quote",
+    1 => LAMBDA: "lambda",
+    2 => DEFINE: "define",
+    3 => SET: "set!",
+    4 => IF: "if",
+    5 => COND: "cond",
+    6 => ELSE: "else",
+    7 => BEGIN: "begin",
+    8 => INCLUDE: "include",
+    9 => INCLUDE_CI: "include-ci",
+    10 => IMPORT: "import",
+    11 => DEFINE_LIBRARY: "define-library",
+}
+
+impl Symbol {
+    /// Find the Symbol of a name, interning it if necessary.
+    pub fn intern(name: &str) -> Self {
+        SYMBOL_TABLE.with(|table| table.borrow_mut().intern(name))
+    }
+
+    /// Get the name of the Symbol.
+    pub fn as_str(self) -> &'static str {
+        SYMBOL_TABLE.with(|table| table.borrow().names[self.0 as usize])
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,80 @@
+use super::exp::Exp;
+use super::symbol::Symbol;
+use super::ScmErr;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A hash table.
+/// The keys are kept sorted, so that tables are printed and serialized in a stable order.
+pub type HashTable = BTreeMap<HashKey, Exp>;
+
+/// A key of a hash table.
+/// Only atoms can be used as keys, and they are compared by value.
+#[derive(Clone, Debug)]
+pub enum HashKey {
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Symbol(Symbol),
+}
+
+impl HashKey {
+    pub fn from_exp(exp: &Exp) -> Result<Self, ScmErr> {
+        match exp {
+            &Exp::Bool(b) => Ok(HashKey::Bool(b)),
+            // -0.0 and 0.0 are the same key.
+            &Exp::Number(n) => Ok(HashKey::Number(n + 0.0)),
+            Exp::String(s) => Ok(HashKey::String(s.clone())),
+            &Exp::Symbol(s) => Ok(HashKey::Symbol(s)),
+            _ => Err(ScmErr::from(&format!(
+                "hash-table: {} cannot be used as a key",
+                exp
+            ))),
+        }
+    }
+
+    pub fn to_exp(&self) -> Exp {
+        match self {
+            &HashKey::Bool(b) => Exp::Bool(b),
+            &HashKey::Number(n) => Exp::Number(n),
+            HashKey::String(s) => Exp::String(s.clone()),
+            &HashKey::Symbol(s) => Exp::Symbol(s),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            HashKey::Bool(_) => 0,
+            HashKey::Number(_) => 1,
+            HashKey::String(_) => 2,
+            HashKey::Symbol(_) => 3,
+        }
+    }
+}
+
+impl Ord for HashKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (HashKey::Bool(a), HashKey::Bool(b)) => a.cmp(b),
+            (HashKey::Number(a), HashKey::Number(b)) => a.total_cmp(b),
+            (HashKey::String(a), HashKey::String(b)) => a.cmp(b),
+            // Symbols are ordered by name rather than by interning order.
+            (HashKey::Symbol(a), HashKey::Symbol(b)) => a.as_str().cmp(b.as_str()),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for HashKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HashKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HashKey {}
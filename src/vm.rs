@@ -0,0 +1,236 @@
+use crate::compile::{Capture, Code, Loc, Op};
+use crate::eval_apply::{self, undefined};
+use crate::gc;
+use crate::interp::Interp;
+use crate::types::*;
+use std::rc::Rc;
+
+// * Stack virtual machine
+
+/// A variable shared between a call frame and the Closures capturing it.
+pub type Upval = RcRefCell<Option<Exp>>;
+
+/// A Closure created by the VM.
+pub struct VmClosure {
+    pub code: Rc<Code>,
+    pub upvals: Vec<Upval>,
+}
+
+struct CallFrame {
+    closure: Rc<VmClosure>,
+    pc: usize,
+    /// Where the local variables of the frame start in `Vm::locals`.
+    locals_base: usize,
+    /// Where the operands of the frame start in `Vm::stack`.
+    stack_base: usize,
+    cells: Vec<Upval>,
+}
+
+struct Vm {
+    stack: Vec<Exp>,
+    locals: Vec<Option<Exp>>,
+    frames: Vec<CallFrame>,
+}
+
+/// Run a compiled top-level form.
+pub fn run(code: Rc<Code>, interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let closure = VmClosure {
+        code,
+        upvals: Vec::new(),
+    };
+    call(Rc::new(closure), &[], interp)
+}
+
+/// Call a compiled Closure with the given arguments.
+pub fn call(closure: Rc<VmClosure>, args: &[Exp], interp: &mut Interp) -> Result<Exp, ScmErr> {
+    let mut vm = Vm {
+        stack: Vec::new(),
+        locals: Vec::new(),
+        frames: Vec::new(),
+    };
+    vm.stack.push(Exp::Compiled(Rc::clone(&closure)));
+    vm.stack.extend_from_slice(args);
+    let depth = interp.depth();
+    interp.enter()?;
+    vm.push_frame(closure, args.len());
+    let res = vm.run(interp);
+    interp.set_depth(depth);
+    res
+}
+
+impl Vm {
+    /// Enter a Closure whose arguments are on the top of the stack, right above the Closure itself.
+    fn push_frame(&mut self, closure: Rc<VmClosure>, argc: usize) {
+        let code = &closure.code;
+        let args_base = self.stack.len() - argc;
+        let locals_base = self.locals.len();
+        self.locals.resize(locals_base + code.locals.len(), None);
+        let cells: Vec<Upval> = code.cells.iter().map(|_| gc::alloc_cell(None)).collect();
+        // Extra arguments are ignored, and missing ones are left undefined.
+        for (&loc, arg) in code.params.iter().zip(self.stack.drain(args_base..)) {
+            match loc {
+                Loc::Local(i) => self.locals[locals_base + i] = Some(arg),
+                Loc::Cell(i) => *cells[i].borrow_mut() = Some(arg),
+            }
+        }
+        // Pop the remaining arguments and the Closure itself.
+        self.stack.truncate(args_base - 1);
+        self.frames.push(CallFrame {
+            closure,
+            pc: 0,
+            locals_base,
+            stack_base: self.stack.len(),
+            cells,
+        });
+    }
+
+    /// Leave the current frame, pushing its return value to the caller.
+    /// Returns the value instead if there is no caller left.
+    fn pop_frame(&mut self, interp: &mut Interp) -> Option<Exp> {
+        interp.leave();
+        let val = self.stack.pop().expect("vm: nothing to return");
+        let frame = self.frames.pop().expect("vm: no frame to return from");
+        self.locals.truncate(frame.locals_base);
+        self.stack.truncate(frame.stack_base);
+        if self.frames.is_empty() {
+            return Some(val);
+        }
+        self.stack.push(val);
+        None
+    }
+
+    /// Call a function which is not a compiled Closure, replacing it and its arguments with the result.
+    fn call_foreign(&mut self, func: Exp, argc: usize, interp: &mut Interp) -> Result<(), ScmErr> {
+        let func_idx = self.stack.len() - argc - 1;
+        let res = eval_apply::apply(func, &self.stack[func_idx + 1..], interp)?;
+        self.stack.truncate(func_idx);
+        self.stack.push(res);
+        Ok(())
+    }
+
+    fn run(&mut self, interp: &mut Interp) -> Result<Exp, ScmErr> {
+        loop {
+            let frame = self.frames.last_mut().expect("vm: no frame to run");
+            let code = &frame.closure.code;
+            let op = code.ops[frame.pc];
+            frame.pc += 1;
+            interp.step()?;
+
+            match op {
+                Op::Const(k) => self.stack.push(code.consts[k].clone()),
+
+                Op::LocalGet(i) => {
+                    let val = self.locals[frame.locals_base + i]
+                        .clone()
+                        .ok_or_else(|| undefined(code.locals[i]))?;
+                    self.stack.push(val);
+                }
+
+                Op::LocalSet(i) => self.locals[frame.locals_base + i] = self.stack.pop(),
+
+                Op::CellGet(i) => {
+                    let val = frame.cells[i]
+                        .borrow()
+                        .clone()
+                        .ok_or_else(|| undefined(code.cells[i]))?;
+                    self.stack.push(val);
+                }
+
+                Op::CellSet(i) => *frame.cells[i].borrow_mut() = self.stack.pop(),
+
+                Op::UpvalGet(i) => {
+                    let val = frame.closure.upvals[i]
+                        .borrow()
+                        .clone()
+                        .ok_or_else(|| undefined(code.upvals[i].0))?;
+                    self.stack.push(val);
+                }
+
+                Op::UpvalSet(i) => *frame.closure.upvals[i].borrow_mut() = self.stack.pop(),
+
+                Op::GlobalGet(g) => {
+                    let cell = &code.globals[g];
+                    let val = cell
+                        .value
+                        .borrow()
+                        .clone()
+                        .ok_or_else(|| undefined(cell.symbol))?;
+                    self.stack.push(val);
+                }
+
+                Op::GlobalSet(g) => {
+                    code.globals[g].value.replace(self.stack.pop());
+                }
+
+                Op::Empty => self.stack.push(Exp::Empty),
+
+                Op::Pop => {
+                    self.stack.pop();
+                }
+
+                Op::Closure(idx) => {
+                    let proto = Rc::clone(&code.protos[idx]);
+                    let upvals = proto
+                        .upvals
+                        .iter()
+                        .map(|&(_, capture)| match capture {
+                            Capture::Cell(i) => Rc::clone(&frame.cells[i]),
+                            Capture::Upval(i) => Rc::clone(&frame.closure.upvals[i]),
+                        })
+                        .collect();
+                    let closure = VmClosure {
+                        code: proto,
+                        upvals,
+                    };
+                    self.stack.push(Exp::Compiled(gc::alloc_closure(closure)));
+                }
+
+                Op::Jump(target) => frame.pc = target,
+
+                Op::JumpIfFalse(target, form) => match self.stack.pop() {
+                    Some(Exp::Bool(true)) => (),
+                    Some(Exp::Bool(false)) => frame.pc = target,
+                    _ => return Err(ScmErr::from(&format!("{}: expected Exp::Bool", form))),
+                },
+
+                Op::NoMatch => return Err(ScmErr::from("cond: missing else clause")),
+
+                Op::Call(argc) => {
+                    let func = self.stack[self.stack.len() - argc - 1].clone();
+                    match func {
+                        Exp::Compiled(closure) => {
+                            interp.enter()?;
+                            self.push_frame(closure, argc);
+                        }
+                        _ => self.call_foreign(func, argc, interp)?,
+                    }
+                }
+
+                Op::TailCall(argc) => {
+                    let func = self.stack[self.stack.len() - argc - 1].clone();
+                    match func {
+                        Exp::Compiled(closure) => {
+                            // In a tail position, the function and its arguments
+                            // are the only operands of the current frame.
+                            let frame = self.frames.pop().unwrap();
+                            self.locals.truncate(frame.locals_base);
+                            self.push_frame(closure, argc);
+                        }
+                        _ => {
+                            self.call_foreign(func, argc, interp)?;
+                            if let Some(val) = self.pop_frame(interp) {
+                                return Ok(val);
+                            }
+                        }
+                    }
+                }
+
+                Op::Return => {
+                    if let Some(val) = self.pop_frame(interp) {
+                        return Ok(val);
+                    }
+                }
+            }
+        }
+    }
+}
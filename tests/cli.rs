@@ -0,0 +1,179 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run the rusk binary with the given arguments and stdin.
+fn rusk(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rusk"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into()
+}
+
+#[test]
+fn eval_and_print() {
+    let output = rusk(
+        &[
+            "-e",
+            "(display (+ 1 2))",
+            "-p",
+            "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))",
+            "-p",
+            "(fib 10)",
+            "-p",
+            "(cons \"a\" null)",
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "355\n(\"a\")\n");
+}
+
+#[test]
+fn stdin() {
+    let output = rusk(
+        &["-"],
+        "(displayln (map (lambda (x) (* x x)) (cons 3 null)))",
+    );
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "(9)\n");
+}
+
+#[test]
+fn exit_status() {
+    let output = rusk(
+        &["-e", "(display 1)", "-e", "(car 1)", "-e", "(display 2)"],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "1");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Error: car: expected a List\n"
+    );
+
+    let output = rusk(&["-"], "(display \"bye\") (exit 3) (display 4)");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stdout(&output), "bye");
+
+    // `emergency-exit` doesn't run the `dynamic-wind` after thunks.
+    let output = rusk(
+        &["-"],
+        "(display \"bye\")
+         (dynamic-wind (lambda () #t) (lambda () (emergency-exit 6)) (lambda () (display 4)))",
+    );
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(stdout(&output), "bye");
+
+    assert_eq!(rusk(&["-"], "(car").status.code(), Some(1));
+}
+
+#[test]
+fn script() {
+    let path = std::env::temp_dir().join(format!("rusk-script-{}.scm", std::process::id()));
+    std::fs::write(
+        &path,
+        "#!/usr/bin/env rusk\n\
+         (write (cdr (command-line)))\n\
+         (exit (if (equal? (get-environment-variable \"STATUS\") \"fail\") 3 0))",
+    )
+    .unwrap();
+    let run = |status: &str| {
+        Command::new(env!("CARGO_BIN_EXE_rusk"))
+            .arg(&path)
+            .args(["a", "-v"])
+            .env("STATUS", status)
+            .output()
+            .unwrap()
+    };
+    let output = run("ok");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "(\"a\" \"-v\")");
+    assert_eq!(run("fail").status.code(), Some(3));
+    std::fs::remove_file(&path).unwrap();
+
+    let output = rusk(&["-", "x"], "#!/usr/bin/env rusk\n(write (command-line))");
+    assert_eq!(stdout(&output), "(\"-\" \"x\")");
+}
+
+#[test]
+fn compile_libraries() {
+    let dir = std::env::temp_dir().join(format!("rusk-compile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("main.scm");
+    let image = dir.join("main.rbc");
+    std::fs::write(&source, "(import (scheme write))\n(display 1)").unwrap();
+    let output = rusk(&["compile", source.to_str().unwrap()], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .ends_with("import: libraries can't be compiled into an image\n"));
+    assert!(!image.exists());
+
+    std::fs::write(&source, "(display 1)").unwrap();
+    assert!(rusk(&["compile", source.to_str().unwrap()], "")
+        .status
+        .success());
+    assert_eq!(stdout(&rusk(&[image.to_str().unwrap()], "")), "1");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compile_includes() {
+    let dir = std::env::temp_dir().join(format!("rusk-compile-include-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("main.scm");
+    std::fs::write(&source, "(include \"square.scm\")\n(display (square 3))").unwrap();
+    std::fs::write(dir.join("square.scm"), "(define (square x) (* x x))").unwrap();
+    assert!(rusk(&["compile", source.to_str().unwrap()], "")
+        .status
+        .success());
+    // The included file is compiled into the image.
+    std::fs::remove_file(dir.join("square.scm")).unwrap();
+    let output = rusk(&[dir.join("main.rbc").to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "9");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compile_stdlib() {
+    let dir = std::env::temp_dir().join(format!("rusk-compile-stdlib-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("main.scm");
+    let image = dir.join("main.rbc");
+    std::fs::write(
+        &source,
+        "(displayln (map (lambda (x) (* x x)) (cons 2 (cons 3 null))))",
+    )
+    .unwrap();
+    // The standard library is compiled into the image, and not loaded again to run it.
+    assert!(rusk(&["compile", source.to_str().unwrap()], "")
+        .status
+        .success());
+    let output = rusk(&[image.to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "(4 9)\n");
+
+    assert!(
+        rusk(&["compile", "--no-stdlib", source.to_str().unwrap()], "")
+            .status
+            .success()
+    );
+    let output = rusk(&[image.to_str().unwrap()], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("displayln"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
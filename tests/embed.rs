@@ -0,0 +1,264 @@
+use rusk::{Arity, Backend, ErrKind, Exp, Interpreter, ScmErr};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+
+const BACKENDS: [Backend; 2] = [Backend::Eval, Backend::Vm];
+
+fn number(exp: Exp) -> f64 {
+    match exp {
+        Exp::Number(n) => n,
+        exp => panic!("expected a Number, got {}", exp),
+    }
+}
+
+#[test]
+fn eval_str() {
+    for &backend in BACKENDS.iter() {
+        let mut rusk = Interpreter::with_backend(backend);
+        let res = rusk.eval_str(
+            "(define (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))
+             (fact 10)",
+        );
+        assert_eq!(number(res.unwrap()), 3_628_800.0);
+        let err = rusk.eval_str("(fact 3) (undefined-thing) (fact 4)");
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "eval: Symbol undefined-thing undefined"
+        );
+        assert!(rusk.eval_str("(fact").is_err());
+        assert!(matches!(rusk.eval_str(""), Ok(Exp::Empty)));
+    }
+}
+
+#[test]
+fn fresh_environments() {
+    let mut a = Interpreter::new();
+    let mut b = Interpreter::new();
+    a.eval_str("(define x 1)").unwrap();
+    assert!(a.get_global("x").is_some());
+    assert!(b.get_global("x").is_none());
+    assert!(b.eval_str("x").is_err());
+}
+
+#[test]
+fn define_and_get_global() {
+    for &backend in BACKENDS.iter() {
+        let mut rusk = Interpreter::with_backend(backend);
+        rusk.define_global("limit", Exp::Number(10.0));
+        rusk.eval_str("(define (over? x) (> x limit))").unwrap();
+        assert!(matches!(rusk.eval_str("(over? 11)"), Ok(Exp::Bool(true))));
+        rusk.define_global("limit", Exp::Number(20.0));
+        assert!(matches!(rusk.eval_str("(over? 11)"), Ok(Exp::Bool(false))));
+        assert_eq!(number(rusk.get_global("limit").unwrap()), 20.0);
+    }
+}
+
+#[test]
+fn call() {
+    for &backend in BACKENDS.iter() {
+        let mut rusk = Interpreter::with_backend(backend);
+        rusk.eval_str("(define (add3 a b c) (+ a (+ b c)))")
+            .unwrap();
+        let args = [Exp::Number(1.0), Exp::Number(2.0), Exp::Number(3.0)];
+        assert_eq!(number(rusk.call("add3", &args).unwrap()), 6.0);
+        assert!(rusk.call("nothing", &[]).is_err());
+    }
+}
+
+#[test]
+fn register_fn() {
+    for &backend in BACKENDS.iter() {
+        let mut rusk = Interpreter::with_backend(backend);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let log_ref = Rc::clone(&log);
+        rusk.register_fn("log!", Arity::Exactly(1), move |args, _| {
+            log_ref.borrow_mut().push(args[0].to_string());
+            Ok(Exp::Empty)
+        });
+        rusk.register_fn("call-with-10", Arity::Exactly(1), |args, interp| {
+            interp.apply(&args[0], &[Exp::Number(10.0)])
+        });
+        rusk.eval_str("(log! 1) (log! (quote two))").unwrap();
+        assert_eq!(*log.borrow(), vec!["1", "two"]);
+
+        let res = rusk.eval_str("(call-with-10 (lambda (x) (* x x)))");
+        assert_eq!(number(res.unwrap()), 100.0);
+        let err = rusk.eval_str("(log! 1 2)").unwrap_err();
+        assert_eq!(err.to_string(), "log!: expected 1 arguments, got 2");
+    }
+}
+
+#[test]
+fn native_error() {
+    let mut rusk = Interpreter::new();
+    rusk.register_fn("fail", Arity::AtLeast(0), |_, _| {
+        Err(ScmErr::from("fail: on purpose"))
+    });
+    let err = rusk.eval_str("(+ 1 (fail))").unwrap_err();
+    assert_eq!(err.to_string(), "fail: on purpose");
+}
+
+#[test]
+fn eval_file() {
+    let path = std::env::temp_dir().join(format!("rusk-embed-{}.scm", std::process::id()));
+    fs::write(&path, "(define (square x) (* x x))\n(square 7)\n").unwrap();
+    let mut rusk = Interpreter::new();
+    let res = rusk.eval_file(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(number(res.unwrap()), 49.0);
+    assert!(rusk.get_global("square").is_some());
+    assert!(rusk.eval_file(&path).is_err());
+}
+
+#[test]
+fn eval_image() {
+    let dir = std::env::temp_dir();
+    let source = dir.join(format!("rusk-image-{}.scm", std::process::id()));
+    let image = source.with_extension("rbc");
+    fs::write(&source, "(define (cube x) (* x (* x x)))\n(cube 3)\n").unwrap();
+    rusk::compile_file(source.to_str().unwrap(), &image, false).unwrap();
+    let mut rusk = Interpreter::new();
+    let res = rusk.eval_file(&image);
+    fs::remove_file(&source).unwrap();
+    fs::remove_file(&image).unwrap();
+    assert_eq!(number(res.unwrap()), 27.0);
+}
+
+#[test]
+fn conversions() {
+    let mut rusk = Interpreter::new();
+    rusk.define_global("n", 42_i64);
+    rusk.define_global("x", 1.5);
+    rusk.define_global("b", true);
+    rusk.define_global("s", "hello \"world\"");
+    rusk.define_global("v", vec![vec![1_i64, 2], vec![], vec![3]]);
+    rusk.define_global("some", Some(String::from("here")));
+    rusk.define_global("none", None::<String>);
+    let mut ages = HashMap::new();
+    ages.insert(String::from("alice"), 30_i64);
+    ages.insert(String::from("bob"), 25);
+    rusk.define_global("ages", ages.clone());
+
+    assert_eq!(rusk.get::<i64>("n").unwrap(), 42);
+    assert_eq!(rusk.get::<f64>("n").unwrap(), 42.0);
+    assert_eq!(rusk.get::<f64>("x").unwrap(), 1.5);
+    assert!(rusk.get::<bool>("b").unwrap());
+    assert_eq!(rusk.get::<String>("s").unwrap(), "hello \"world\"");
+    assert_eq!(
+        rusk.get::<Vec<Vec<i64>>>("v").unwrap(),
+        vec![vec![1, 2], vec![], vec![3]]
+    );
+    assert_eq!(rusk.get::<Option<String>>("some").unwrap().unwrap(), "here");
+    assert_eq!(rusk.get::<Option<String>>("none").unwrap(), None);
+    assert_eq!(rusk.get::<HashMap<String, i64>>("ages").unwrap(), ages);
+
+    assert_eq!(
+        rusk.get::<i64>("x").unwrap_err().to_string(),
+        "x: expected an integer, got 1.5"
+    );
+    assert_eq!(
+        rusk.get::<Vec<i64>>("n").unwrap_err().to_string(),
+        "n: expected a List, got 42"
+    );
+    assert_eq!(
+        rusk.get::<bool>("missing").unwrap_err().to_string(),
+        "eval: Symbol missing undefined"
+    );
+
+    rusk.eval_str("(define pairs (cons (cons \"k\" 1) null))")
+        .unwrap();
+    let pairs: HashMap<String, f64> = rusk.get("pairs").unwrap();
+    assert_eq!(pairs["k"], 1.0);
+
+    rusk.eval_str(
+        "(define literal (quote (1 2 3))) (define alist (quote ((\"a\" . 1) (\"b\" . 2))))",
+    )
+    .unwrap();
+    assert_eq!(rusk.get::<Vec<i64>>("literal").unwrap(), vec![1, 2, 3]);
+    let alist: HashMap<String, i64> = rusk.get("alist").unwrap();
+    assert_eq!((alist["a"], alist["b"]), (1, 2));
+
+    // Flat Lists built from Rust are taken as their items, unless they have two items.
+    let flat = |items: &[f64]| Exp::List(items.iter().map(|&n| Exp::Number(n)).collect());
+    rusk.define_global("flat", flat(&[1.0, 2.0, 3.0]));
+    assert_eq!(rusk.get::<Vec<i64>>("flat").unwrap(), vec![1, 2, 3]);
+    let nested = vec![flat(&[1.0, 2.0, 3.0]), flat(&[]), flat(&[4.0])];
+    rusk.define_global("nested", Exp::List(nested));
+    assert_eq!(
+        rusk.get::<Vec<Vec<i64>>>("nested").unwrap(),
+        vec![vec![1, 2, 3], vec![], vec![4]]
+    );
+}
+
+#[test]
+fn wrapped_fns() {
+    for &backend in BACKENDS.iter() {
+        let mut rusk = Interpreter::with_backend(backend);
+        rusk.register_wrapped("answer", || 42_i64);
+        rusk.register_wrapped("shout", |s: String| s.to_uppercase());
+        rusk.register_wrapped("sum", |xs: Vec<f64>| xs.iter().sum::<f64>());
+        rusk.register_wrapped("checked-div", |a: i64, b: i64| {
+            a.checked_div(b)
+                .ok_or_else(|| ScmErr::from("checked-div: division by zero"))
+        });
+        rusk.register_wrapped("lookup", |table: HashMap<String, i64>, key: String| {
+            table.get(&key).cloned()
+        });
+
+        assert_eq!(number(rusk.eval_str("(answer)").unwrap()), 42.0);
+        assert_eq!(
+            rusk.eval_str(r#"(shout "hey")"#).unwrap().to_string(),
+            r#""HEY""#
+        );
+        assert_eq!(
+            number(
+                rusk.eval_str("(sum (cons 1 (cons 2 (cons 3 null))))")
+                    .unwrap()
+            ),
+            6.0
+        );
+        assert_eq!(number(rusk.eval_str("(checked-div 7 2)").unwrap()), 3.0);
+        assert_eq!(
+            rusk.eval_str("(checked-div 7 0)").unwrap_err().to_string(),
+            "checked-div: division by zero"
+        );
+        rusk.eval_str(r#"(define table (cons (cons "a" 1) null))"#)
+            .unwrap();
+        assert_eq!(number(rusk.eval_str(r#"(lookup table "a")"#).unwrap()), 1.0);
+        assert!(matches!(
+            rusk.eval_str(r#"(lookup table "b")"#),
+            Ok(Exp::Bool(false))
+        ));
+
+        assert_eq!(
+            rusk.eval_str("(shout 1)").unwrap_err().to_string(),
+            "shout: expected a String, got 1"
+        );
+        assert_eq!(
+            rusk.eval_str(r#"(shout "a" "b")"#).unwrap_err().to_string(),
+            "shout: expected 1 arguments, got 2"
+        );
+    }
+}
+
+#[test]
+fn exit() {
+    for &backend in BACKENDS.iter() {
+        let mut rusk = Interpreter::with_backend(backend);
+        rusk.eval_str("(define unwound #f)").unwrap();
+        let err = rusk
+            .eval_str(
+                "(dynamic-wind
+                    (lambda () #t)
+                    (lambda () (exit 3) (set! unwound 1))
+                    (lambda () (set! unwound #t)))",
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrKind::Exit(3));
+        assert!(rusk.get::<bool>("unwound").unwrap());
+        // The interpreter is still usable after an exit.
+        assert_eq!(number(rusk.eval_str("(+ 1 2)").unwrap()), 3.0);
+    }
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "serde")]
+
+use rusk::{Exp, Interpreter};
+
+fn to_json(rusk: &mut Interpreter, src: &str) -> Result<String, String> {
+    let exp = rusk.eval_str(src).unwrap();
+    serde_json::to_string(&exp).map_err(|e| e.to_string())
+}
+
+#[test]
+fn serialize() {
+    let mut rusk = Interpreter::new();
+    assert_eq!(to_json(&mut rusk, "42").unwrap(), "42");
+    assert_eq!(to_json(&mut rusk, "1.5").unwrap(), "1.5");
+    assert_eq!(to_json(&mut rusk, r#""hi""#).unwrap(), r#""hi""#);
+    assert_eq!(to_json(&mut rusk, "(quote foo)").unwrap(), r#""foo""#);
+    assert_eq!(to_json(&mut rusk, "#t").unwrap(), "true");
+    assert_eq!(
+        to_json(&mut rusk, "(cons 1 (cons 2 null))").unwrap(),
+        "[1,2]"
+    );
+    assert_eq!(to_json(&mut rusk, "(quote (1 2 3))").unwrap(), "[1,2,3]");
+    assert_eq!(
+        to_json(&mut rusk, "(quote (a (b (c)) ()))").unwrap(),
+        r#"["a",["b",["c"]],[]]"#
+    );
+    assert_eq!(to_json(&mut rusk, "(quote (a ()))").unwrap(), r#"["a",[]]"#);
+    assert_eq!(to_json(&mut rusk, "(quote ())").unwrap(), "[]");
+    assert_eq!(to_json(&mut rusk, "(vector 1 #t)").unwrap(), "[1,true]");
+    rusk.eval_str("(define t (make-hash-table))").unwrap();
+    rusk.eval_str(r#"(hash-table-set! t "a" #(1))"#).unwrap();
+    assert_eq!(to_json(&mut rusk, "t").unwrap(), r#"{"a":[1]}"#);
+}
+
+#[test]
+fn serialize_procedure() {
+    let mut rusk = Interpreter::new();
+    assert_eq!(
+        to_json(&mut rusk, "car").unwrap_err(),
+        "serialize: cannot serialize #<procedure>"
+    );
+    assert_eq!(
+        to_json(&mut rusk, "(vector (lambda (x) x))").unwrap_err(),
+        "serialize: cannot serialize #<procedure>"
+    );
+    assert_eq!(
+        to_json(&mut rusk, "(cons 1 2)").unwrap_err(),
+        "serialize: cannot serialize the improper List (1 . 2)"
+    );
+}
+
+#[test]
+fn deserialize() {
+    let mut rusk = Interpreter::new();
+    let exp: Exp = serde_json::from_str(r#"{"xs": [1, 2.5, "a"], "ok": true, "n": null}"#).unwrap();
+    rusk.define_global("data", exp);
+    let get = |rusk: &mut Interpreter, src: &str| rusk.eval_str(src).unwrap().to_string();
+    assert_eq!(
+        get(&mut rusk, r#"(hash-table-ref data "xs")"#),
+        r#"#(1 2.5 "a")"#
+    );
+    assert_eq!(get(&mut rusk, r#"(hash-table-ref data "ok")"#), "#t");
+    assert_eq!(get(&mut rusk, "(hash-table-count data)"), "3");
+}
+
+#[test]
+fn roundtrip() {
+    let json = r#"{"a":[1,{"b":false}],"c":"d"}"#;
+    let exp: Exp = serde_json::from_str(json).unwrap();
+    assert_eq!(serde_json::to_string(&exp).unwrap(), json);
+
+    let mut rusk = Interpreter::new();
+    for src in &["(quote (1 (2 (3)) ()))", "(quote (()))", "(quote ())"] {
+        let json = to_json(&mut rusk, src).unwrap();
+        let exp: Exp = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&exp).unwrap(), json);
+    }
+}